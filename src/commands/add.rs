@@ -1,14 +1,13 @@
-use std::{fs, path::PathBuf};
+use std::{collections::BTreeMap, fs, path::PathBuf};
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Result, bail};
 
-use crate::{
-    commands::rm::rm,
-    git::{
-        index::{GitIndexEntry, read_index, write_index},
-        objects::{GitObjectType, object_hash},
-        repo::{GitRepository, repo_find},
-    },
+use crate::git::{
+    index::{GitIndexEntry, read_index, write_index},
+    objects::{GitObjectType, object_hash},
+    repo::{GitRepository, repo_find},
+    status::{filemode_trusted, index_mode, worktree_content},
+    worktree::{index_entry_for, worktree_relpath},
 };
 
 pub fn run(paths: &[PathBuf]) -> Result<()> {
@@ -18,68 +17,46 @@ pub fn run(paths: &[PathBuf]) -> Result<()> {
 }
 
 pub fn add(repo: &GitRepository, paths: &[PathBuf]) -> Result<()> {
-    rm(repo, paths, false, true)?;
-
-    let worktree = repo.worktree.canonicalize()?;
+    // Symlinks are added as links, so paths are not resolved through them
     let mut clean_paths = Vec::new();
-
     for path in paths {
-        let abs = path.canonicalize()?;
-        if !abs.starts_with(&worktree) || !abs.is_file() {
+        let rel = worktree_relpath(repo, path)?;
+        let meta = fs::symlink_metadata(repo.worktree.join(&rel)).ok();
+        if rel.is_empty() || !meta.is_some_and(|m| m.is_file() || m.is_symlink()) {
             bail!("Not a file, or outside the worktree: {:?}", path);
         }
-
-        let rel = abs
-            .strip_prefix(&worktree)
-            .with_context(|| format!("Path {abs:?} not inside repo"))?
-            .to_path_buf();
-
-        clean_paths.push((abs, rel));
+        clean_paths.push(rel);
     }
 
     let mut index = read_index(repo)?;
+    let filemode = filemode_trusted(repo);
+
+    let mut entries: BTreeMap<(String, u16), GitIndexEntry> = std::mem::take(&mut index.entries)
+        .into_iter()
+        .map(|e| ((e.path.clone(), e.stage()), e))
+        .collect();
+
+    for relpath in clean_paths {
+        let abspath = repo.worktree.join(&relpath);
+        let meta = fs::symlink_metadata(&abspath)?;
+
+        let sha = object_hash(repo, worktree_content(&abspath)?, &GitObjectType::blob)?;
+        let current = entries.get(&(relpath.clone(), 0)).map(|e| e.mode);
+        let mode = index_mode(&meta, current, filemode);
+
+        // Adding a conflicted path resolves it
+        for stage in 1..=3 {
+            if let Some(e) = entries.remove(&(relpath.clone(), stage)) {
+                index.resolve_undo_record(&e);
+            }
+        }
 
-    for (abspath, relpath) in clean_paths {
-        let data = fs::read(&abspath)?;
-
-        let sha = object_hash(&repo, data, &GitObjectType::blob)?;
-
-        let meta = fs::metadata(&abspath)?;
-
-        let ctime_s = meta
-            .created()
-            .ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs() as i32)
-            .unwrap_or(0);
-
-        let mtime_s = meta
-            .modified()
-            .ok()
-            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-            .map(|d| d.as_secs() as i32)
-            .unwrap_or(0);
-
-        let entry = GitIndexEntry {
-            // Git packs ctime/mtime as seconds, ignoring nanos for now
-            ctime: ctime_s as u32,
-            mtime: mtime_s as u32,
-            dev: 0,
-            ino: 0,
-            // combine file type + permissions into one mode
-            mode: (0b1000 << 12) | 0o644, // regular file + rw-r--r--
-            uid: 0,
-            gid: 0,
-            size: meta.len() as u32,
-            sha,
-            flags: 0, // you can OR bits for assume-valid/stage if needed
-            path: relpath.to_string_lossy().to_string(),
-        };
-
-        index.entries.push(entry);
+        let entry = index_entry_for(repo, &relpath, mode, &sha, 0)?;
+        entries.insert((relpath, 0), entry);
     }
 
     // Write index back
+    index.entries = entries.into_values().collect();
     write_index(repo, &index)?;
     Ok(())
 }
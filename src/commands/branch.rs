@@ -0,0 +1,128 @@
+use anyhow::{Result, bail};
+
+use crate::{
+    commands::{commit::head_commit, status::branch_get_active},
+    git::{
+        merge_base::is_ancestor,
+        objects::{GitObjectType, object_find},
+        reflog::reflog_rename,
+        refs::{
+            collect_refs, head_set_symbolic, ref_create, ref_delete, ref_name_valid, resolve_ref,
+        },
+        repo::repo_find,
+    },
+};
+
+pub fn list_branches() -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let active = branch_get_active(&repo)?;
+    if active.is_none() {
+        let head = head_commit(&repo)?.unwrap_or_default();
+        println!("* (HEAD detached at {})", &head[..7.min(head.len())]);
+    }
+
+    for (_, refname) in collect_refs(&repo, "refs/heads")? {
+        let name = &refname["refs/heads/".len()..];
+        let marker = if active.as_deref() == Some(name) {
+            '*'
+        } else {
+            ' '
+        };
+        println!("{marker} {name}");
+    }
+
+    Ok(())
+}
+
+pub fn create_branch(name: &str, start: &str) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    if !ref_name_valid(name) {
+        bail!("'{name}' is not a valid branch name");
+    }
+    let refname = format!("refs/heads/{name}");
+    if resolve_ref(&repo, &refname).is_ok() {
+        bail!("A branch named '{name}' already exists");
+    }
+
+    let sha = object_find(&repo, start, Some(GitObjectType::commit))?;
+    ref_create(
+        &repo,
+        &refname,
+        &sha,
+        &format!("branch: Created from {start}"),
+    )
+}
+
+pub fn delete_branch(name: &str, force: bool) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let refname = format!("refs/heads/{name}");
+    let sha =
+        resolve_ref(&repo, &refname).map_err(|_| anyhow::anyhow!("branch '{name}' not found"))?;
+
+    if branch_get_active(&repo)?.as_deref() == Some(name) {
+        bail!(
+            "Cannot delete branch '{name}' checked out at '{}'",
+            repo.worktree.display()
+        );
+    }
+
+    if !force {
+        let merged = match head_commit(&repo)? {
+            Some(head) => is_ancestor(&repo, &sha, &head)?,
+            None => false,
+        };
+        if !merged {
+            bail!(
+                "The branch '{name}' is not fully merged.\nIf you are sure you want to delete it, run 'branch -D {name}'."
+            );
+        }
+    }
+
+    ref_delete(&repo, &refname)?;
+    println!("Deleted branch {name} (was {}).", &sha[..7]);
+
+    Ok(())
+}
+
+pub fn rename_branch(old: Option<&str>, new: &str) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let active = branch_get_active(&repo)?;
+    let Some(old) = old.or(active.as_deref()) else {
+        bail!("No branch to rename: HEAD is detached");
+    };
+    if !ref_name_valid(new) {
+        bail!("'{new}' is not a valid branch name");
+    }
+
+    let old_ref = format!("refs/heads/{old}");
+    let new_ref = format!("refs/heads/{new}");
+    if resolve_ref(&repo, &new_ref).is_ok() {
+        bail!("A branch named '{new}' already exists");
+    }
+
+    let is_active = active.as_deref() == Some(old);
+    let sha = match resolve_ref(&repo, &old_ref) {
+        Ok(sha) => Some(sha),
+        // The current branch may not have any commits yet
+        Err(_) if is_active => None,
+        Err(_) => bail!("branch '{old}' not found"),
+    };
+
+    let message = format!("Branch: renamed {old_ref} to {new_ref}");
+    if let Some(sha) = sha {
+        // The branch keeps its history under the new name
+        reflog_rename(&repo, &old_ref, &new_ref)?;
+        ref_create(&repo, &new_ref, &sha, &message)?;
+        ref_delete(&repo, &old_ref)?;
+    }
+
+    if is_active {
+        head_set_symbolic(&repo, &new_ref, &message)?;
+    }
+
+    Ok(())
+}
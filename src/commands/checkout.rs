@@ -1,94 +1,135 @@
-use std::{fs, io::Write, path::Path};
-
-use anyhow::{Context, Result, bail};
-
-use crate::git::{
-    objects::{GitBlob, GitCommit, GitObjectType, object_find, object_read},
-    repo::{GitRepository, repo_find},
-    tree::GitTree,
+use anyhow::{Result, bail};
+
+use crate::{
+    commands::{commit::head_commit, status::branch_get_active},
+    git::{
+        hooks::hook_run,
+        objects::{GitObjectType, commit_read, object_find, object_resolve},
+        reflog::NULL_SHA,
+        refs::{head_set_symbolic, ref_create, ref_name_valid, resolve_ref},
+        repo::{GitRepository, repo_find},
+        status::head_tree,
+        tree::tree_flatten,
+        worktree::worktree_switch,
+    },
 };
 
-fn checkout_tree(repo: &GitRepository, sha: &str, path: &Path) -> Result<()> {
-    let (otype, obj) = object_read(repo, sha)?;
-    if otype != GitObjectType::tree {
-        bail!("Object {sha} is not a tree");
-    }
+/// `checkout <target>`: a branch name switches to that branch, anything else
+/// that names a commit detaches HEAD at it. With `new_branch` the branch is
+/// first created at `target`, or at HEAD when no target is given.
+pub fn run(target: Option<&str>, new_branch: Option<&str>) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
 
-    let tree = obj
-        .as_any()
-        .downcast_ref::<GitTree>()
-        .context("Failed to downcast to GitTree")?;
-
-    fs::create_dir_all(path)?;
-
-    for entry in &tree.entries {
-        let entry_sha = hex::encode(entry.sha);
-        let entry_path = path.join(&entry.path);
-
-        match entry.mode.as_str() {
-            m if m.starts_with("04") => {
-                checkout_tree(&repo, &entry_sha, &entry_path)?;
-            }
-            m if m.starts_with("10") || m.starts_with("12") => {
-                let (_, obj) = object_read(repo, &entry_sha)?;
-
-                if otype != GitObjectType::blob {
-                    bail!("Tree entry {} is not a blob", entry.path);
-                }
-                let blob = obj
-                    .as_any()
-                    .downcast_ref::<GitBlob>()
-                    .context("Failed to downcast to GitBlob")?;
-
-                let mut file = fs::File::create(&entry_path)?;
-                file.write_all(&blob.data)?;
-            }
-            m if m.starts_with("16") => {
-                // Submodule = commit object (store SHA as a file placeholder for now)
-                let mut file = fs::File::create(&entry_path)?;
-                file.write_all(entry_sha.as_bytes())?;
-            }
-            other => bail!("Weird tree entry mode {}", other),
+    let target = match (new_branch, target) {
+        (Some(name), start) => {
+            return switch_create(&repo, name, start.unwrap_or("HEAD"), "checkout");
         }
+        (None, Some(target)) => target,
+        (None, None) => bail!("missing branch or commit argument"),
+    };
+
+    if resolve_ref(&repo, &format!("refs/heads/{target}")).is_ok() {
+        switch_branch(&repo, target, "checkout")
+    } else {
+        switch_detach(&repo, target, "checkout")
     }
-
-    Ok(())
 }
 
-pub fn run(commit: &str) -> Result<()> {
-    let repo = repo_find(".", true)?.unwrap();
-
-    let sha = object_find(&repo, commit, Some(GitObjectType::blob))?;
-    let (obj_type, obj) = object_read(&repo, &sha)?;
+/// Check out `branch` and point HEAD at it.
+pub fn switch_branch(repo: &GitRepository, branch: &str, operation: &str) -> Result<()> {
+    let refname = format!("refs/heads/{branch}");
+    let sha = resolve_ref(repo, &refname)?;
 
-    if obj_type != GitObjectType::commit {
-        bail!("Object {sha} is not a commit");
+    if branch_get_active(repo)?.as_deref() == Some(branch) {
+        println!("Already on '{branch}'");
+        return Ok(());
     }
 
-    let commit = obj
-        .as_any()
-        .downcast_ref::<GitCommit>()
-        .context("Failed to downcast to GitCommit")?;
+    let old = head_commit(repo)?;
+    let message = format!("checkout: moving from {} to {branch}", head_describe(repo)?);
+    switch_to_commit(repo, &sha, operation)?;
+    head_set_symbolic(repo, &refname, &message)?;
+    println!("Switched to branch '{branch}'");
 
-    let tree_sha = commit.kvlm.get(b"tree").context("Missing 'tree' field")?;
-    let tree_sha = std::str::from_utf8(tree_sha)?.to_string();
+    post_checkout(repo, old.as_deref(), &sha)
+}
 
-    for entry in fs::read_dir(&repo.worktree)? {
-        let entry = entry?;
+/// Check out the commit `rev` names and detach HEAD at it.
+pub fn switch_detach(repo: &GitRepository, rev: &str, operation: &str) -> Result<()> {
+    let sha = object_find(repo, rev, Some(GitObjectType::commit))?;
+    let was_detached = branch_get_active(repo)?.is_none();
+    let old = head_commit(repo)?;
 
-        if entry.file_name() == ".git" {
-            continue;
-        }
+    let message = format!("checkout: moving from {} to {rev}", head_describe(repo)?);
+    switch_to_commit(repo, &sha, operation)?;
+    ref_create(repo, "HEAD", &sha, &message)?;
 
-        let path = entry.path();
-        if path.is_dir() {
-            fs::remove_dir_all(&path)?;
-        } else {
-            fs::remove_file(&path)?;
-        }
+    if !was_detached {
+        println!("Note: switching to '{rev}'.\n");
+        println!("You are in 'detached HEAD' state. Commits you make here belong to no branch;");
+        println!("switch back to a branch to keep them reachable.\n");
+    }
+    let subject = commit_read(repo, &sha)?.kvlm.message;
+    let subject = String::from_utf8_lossy(&subject);
+    println!(
+        "HEAD is now at {} {}",
+        &sha[..7],
+        subject.lines().next().unwrap_or_default()
+    );
+
+    post_checkout(repo, old.as_deref(), &sha)
+}
+
+/// Create `branch` at `start` and switch to it.
+pub fn switch_create(
+    repo: &GitRepository,
+    branch: &str,
+    start: &str,
+    operation: &str,
+) -> Result<()> {
+    if !ref_name_valid(branch) {
+        bail!("'{branch}' is not a valid branch name");
+    }
+    let refname = format!("refs/heads/{branch}");
+    if resolve_ref(repo, &refname).is_ok() {
+        bail!("A branch named '{branch}' already exists");
     }
 
-    checkout_tree(&repo, &tree_sha, Path::new(&repo.worktree))?;
+    let sha = object_find(repo, start, Some(GitObjectType::commit))?;
+    let old = head_commit(repo)?;
+    let message = format!("checkout: moving from {} to {branch}", head_describe(repo)?);
+    switch_to_commit(repo, &sha, operation)?;
+    ref_create(
+        repo,
+        &refname,
+        &sha,
+        &format!("branch: Created from {start}"),
+    )?;
+    head_set_symbolic(repo, &refname, &message)?;
+    println!("Switched to a new branch '{branch}'");
+
+    post_checkout(repo, old.as_deref(), &sha)
+}
 
+/// Where HEAD is, for reflog messages: the branch name or the commit SHA.
+fn head_describe(repo: &GitRepository) -> Result<String> {
+    match branch_get_active(repo)? {
+        Some(branch) => Ok(branch),
+        None => object_resolve(repo, "HEAD"),
+    }
+}
+
+/// Run the post-checkout hook for a branch switch from `old` to `new`. The
+/// checkout has already happened, so its result is ignored.
+fn post_checkout(repo: &GitRepository, old: Option<&str>, new: &str) -> Result<()> {
+    hook_run(repo, "post-checkout", &[old.unwrap_or(NULL_SHA), new, "1"], None)?;
     Ok(())
 }
+
+/// Move the worktree and index from HEAD to the tree of `sha`.
+fn switch_to_commit(repo: &GitRepository, sha: &str, operation: &str) -> Result<()> {
+    let from = head_tree(repo)?;
+    let to = tree_flatten(repo, &commit_read(repo, sha)?.tree()?)?;
+
+    worktree_switch(repo, &from, &to, operation)
+}
@@ -1,38 +1,344 @@
-use std::fs;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-use anyhow::{Context, Result};
-use chrono::Utc;
+use anyhow::{Context, Result, bail};
 
 use crate::git::{
-    index::{GitIndex, read_index},
+    hooks::hook_run,
+    ident::{IdentRole, Signature, date_parse, ident_date, ident_default, ident_parse},
+    index::{GitIndexEntry, read_index, write_index},
     kvlm::Kvlm,
-    objects::{GitCommit, GitObjectType, object_write},
-    refs::{ref_create, resolve_ref},
-    repo::{GitRepository, gitconfig_read, gitconfig_user_get, repo_find},
-    tree::{GitTree, GitTreeLeaf},
+    lockfile::lockfile_write,
+    objects::{GitCommit, GitObjectType, commit_read, object_hash, object_write},
+    refs::{head_symbolic, ref_create, resolve_ref},
+    repo::{GitRepository, repo_config_get, repo_find},
+    status::{ChangeKind, filemode_trusted, index_mode, status_compute, worktree_content},
+    tree::tree_from_index,
+    worktree::index_entry_for,
 };
 
-pub fn run(message: &str) -> Result<()> {
+pub struct CommitOptions {
+    /// `-m` paragraphs, joined by blank lines.
+    pub message: Vec<String>,
+    /// Read the message from this file ("-" for stdin).
+    pub file: Option<PathBuf>,
+    /// Replace HEAD instead of adding a child to it.
+    pub amend: bool,
+    /// Reuse the amended commit's or merge's message without starting an editor.
+    pub no_edit: bool,
+    /// Make the amended commit's author the current user.
+    pub reset_author: bool,
+    /// Override the author, as "Name <email>".
+    pub author: Option<String>,
+    /// Override the author date.
+    pub date: Option<String>,
+    pub allow_empty: bool,
+    /// Stage modified and deleted tracked files first.
+    pub all: bool,
+    /// Skip the pre-commit and commit-msg hooks.
+    pub no_verify: bool,
+}
+
+pub fn run(opts: CommitOptions) -> Result<()> {
     // 1. Find repo
     let repo = repo_find(".", true)?.context("Not a git repository")?;
 
-    // 2. Read index
-    let index = read_index(&repo)?;
+    if opts.all {
+        stage_tracked(&repo)?;
+    }
+    if !opts.no_verify && !hook_run(&repo, "pre-commit", &[], None)? {
+        bail!("pre-commit hook failed");
+    }
+
+    // 2. Read index (the pre-commit hook may have changed it)
+    let mut index = read_index(&repo)?;
+    if index.entries.iter().any(|e| e.stage() != 0) {
+        bail!("Committing is not possible because you have unmerged files.");
+    }
+
+    // 3. Write tree, keeping the updated cache tree for the next commit
+    let tree_sha = tree_from_index(&repo, &mut index)?;
+    write_index(&repo, &index)?;
+
+    // 4. Find parent commit (if HEAD exists), plus the other side of a merge.
+    // Amending reuses the parents of the commit being replaced.
+    let head = head_commit(&repo)?;
+    let merge_head = repo.gitdir.join("MERGE_HEAD");
+    let amended = match (&head, opts.amend) {
+        (_, false) => None,
+        (None, true) => bail!("You have nothing to amend."),
+        (Some(_), true) if merge_head.exists() => {
+            bail!("You are in the middle of a merge -- cannot amend.")
+        }
+        (Some(sha), true) => Some((sha.clone(), commit_read(&repo, sha)?)),
+    };
+    let mut parents: Vec<String> = match &amended {
+        Some((_, commit)) => commit.parents(),
+        None => head.into_iter().collect(),
+    };
+    if merge_head.exists() {
+        parents.push(fs::read_to_string(&merge_head)?.trim().to_string());
+    }
+
+    // Merges always record something; anything else must change the tree
+    if !opts.allow_empty && parents.len() <= 1 {
+        let parent_tree = match parents.first() {
+            Some(parent) => Some(commit_read(&repo, parent)?.tree()?),
+            None => None,
+        };
+        let empty = match parent_tree {
+            Some(tree) => tree == tree_sha,
+            None => index.entries.is_empty(),
+        };
+        if empty && amended.is_some() {
+            bail!(
+                "You asked to amend the most recent commit, but doing so would make\nit empty. You can repeat your command with --allow-empty, or you can\nremove the commit entirely with \"git reset HEAD^\"."
+            );
+        }
+        if empty {
+            let status = status_compute(&repo, &index)?;
+            if status.entries.iter().any(|e| e.unstaged.is_some()) {
+                bail!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
+            }
+            bail!("nothing to commit, working tree clean");
+        }
+    }
+
+    let message = commit_message(&repo, &opts, amended.as_ref())?;
+
+    // 5-6. Build commit object
+    let author = commit_author(&repo, &opts, amended.as_ref().map(|(_, commit)| commit))?;
+    let commit_sha = commit_create(&repo, &tree_sha, &parents, &message, Some(&author))?;
+
+    // 7. Update ref
+    let kind = match (amended.is_some(), parents.len()) {
+        (true, _) => " (amend)",
+        (false, 0) => " (initial)",
+        (false, 1) => "",
+        _ => " (merge)",
+    };
+    let subject = message.lines().next().unwrap_or_default();
+    head_update(&repo, &commit_sha, &format!("commit{kind}: {subject}"))?;
+
+    if merge_head.exists() {
+        fs::remove_file(&merge_head)?;
+        let merge_msg = repo.gitdir.join("MERGE_MSG");
+        if merge_msg.exists() {
+            fs::remove_file(merge_msg)?;
+        }
+    }
+
+    println!("[{}] {}", &commit_sha[..7], subject);
+
+    hook_run(&repo, "post-commit", &[], None)?;
+    if let Some((old, _)) = &amended {
+        let rewritten = format!("{old} {commit_sha}\n");
+        hook_run(&repo, "post-rewrite", &["amend"], Some(rewritten.as_bytes()))?;
+    }
+
+    Ok(())
+}
+
+/// The new commit's author: `--author` or the amended commit's author if
+/// given, otherwise the current user, with `--date` replacing the date.
+fn commit_author(
+    repo: &GitRepository,
+    opts: &CommitOptions,
+    amended: Option<&GitCommit>,
+) -> Result<Signature> {
+    let mut author = match &opts.author {
+        Some(text) => {
+            let (name, email) = ident_parse(text)
+                .with_context(|| format!("--author '{text}' is not 'Name <email>'"))?;
+            let (timestamp, tz) = ident_date(IdentRole::Author)?;
+            Signature {
+                name,
+                email,
+                timestamp,
+                tz,
+            }
+        }
+        None => match amended.filter(|_| !opts.reset_author).and_then(GitCommit::author) {
+            Some(author) => author,
+            None => ident_default(repo, IdentRole::Author)?,
+        },
+    };
+
+    if let Some(date) = &opts.date {
+        (author.timestamp, author.tz) = date_parse(date)?;
+    }
+    Ok(author)
+}
+
+/// Update the index entries of tracked files that were modified or deleted
+/// in the worktree, like `git add -u`.
+fn stage_tracked(repo: &GitRepository) -> Result<()> {
+    let mut index = read_index(repo)?;
+    let status = status_compute(repo, &index)?;
+    let filemode = filemode_trusted(repo);
+
+    let mut entries: BTreeMap<(String, u16), GitIndexEntry> = std::mem::take(&mut index.entries)
+        .into_iter()
+        .map(|e| ((e.path.clone(), e.stage()), e))
+        .collect();
+
+    for change in &status.entries {
+        let key = (change.path.clone(), 0);
+        match change.unstaged {
+            Some(ChangeKind::Deleted) => {
+                entries.remove(&key);
+            }
+            Some(_) => {
+                let abs = repo.worktree.join(&change.path);
+                let meta = fs::symlink_metadata(&abs)?;
+                let sha = object_hash(repo, worktree_content(&abs)?, &GitObjectType::blob)?;
+                let current = entries.get(&key).map(|e| e.mode);
+                let mode = index_mode(&meta, current, filemode);
+                let entry = index_entry_for(repo, &change.path, mode, &sha, 0)?;
+                entries.insert(key, entry);
+            }
+            None => {}
+        }
+    }
+
+    index.entries = entries.into_values().collect();
+    write_index(repo, &index)
+}
 
-    // 3. Write tree
-    let tree_sha = write_tree(&repo, &index)?;
+/// The message for the new commit: from `-m` or `-F`, reused from the amended
+/// commit with `--no-edit`, or else written by the user in an editor. It goes
+/// through `.git/COMMIT_EDITMSG` so the `prepare-commit-msg` and (unless
+/// `--no-verify`) `commit-msg` hooks can edit it.
+fn commit_message(
+    repo: &GitRepository,
+    opts: &CommitOptions,
+    amended: Option<&(String, GitCommit)>,
+) -> Result<String> {
+    let amended_message =
+        amended.map(|(_, commit)| String::from_utf8_lossy(&commit.kvlm.message).into_owned());
+    let merge_msg = fs::read_to_string(repo.gitdir.join("MERGE_MSG")).ok();
 
-    // 4. Find parent commit (if HEAD exists)
+    // The message so far, and what prepare-commit-msg is told about it
+    let (text, source) = if !opts.message.is_empty() {
+        (message_cleanup(&opts.message.join("\n\n"), false), vec!["message"])
+    } else if let Some(file) = &opts.file {
+        let mut text = String::new();
+        if file == Path::new("-") {
+            std::io::stdin().read_to_string(&mut text)?;
+        } else {
+            text = fs::read_to_string(file)
+                .with_context(|| format!("could not read log file '{}'", file.display()))?;
+        }
+        (message_cleanup(&text, false), vec!["message"])
+    } else if let (Some((sha, _)), Some(message)) = (amended, amended_message) {
+        (message, vec!["commit", sha.as_str()])
+    } else if let Some(message) = merge_msg {
+        (message, vec!["merge"])
+    } else {
+        (String::new(), vec![])
+    };
+    let edit = opts.message.is_empty() && opts.file.is_none() && !opts.no_edit;
+
+    let path = repo.gitdir.join("COMMIT_EDITMSG");
+    let mut text = text;
+    if edit {
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(
+            "\n# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored, and an empty message aborts the commit.\n#\n",
+        );
+    }
+    lockfile_write(&path, text.as_bytes())?;
+
+    let path_arg = path.to_string_lossy();
+    let mut args = vec![path_arg.as_ref()];
+    args.extend(source);
+    if !hook_run(repo, "prepare-commit-msg", &args, None)? {
+        bail!("prepare-commit-msg hook failed");
+    }
+    if edit {
+        editor_launch(repo, &path)?;
+    }
+    if !opts.no_verify && !hook_run(repo, "commit-msg", &[&path_arg], None)? {
+        bail!("commit-msg hook failed");
+    }
+
+    let message = message_cleanup(&fs::read_to_string(&path)?, edit);
+    if message.is_empty() {
+        bail!("Aborting commit due to empty commit message.");
+    }
+    Ok(message)
+}
+
+/// The editor command: `$GIT_EDITOR`, `core.editor`, `$EDITOR`, then vi.
+fn editor_get(repo: &GitRepository) -> String {
+    env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| repo_config_get(repo, "core", "editor"))
+        .or_else(|| env::var("EDITOR").ok())
+        .filter(|e| !e.is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Let the user edit the file at `path`.
+fn editor_launch(repo: &GitRepository, path: &Path) -> Result<()> {
+    // Like git, the editor is a shell command that gets the file appended
+    let editor = editor_get(repo);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("unable to start editor '{editor}'"))?;
+    if !status.success() {
+        bail!("There was a problem with the editor '{editor}'.");
+    }
+
+    Ok(())
+}
+
+/// Normalize a message like git's default cleanup: trailing whitespace and
+/// surrounding blank lines go, runs of blank lines become one, and the
+/// result ends in a newline. `strip_comments` also drops '#' lines.
+pub fn message_cleanup(text: &str, strip_comments: bool) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+
+    for line in text.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    out
+}
+
+/// The commit HEAD points to, or `None` on an unborn branch.
+pub fn head_commit(repo: &GitRepository) -> Result<Option<String>> {
     let head_ref = repo.gitdir.join("HEAD");
     let parent = if head_ref.exists() {
         let target = fs::read_to_string(&head_ref)?.trim().to_string();
-        if target.starts_with("ref:") {
-            let refname = target.strip_prefix("ref: ").unwrap();
+        if let Some(refname) = target.strip_prefix("ref: ") {
             // Try to resolve the ref, but it's OK if it doesn't exist for first commit
-            match resolve_ref(&repo, refname) {
-                Ok(sha) => Some(sha),
-                Err(_) => None, // First commit, no parent
-            }
+            resolve_ref(repo, refname).ok()
         } else if target.len() == 40 {
             Some(target)
         } else {
@@ -42,67 +348,55 @@ pub fn run(message: &str) -> Result<()> {
         None
     };
 
-    // 5. Author/committer
-    let config = gitconfig_read()?;
-    let author = gitconfig_user_get(&config).context("Missing user name/email in git config")?;
-    let timestamp = Utc::now().timestamp();
-    let tz = "+0000"; // simplify: UTC only
+    Ok(parent)
+}
+
+/// Write a commit object for `tree_sha` with the given parents. The author
+/// defaults to the current user, who is always the committer.
+pub fn commit_create(
+    repo: &GitRepository,
+    tree_sha: &str,
+    parents: &[String],
+    message: &str,
+    author: Option<&Signature>,
+) -> Result<String> {
+    // Author/committer
+    let committer = ident_default(repo, IdentRole::Committer)?.to_string();
+    let author = match author {
+        Some(author) => author.to_string(),
+        None => ident_default(repo, IdentRole::Author)?.to_string(),
+    };
 
-    // 6. Build commit object
     let mut kvlm = Kvlm::new();
     kvlm.headers
         .push((b"tree".to_vec(), tree_sha.as_bytes().to_vec()));
-    if let Some(parent_sha) = &parent {
+    for parent_sha in parents {
         kvlm.headers
             .push((b"parent".to_vec(), parent_sha.as_bytes().to_vec()));
     }
-    kvlm.headers.push((
-        b"author".to_vec(),
-        format!("{author} {timestamp} {tz}").into_bytes(),
-    ));
-    kvlm.headers.push((
-        b"committer".to_vec(),
-        format!("{author} {timestamp} {tz}").into_bytes(),
-    ));
+    kvlm.headers.push((b"author".to_vec(), author.into_bytes()));
+    kvlm.headers
+        .push((b"committer".to_vec(), committer.into_bytes()));
     kvlm.message = message.as_bytes().to_vec();
 
     let commit = GitCommit { kvlm };
-    let commit_sha = object_write(&repo, &commit, &GitObjectType::commit, true)?;
+    object_write(repo, &commit, &GitObjectType::commit, true)
+}
 
-    // 7. Update ref
+/// Point HEAD (or the branch it refers to) at `commit_sha`, logging the move
+/// with `reflog_message`.
+pub fn head_update(repo: &GitRepository, commit_sha: &str, reflog_message: &str) -> Result<()> {
+    let head_ref = repo.gitdir.join("HEAD");
     if head_ref.exists() {
-        let target = fs::read_to_string(&head_ref)?.trim().to_string();
-        if target.starts_with("ref:") {
-            let refname = target.strip_prefix("ref: ").unwrap();
-            ref_create(&repo, refname, &commit_sha)?;
-        } else {
-            fs::write(&head_ref, format!("{commit_sha}\n"))?;
+        match head_symbolic(repo)? {
+            Some(refname) => ref_create(repo, &refname, commit_sha, reflog_message)?,
+            None => ref_create(repo, "HEAD", commit_sha, reflog_message)?,
         }
     } else {
         // Create default HEAD pointing to refs/heads/master
-        fs::write(&head_ref, "ref: refs/heads/master\n")?;
-        ref_create(&repo, "heads/master", &commit_sha)?;
+        lockfile_write(&head_ref, b"ref: refs/heads/master\n")?;
+        ref_create(repo, "refs/heads/master", commit_sha, reflog_message)?;
     }
 
-    println!("[{}] {}", &commit_sha[..7], message.trim());
-
     Ok(())
 }
-
-fn write_tree(repo: &GitRepository, index: &GitIndex) -> Result<String> {
-    let mut tree = GitTree {
-        entries: Vec::new(),
-    };
-
-    for entry in &index.entries {
-        let mut sha = [0u8; 20];
-        hex::decode_to_slice(&entry.sha, &mut sha)?;
-        tree.entries.push(GitTreeLeaf {
-            mode: "100644".to_string(),
-            path: entry.path.clone(),
-            sha,
-        });
-    }
-
-    object_write(repo, &tree, &GitObjectType::tree, true)
-}
@@ -0,0 +1,214 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::PathBuf,
+};
+
+use anyhow::{Result, bail};
+
+use crate::git::{
+    diff::{is_binary, unified_diff},
+    index::{GitIndex, read_index},
+    objects::{GitObjectType, object_find, object_read_raw},
+    repo::{GitRepository, repo_find},
+    status::{filemode_trusted, index_mode, index_mtime, worktree_hash, worktree_matches},
+    tree::tree_flatten,
+};
+
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// One side of a file comparison.
+struct FileState {
+    mode: u32,
+    sha: String,
+    /// Set when the content must be read from the worktree rather than the object store.
+    disk_path: Option<PathBuf>,
+}
+
+type Snapshot = BTreeMap<String, FileState>;
+
+pub fn run(cached: bool, context: usize, commits: &[String]) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+    let index = read_index(&repo)?;
+
+    let (old, new) = match (cached, commits) {
+        (false, []) => (snapshot_index(&index), snapshot_worktree(&repo, &index)?),
+        (false, [commit]) => (
+            snapshot_tree(&repo, commit)?,
+            snapshot_worktree(&repo, &index)?,
+        ),
+        (true, []) => (snapshot_head(&repo)?, snapshot_index(&index)),
+        (true, [commit]) => (snapshot_tree(&repo, commit)?, snapshot_index(&index)),
+        (false, [a, b]) => (snapshot_tree(&repo, a)?, snapshot_tree(&repo, b)?),
+        _ => bail!("usage: diff [--cached] [<commit> [<commit>]]"),
+    };
+
+    let out = diff_snapshots(&repo, &old, &new, context)?;
+    std::io::stdout().write_all(&out)?;
+
+    Ok(())
+}
+
+fn snapshot_tree(repo: &GitRepository, rev: &str) -> Result<Snapshot> {
+    let tree = object_find(repo, rev, Some(GitObjectType::tree))?;
+    Ok(tree_flatten(repo, &tree)?
+        .into_iter()
+        .map(|(path, leaf)| {
+            let state = FileState {
+                mode: leaf.mode_bits(),
+                sha: hex::encode(leaf.sha),
+                disk_path: None,
+            };
+            (path, state)
+        })
+        .collect())
+}
+
+fn snapshot_head(repo: &GitRepository) -> Result<Snapshot> {
+    // An unborn branch compares against the empty tree
+    match object_find(repo, "HEAD", None) {
+        Ok(_) => snapshot_tree(repo, "HEAD"),
+        Err(_) => Ok(Snapshot::new()),
+    }
+}
+
+fn snapshot_index(index: &GitIndex) -> Snapshot {
+    index
+        .entries
+        .iter()
+        .map(|e| {
+            let state = FileState {
+                mode: e.mode,
+                sha: e.sha.clone(),
+                disk_path: None,
+            };
+            (e.path.clone(), state)
+        })
+        .collect()
+}
+
+/// Tracked files as they are on disk; unchanged files reuse the index SHA.
+fn snapshot_worktree(repo: &GitRepository, index: &GitIndex) -> Result<Snapshot> {
+    let index_mtime = index_mtime(repo);
+    let filemode = filemode_trusted(repo);
+
+    let mut snapshot = Snapshot::new();
+    for e in &index.entries {
+        let abs = repo.worktree.join(&e.path);
+        let Ok(meta) = fs::symlink_metadata(&abs) else {
+            continue;
+        };
+        if meta.is_dir() {
+            continue;
+        }
+
+        let mode = index_mode(&meta, Some(e.mode), filemode);
+        let state = if worktree_matches(repo, e, &meta, index_mtime)? {
+            FileState {
+                mode,
+                sha: e.sha.clone(),
+                disk_path: None,
+            }
+        } else {
+            FileState {
+                mode,
+                sha: worktree_hash(repo, &abs)?,
+                disk_path: Some(abs),
+            }
+        };
+        snapshot.insert(e.path.clone(), state);
+    }
+
+    Ok(snapshot)
+}
+
+fn load(repo: &GitRepository, state: Option<&FileState>) -> Result<Vec<u8>> {
+    match state {
+        None => Ok(Vec::new()),
+        Some(FileState {
+            disk_path: Some(path),
+            ..
+        }) => {
+            let meta = fs::symlink_metadata(path)?;
+            if meta.file_type().is_symlink() {
+                Ok(fs::read_link(path)?.to_string_lossy().into_owned().into_bytes())
+            } else {
+                Ok(fs::read(path)?)
+            }
+        }
+        Some(state) => Ok(object_read_raw(repo, &state.sha)?.1),
+    }
+}
+
+/// Render `diff --git` output for every path that differs between two snapshots.
+fn diff_snapshots(
+    repo: &GitRepository,
+    old: &Snapshot,
+    new: &Snapshot,
+    context: usize,
+) -> Result<Vec<u8>> {
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut out = Vec::new();
+
+    for path in paths {
+        let a = old.get(path);
+        let b = new.get(path);
+        if let (Some(a), Some(b)) = (a, b)
+            && a.sha == b.sha
+            && a.mode == b.mode
+        {
+            continue;
+        }
+
+        writeln!(out, "diff --git a/{path} b/{path}")?;
+
+        let a_sha = a.map(|s| s.sha.as_str()).unwrap_or(NULL_SHA);
+        let b_sha = b.map(|s| s.sha.as_str()).unwrap_or(NULL_SHA);
+        let index_line = format!("index {}..{}", &a_sha[..7], &b_sha[..7]);
+
+        match (a, b) {
+            (None, Some(b)) => {
+                writeln!(out, "new file mode {:06o}", b.mode)?;
+                writeln!(out, "{index_line}")?;
+            }
+            (Some(a), None) => {
+                writeln!(out, "deleted file mode {:06o}", a.mode)?;
+                writeln!(out, "{index_line}")?;
+            }
+            (Some(a), Some(b)) if a.mode != b.mode => {
+                writeln!(out, "old mode {:06o}", a.mode)?;
+                writeln!(out, "new mode {:06o}", b.mode)?;
+                if a.sha != b.sha {
+                    writeln!(out, "{index_line}")?;
+                }
+            }
+            (Some(a), Some(_)) => writeln!(out, "{index_line} {:06o}", a.mode)?,
+            (None, None) => unreachable!(),
+        }
+
+        if a_sha == b_sha {
+            continue;
+        }
+
+        let old_data = load(repo, a)?;
+        let new_data = load(repo, b)?;
+        let a_name = if a.is_some() { format!("a/{path}") } else { "/dev/null".to_string() };
+        let b_name = if b.is_some() { format!("b/{path}") } else { "/dev/null".to_string() };
+
+        if is_binary(&old_data) || is_binary(&new_data) {
+            writeln!(out, "Binary files {a_name} and {b_name} differ")?;
+            continue;
+        }
+
+        // Empty files added or removed have no hunks and no ---/+++ lines
+        let hunks = unified_diff(&old_data, &new_data, context);
+        if !hunks.is_empty() {
+            writeln!(out, "--- {a_name}")?;
+            writeln!(out, "+++ {b_name}")?;
+            out.extend_from_slice(&hunks);
+        }
+    }
+
+    Ok(out)
+}
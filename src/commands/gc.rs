@@ -0,0 +1,13 @@
+use anyhow::Result;
+
+use crate::{
+    commands::repack::{DEFAULT_DEPTH, DEFAULT_WINDOW, repack},
+    git::repo::repo_find,
+};
+
+pub fn run(aggressive: bool) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let window = if aggressive { 250 } else { DEFAULT_WINDOW };
+    repack(&repo, true, window, DEFAULT_DEPTH)
+}
@@ -1,67 +1,415 @@
-use anyhow::{Context, Result};
-use std::collections::HashSet;
+use std::{cmp::Ordering, io::Write};
+
+use anyhow::{Result, bail};
 
 use crate::git::{
-    objects::{GitCommit, GitObjectType, object_read},
-    repo::{GitRepository, repo_find},
+    ident::Signature,
+    objects::GitCommit,
+    repo::repo_find,
+    revwalk::{CommitFilter, RevWalkOptions, WalkOrder, WalkedCommit, rev_specs_parse, rev_walk},
 };
 
-pub fn run(sha: &str) -> Result<()> {
+/// How each commit is printed.
+#[derive(Debug, Clone)]
+pub enum LogFormat {
+    /// `commit <sha>` / `Author:` / `Date:` / indented message.
+    Medium,
+    /// `<sha> <subject>`, abbreviated with `--oneline`.
+    Oneline { abbrev: bool },
+    /// A GraphViz digraph of the history.
+    Dot,
+    /// A `%`-placeholder template; `terminator` ends every entry with a
+    /// newline (`tformat:`) instead of separating entries (`format:`).
+    Template { template: String, terminator: bool },
+}
+
+impl LogFormat {
+    /// Whether every entry is followed by a newline, as opposed to entries
+    /// being separated by one (`medium` and `format:`).
+    fn terminator(&self) -> bool {
+        match self {
+            LogFormat::Medium | LogFormat::Dot => false,
+            LogFormat::Oneline { .. } => true,
+            LogFormat::Template { terminator, .. } => *terminator,
+        }
+    }
+
+    /// Parse a `--format`/`--pretty` value.
+    pub fn parse(value: &str) -> Result<Self> {
+        Ok(match value {
+            "medium" => LogFormat::Medium,
+            "oneline" => LogFormat::Oneline { abbrev: false },
+            "dot" => LogFormat::Dot,
+            _ => {
+                if let Some(template) = value.strip_prefix("format:") {
+                    LogFormat::Template {
+                        template: template.to_string(),
+                        terminator: false,
+                    }
+                } else if let Some(template) = value.strip_prefix("tformat:") {
+                    LogFormat::Template {
+                        template: template.to_string(),
+                        terminator: true,
+                    }
+                } else if value.contains('%') {
+                    LogFormat::Template {
+                        template: value.to_string(),
+                        terminator: true,
+                    }
+                } else {
+                    bail!("invalid --pretty format: {value}");
+                }
+            }
+        })
+    }
+}
+
+pub struct LogOptions {
+    pub format: LogFormat,
+    pub graph: bool,
+    pub order: WalkOrder,
+    pub max_count: Option<usize>,
+    pub reverse: bool,
+    pub paths: Vec<String>,
+    pub filter: CommitFilter,
+}
+
+pub fn run(revs: &[String], opts: LogOptions) -> Result<()> {
     let repo = repo_find(".", true)?.unwrap();
 
+    let specs = rev_specs_parse(&repo, revs)?;
+
+    // Lanes only make sense when children come before their parents
+    let order = match opts.order {
+        WalkOrder::Date if opts.graph => WalkOrder::Topo,
+        order => order,
+    };
+    let walked = rev_walk(
+        &repo,
+        &RevWalkOptions {
+            include: specs.include,
+            exclude: specs.exclude,
+            order,
+            max_count: opts.max_count,
+            reverse: opts.reverse,
+            paths: opts.paths,
+            filter: opts.filter,
+        },
+    )?;
+
+    if let LogFormat::Dot = opts.format {
+        print_dot(&walked);
+        return Ok(());
+    }
+
+    let terminator = opts.format.terminator();
+    let mut out = String::new();
+    let mut graph = opts.graph.then(Graph::default);
+    let mut previous_terminated = false;
+
+    for (i, entry) in walked.iter().enumerate() {
+        let text = format_commit(entry, &opts.format);
+
+        let Some(graph) = graph.as_mut() else {
+            if !terminator && i > 0 {
+                out.push('\n');
+            }
+            out.push_str(&text);
+            if terminator {
+                out.push('\n');
+            }
+            continue;
+        };
+
+        let rows = graph.next(&entry.sha, &entry.parents);
+        if !terminator && i > 0 {
+            // The separator line already belongs to this commit's lanes
+            if previous_terminated {
+                out.push_str(&rows.before);
+            }
+            out.push('\n');
+        }
+
+        let terminated = text.ends_with('\n');
+        let mut prefixes = std::iter::once(&rows.commit).chain(&rows.connectors);
+        for (j, line) in text
+            .strip_suffix('\n')
+            .unwrap_or(&text)
+            .split('\n')
+            .enumerate()
+        {
+            if j > 0 {
+                out.push('\n');
+            }
+            out.push_str(prefixes.next().unwrap_or(&rows.padding));
+            out.push_str(line);
+        }
+        // Lanes still joining or splitting get rows of their own
+        for connector in prefixes {
+            out.push('\n');
+            out.push_str(connector);
+        }
+        if terminated {
+            out.push('\n');
+        }
+        if terminator {
+            if terminated {
+                out.push_str(&rows.padding);
+            }
+            out.push('\n');
+        }
+        previous_terminated = terminated;
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(out.as_bytes())?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn print_dot(walked: &[WalkedCommit]) {
     println!("digraph wyaglog{{");
     println!("  node[shape=rect]");
 
-    let mut seen = HashSet::<String>::new();
-    walk(&repo, sha, &mut seen)?;
+    for entry in walked {
+        let subject = message_subject(&entry.commit)
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        println!(
+            r#"  c_{s} [label="{short}: {label}"]"#,
+            s = entry.sha,
+            short = &entry.sha[..7],
+            label = subject
+        );
+        for parent in &entry.parents {
+            println!("  c_{s} -> c_{p};", s = entry.sha, p = parent);
+        }
+    }
 
     println!("}}");
-    Ok(())
 }
 
-fn walk(repo: &GitRepository, sha: &str, seen: &mut HashSet<String>) -> Result<()> {
-    if !seen.insert(sha.to_string()) {
-        return Ok(());
-    }
+/// The text shown for one commit, before any graph decoration.
+fn format_commit(entry: &WalkedCommit, format: &LogFormat) -> String {
+    let commit = &entry.commit;
 
-    let (obj_type, obj) = object_read(repo, sha)?;
-    let commit = match obj_type {
-        GitObjectType::commit => {
-            let commit = obj
-                .as_any()
-                .downcast_ref::<GitCommit>()
-                .context("Failed to downcast to GitCommit")?;
-
-            commit
-            // let msg = String::from_utf8_lossy(&commit.kvlm.message);
-            // println!("commit {sha}\n\n  {}", msg.lines().next().unwrap_or(""));
+    match format {
+        LogFormat::Medium => {
+            let mut out = format!("commit {}\n", entry.sha);
+
+            let parents = commit.parents();
+            if parents.len() > 1 {
+                let short: Vec<&str> = parents.iter().map(|p| &p[..7]).collect();
+                out.push_str(&format!("Merge: {}\n", short.join(" ")));
+            }
+            if let Some(author) = commit.author() {
+                out.push_str(&format!("Author: {}\n", author.ident()));
+                out.push_str(&format!("Date:   {}\n", author.date_default()));
+            }
+            out.push('\n');
+
+            let message = String::from_utf8_lossy(&commit.kvlm.message);
+            for line in message.trim_end_matches('\n').lines() {
+                out.push_str(&format!("    {line}\n"));
+            }
+            out
         }
-        _ => anyhow::bail!("object {sha} is not a commit"),
-    };
+        LogFormat::Oneline { abbrev } => {
+            let sha = if *abbrev { &entry.sha[..7] } else { &entry.sha };
+            format!("{sha} {}", message_subject(commit))
+        }
+        LogFormat::Template { template, .. } => format_template(template, entry),
+        LogFormat::Dot => String::new(),
+    }
+}
+
+/// Expand `%` placeholders for one commit.
+fn format_template(template: &str, entry: &WalkedCommit) -> String {
+    let commit = &entry.commit;
+    let author = commit.author();
+    let committer = commit.committer();
+    let parents = commit.parents();
+
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(pos) = rest.find('%') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
 
-    let mut first_line = String::new();
-    if let Ok(msg) = String::from_utf8(commit.kvlm.message.clone()) {
-        first_line = msg.lines().next().unwrap_or("").to_string();
-        first_line = first_line.replace('\\', "\\\\").replace('"', "\\\"");
+        let sig_field = |sig: &Option<Signature>, field: char| {
+            sig.as_ref().map(|s| match field {
+                'n' => s.name.clone(),
+                'e' => s.email.clone(),
+                'd' => s.date_default(),
+                't' => s.timestamp.to_string(),
+                _ => unreachable!(),
+            })
+        };
+
+        let (expansion, used) = match rest.as_bytes() {
+            [b'H', ..] => (Some(entry.sha.clone()), 1),
+            [b'h', ..] => (Some(entry.sha[..7].to_string()), 1),
+            [b'T', ..] => (commit.tree().ok(), 1),
+            [b't', ..] => (commit.tree().ok().map(|t| t[..7].to_string()), 1),
+            [b'P', ..] => (Some(parents.join(" ")), 1),
+            [b'p', ..] => (
+                Some(
+                    parents
+                        .iter()
+                        .map(|p| &p[..7])
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                1,
+            ),
+            [b'a', f @ (b'n' | b'e' | b'd' | b't'), ..] => (sig_field(&author, *f as char), 2),
+            [b'c', f @ (b'n' | b'e' | b'd' | b't'), ..] => (sig_field(&committer, *f as char), 2),
+            [b's', ..] => (Some(message_subject(commit)), 1),
+            [b'b', ..] => (Some(message_body(commit)), 1),
+            [b'B', ..] => (
+                Some(String::from_utf8_lossy(&commit.kvlm.message).to_string()),
+                1,
+            ),
+            [b'n', ..] => (Some("\n".to_string()), 1),
+            [b'%', ..] => (Some("%".to_string()), 1),
+            // Unknown placeholders are printed as-is
+            _ => (None, 0),
+        };
+
+        match expansion {
+            Some(text) => out.push_str(&text),
+            None => {
+                out.push('%');
+                out.push_str(&rest[..used]);
+            }
+        }
+        rest = &rest[used..];
     }
-    println!(
-        r#"  c_{s} [label="{short}: {label}"]"#,
-        s = sha,
-        short = &sha[..7.min(sha.len())],
-        label = first_line
-    );
-
-    // Parents: all headers with key "parent"
-    for (_k, v) in commit
-        .kvlm
-        .headers
-        .iter()
-        .filter(|(k, _)| k.as_slice() == b"parent")
-    {
-        let p = String::from_utf8_lossy(v).to_string();
-        println!("  c_{s} -> c_{p};", s = sha, p = p);
-        walk(repo, &p, seen)?;
+    out.push_str(rest);
+
+    out
+}
+
+/// The first paragraph of the message, joined into one line.
+fn message_subject(commit: &GitCommit) -> String {
+    let message = String::from_utf8_lossy(&commit.kvlm.message);
+    message
+        .lines()
+        .skip_while(|l| l.trim().is_empty())
+        .take_while(|l| !l.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Everything after the subject paragraph.
+fn message_body(commit: &GitCommit) -> String {
+    let message = String::from_utf8_lossy(&commit.kvlm.message);
+    let body: Vec<&str> = message
+        .lines()
+        .skip_while(|l| l.trim().is_empty())
+        .skip_while(|l| !l.trim().is_empty())
+        .skip_while(|l| l.trim().is_empty())
+        .collect();
+
+    if body.is_empty() {
+        String::new()
+    } else {
+        body.join("\n") + "\n"
     }
+}
 
-    Ok(())
+/// Graph prefixes for one commit's output.
+struct GraphRows {
+    /// Lanes as they are before the commit, for a separator line above it.
+    before: String,
+    /// Prefix of the commit's first line.
+    commit: String,
+    /// Rows showing lanes splitting or joining below the commit.
+    connectors: Vec<String>,
+    /// Prefix of the commit's remaining lines.
+    padding: String,
+}
+
+/// ASCII lanes for `--graph`: each lane holds the commit expected next in it.
+#[derive(Default)]
+struct Graph {
+    lanes: Vec<String>,
+}
+
+impl Graph {
+    fn next(&mut self, sha: &str, parents: &[String]) -> GraphRows {
+        let idx = match self.lanes.iter().position(|l| l == sha) {
+            Some(idx) => idx,
+            None => {
+                self.lanes.push(sha.to_string());
+                self.lanes.len() - 1
+            }
+        };
+
+        // The commit's lane continues with its parents, new ones taking its place
+        let mut next = self.lanes.clone();
+        next.remove(idx);
+        let mut insert_at = idx;
+        for parent in parents {
+            if !next.contains(parent) {
+                next.insert(insert_at, parent.clone());
+                insert_at += 1;
+            }
+        }
+
+        let width = self.lanes.len().max(next.len()) * 2;
+        let pad = |row: String| format!("{row:width$}");
+
+        let commit_row: String = (0..self.lanes.len())
+            .map(|i| if i == idx { "* " } else { "| " })
+            .collect();
+
+        // Where every old lane ends up in the next row
+        let mut edges = Vec::new();
+        for (i, lane) in self.lanes.iter().enumerate() {
+            if i == idx {
+                for parent in parents {
+                    if let Some(to) = next.iter().position(|l| l == parent) {
+                        edges.push((i, to));
+                    }
+                }
+            } else if let Some(to) = next.iter().position(|l| l == lane) {
+                edges.push((i, to));
+            }
+        }
+
+        // Lines move one column per row until every lane reaches its place
+        let mut connectors = Vec::new();
+        while edges.iter().any(|(from, to)| from != to) {
+            let mut row = vec![' '; width];
+            for (from, to) in edges.iter_mut() {
+                match (*to).cmp(from) {
+                    Ordering::Equal => row[2 * *from] = '|',
+                    Ordering::Greater => {
+                        row[2 * *from + 1] = '\\';
+                        *from += 1;
+                    }
+                    Ordering::Less => {
+                        row[2 * *from - 1] = '/';
+                        *from -= 1;
+                    }
+                }
+            }
+            connectors.push(row.into_iter().collect());
+        }
+
+        let before: String = self.lanes.iter().map(|_| "| ").collect();
+        let padding: String = next.iter().map(|_| "| ").collect();
+
+        self.lanes = next;
+        GraphRows {
+            before: pad(before),
+            commit: pad(commit_row),
+            connectors,
+            padding: pad(padding),
+        }
+    }
 }
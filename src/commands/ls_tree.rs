@@ -9,13 +9,10 @@ use crate::git::{
 };
 
 fn mode_to_type(mode: &str) -> Result<&'static str> {
-    let prefix = if mode.len() == 5 {
-        &mode[0..1]
-    } else {
-        &mode[0..2]
-    };
+    // Trees are stored as "40000", without the leading zero
+    let mode = format!("{mode:0>6}");
 
-    match prefix {
+    match &mode[0..2] {
         "04" => Ok("tree"),
         "10" => Ok("blob"),   // regular file
         "12" => Ok("blob"),   // symlink
@@ -25,7 +22,7 @@ fn mode_to_type(mode: &str) -> Result<&'static str> {
 }
 
 fn ls_tree(repo: &GitRepository, sha: &str, recursive: bool, prefix: &Path) -> Result<()> {
-    let (obj_type, obj) = object_read(&repo, sha)?;
+    let (obj_type, obj) = object_read(repo, sha)?;
 
     if obj_type == GitObjectType::tree {
         let tree = obj
@@ -37,7 +34,7 @@ fn ls_tree(repo: &GitRepository, sha: &str, recursive: bool, prefix: &Path) -> R
             let otype = mode_to_type(&entry.mode)?;
             let path = prefix.join(&entry.path);
 
-            if !(recursive && otype == "Tree") {
+            if !(recursive && otype == "tree") {
                 let padded_mode = format!("{:0>6}", entry.mode);
 
                 println!(
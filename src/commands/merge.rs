@@ -0,0 +1,223 @@
+use std::{collections::BTreeMap, fs};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    commands::commit::{commit_create, head_commit, head_update, message_cleanup},
+    git::{
+        hooks::hook_run,
+        index::{read_index, write_index},
+        lockfile::lockfile_write,
+        merge::{MergeEntry, merge_trees},
+        merge_base::merge_bases,
+        objects::{GitBlob, GitObjectType, commit_read, object_find, object_write},
+        refs::resolve_ref,
+        repo::{GitRepository, repo_find},
+        status::{RepoStatus, head_tree, status_compute},
+        tree::{GitTreeLeaf, tree_flatten, tree_from_index},
+        worktree::{
+            index_entry_for, index_from_tree, worktree_remove, worktree_untracked_check,
+            worktree_update, worktree_write, worktree_write_blob,
+        },
+    },
+};
+
+/// Merge `name` into HEAD. `no_verify` skips the pre-merge-commit and
+/// commit-msg hooks.
+pub fn run(name: &str, no_verify: bool) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    if repo.gitdir.join("MERGE_HEAD").exists() {
+        bail!("You have not concluded your merge (MERGE_HEAD exists). Commit the result first.");
+    }
+
+    let theirs = object_find(&repo, name, Some(GitObjectType::commit))?;
+
+    let index = read_index(&repo)?;
+    let status = status_compute(&repo, &index)?;
+    if !status.entries.is_empty() {
+        bail!("Your local changes would be overwritten by merge. Commit them first.");
+    }
+
+    let Some(ours) = head_commit(&repo)? else {
+        // Nothing to merge into: just take their history
+        return fast_forward(&repo, &status, name, None, &theirs);
+    };
+
+    let bases = merge_bases(&repo, &ours, &theirs)?;
+    if bases.contains(&theirs) {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    if bases.contains(&ours) {
+        return fast_forward(&repo, &status, name, Some(&ours), &theirs);
+    }
+    let Some(base) = bases.first() else {
+        bail!("refusing to merge unrelated histories");
+    };
+
+    let base_tree = tree_flatten(&repo, &commit_read(&repo, base)?.tree()?)?;
+    let ours_tree = head_tree(&repo)?;
+    let theirs_tree = tree_flatten(&repo, &commit_read(&repo, &theirs)?.tree()?)?;
+
+    let merged = merge_trees(&repo, &base_tree, &ours_tree, &theirs_tree, "HEAD", name)?;
+
+    let kind = if resolve_ref(&repo, &format!("refs/heads/{name}")).is_ok() {
+        "branch"
+    } else {
+        "commit"
+    };
+    let mut message = format!("Merge {kind} '{name}'\n");
+
+    let conflicts = merge_apply(&repo, &status, &ours_tree, &merged)?;
+
+    if !conflicts.is_empty() {
+        message.push_str("\nConflicts:\n");
+        for (path, reason) in &conflicts {
+            println!("CONFLICT ({reason}): Merge conflict in {path}");
+            message.push_str(&format!("\t{path}\n"));
+        }
+
+        lockfile_write(
+            &repo.gitdir.join("MERGE_HEAD"),
+            format!("{theirs}\n").as_bytes(),
+        )?;
+        lockfile_write(&repo.gitdir.join("MERGE_MSG"), message.as_bytes())?;
+        bail!("Automatic merge failed; fix conflicts and then commit the result.");
+    }
+
+    // The merge is recorded as in progress while hooks look at it, so a
+    // rejected merge can still be concluded with `commit`
+    let merge_head = repo.gitdir.join("MERGE_HEAD");
+    let merge_msg = repo.gitdir.join("MERGE_MSG");
+    lockfile_write(&merge_head, format!("{theirs}\n").as_bytes())?;
+    lockfile_write(&merge_msg, message.as_bytes())?;
+
+    let merge_msg_arg = merge_msg.to_string_lossy();
+    let hooks_passed = (no_verify || hook_run(&repo, "pre-merge-commit", &[], None)?)
+        && hook_run(&repo, "prepare-commit-msg", &[&merge_msg_arg, "merge"], None)?
+        && (no_verify || hook_run(&repo, "commit-msg", &[&merge_msg_arg], None)?);
+    if !hooks_passed {
+        bail!("Not committing merge; use 'commit' to complete the merge.");
+    }
+    let message = message_cleanup(&fs::read_to_string(&merge_msg)?, false);
+
+    let mut index = read_index(&repo)?;
+    let tree_sha = tree_from_index(&repo, &mut index)?;
+    write_index(&repo, &index)?;
+    let commit_sha = commit_create(&repo, &tree_sha, &[ours, theirs], &message, None)?;
+    head_update(
+        &repo,
+        &commit_sha,
+        &format!("merge {name}: Merge made by the 'three-way' strategy."),
+    )?;
+    fs::remove_file(&merge_head)?;
+    fs::remove_file(&merge_msg)?;
+
+    println!("Merge made by the three-way strategy.");
+    println!("[{}] {}", &commit_sha[..7], message.trim());
+
+    hook_run(&repo, "post-merge", &["0"], None)?;
+
+    Ok(())
+}
+
+/// Write the merge result to the worktree and index (conflicts as stages 1-3),
+/// unless that would overwrite untracked files. Returns the conflicted paths
+/// with the kind of conflict.
+fn merge_apply(
+    repo: &GitRepository,
+    status: &RepoStatus,
+    ours_tree: &BTreeMap<String, GitTreeLeaf>,
+    merged: &BTreeMap<String, MergeEntry>,
+) -> Result<Vec<(String, &'static str)>> {
+    worktree_untracked_check(
+        repo,
+        status,
+        ours_tree,
+        merged.keys().map(String::as_str),
+        "merge",
+    )?;
+
+    for path in ours_tree.keys() {
+        if !merged.contains_key(path) {
+            worktree_remove(repo, path)?;
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for (path, entry) in merged {
+        match entry {
+            MergeEntry::Clean(mode, sha) => {
+                let unchanged = ours_tree
+                    .get(path)
+                    .is_some_and(|l| hex::encode(l.sha) == *sha && l.mode_bits() == *mode);
+                if !unchanged {
+                    worktree_write_blob(repo, path, *mode, sha)?;
+                }
+                entries.push(index_entry_for(repo, path, *mode, sha, 0)?);
+            }
+            MergeEntry::Merged(mode, data) => {
+                let blob = GitBlob { data: data.clone() };
+                let sha = object_write(repo, &blob, &GitObjectType::blob, true)?;
+                worktree_write(repo, path, *mode, data)?;
+                entries.push(index_entry_for(repo, path, *mode, &sha, 0)?);
+            }
+            MergeEntry::Conflict {
+                stages,
+                worktree,
+                reason,
+            } => {
+                match worktree {
+                    Some((mode, data)) => worktree_write(repo, path, *mode, data)?,
+                    None => worktree_remove(repo, path)?,
+                }
+                for (stage, side) in stages.iter().enumerate() {
+                    if let Some((mode, sha)) = side {
+                        entries.push(index_entry_for(repo, path, *mode, sha, stage as u16 + 1)?);
+                    }
+                }
+                conflicts.push((path.clone(), *reason));
+            }
+        }
+    }
+
+    let mut index = read_index(repo)?;
+    index.entries = entries;
+    index.resolve_undo.clear();
+    write_index(repo, &index)?;
+    Ok(conflicts)
+}
+
+fn fast_forward(
+    repo: &GitRepository,
+    status: &RepoStatus,
+    name: &str,
+    ours: Option<&str>,
+    theirs: &str,
+) -> Result<()> {
+    let from = match ours {
+        Some(_) => head_tree(repo)?,
+        None => BTreeMap::new(),
+    };
+    let to = tree_flatten(repo, &commit_read(repo, theirs)?.tree()?)?;
+    worktree_untracked_check(repo, status, &from, to.keys().map(String::as_str), "merge")?;
+
+    worktree_update(repo, &from, &to)?;
+    let mut index = read_index(repo)?;
+    index.entries = index_from_tree(repo, &to)?;
+    index.resolve_undo.clear();
+    write_index(repo, &index)?;
+    head_update(repo, theirs, &format!("merge {name}: Fast-forward"))?;
+
+    if let Some(ours) = ours {
+        println!("Updating {}..{}", &ours[..7], &theirs[..7]);
+    }
+    println!("Fast-forward");
+
+    hook_run(repo, "post-merge", &["0"], None)?;
+
+    Ok(())
+}
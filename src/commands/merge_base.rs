@@ -0,0 +1,63 @@
+use anyhow::{Result, bail};
+
+use crate::git::{
+    merge_base::{is_ancestor, merge_bases_many, merge_bases_octopus},
+    objects::{GitObjectType, object_find},
+    repo::repo_find,
+};
+
+/// Print the best common ancestor of `commits` (all of them with `all`). The
+/// first commit is compared against a hypothetical merge of the others, or
+/// with `octopus` all of them against each other. Exits with 1 if there is
+/// none.
+pub fn run(commits: &[String], all: bool, octopus: bool) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let shas = commits
+        .iter()
+        .map(|c| object_find(&repo, c, Some(GitObjectType::commit)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let bases = if octopus {
+        merge_bases_octopus(&repo, &shas)?
+    } else {
+        let Some((first, others)) = shas.split_first() else {
+            bail!("merge-base needs at least two commits");
+        };
+        if others.is_empty() {
+            bail!("merge-base needs at least two commits");
+        }
+        merge_bases_many(&repo, first, others)?
+    };
+
+    if bases.is_empty() {
+        std::process::exit(1);
+    }
+    let shown = if all { bases.len() } else { 1 };
+    for base in &bases[..shown] {
+        println!("{base}");
+    }
+
+    Ok(())
+}
+
+/// Exit with 0 if `ancestor` is an ancestor of `descendant`, 1 otherwise.
+/// Errors, like a name that does not resolve to a commit, exit with 128 so
+/// they cannot be mistaken for a "no".
+pub fn is_ancestor_check(ancestor: &str, descendant: &str) -> Result<()> {
+    let check = || -> Result<bool> {
+        let repo = repo_find(".", true)?.unwrap();
+        let ancestor = object_find(&repo, ancestor, Some(GitObjectType::commit))?;
+        let descendant = object_find(&repo, descendant, Some(GitObjectType::commit))?;
+        is_ancestor(&repo, &ancestor, &descendant)
+    };
+
+    match check() {
+        Ok(true) => Ok(()),
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            std::process::exit(128);
+        }
+    }
+}
@@ -12,4 +12,17 @@ pub mod check_ignore;
 pub mod status;
 pub mod rm;
 pub mod add;
-pub mod commit;
\ No newline at end of file
+pub mod commit;
+pub mod repack;
+pub mod gc;
+pub mod diff;
+pub mod merge;
+pub mod branch;
+pub mod switch;
+pub mod pack_refs;
+pub mod reflog;
+pub mod rev_list;
+pub mod merge_base;
+pub mod reset;
+pub mod restore;
+pub mod update_ref;
\ No newline at end of file
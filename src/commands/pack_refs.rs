@@ -0,0 +1,75 @@
+use std::{collections::BTreeMap, fs};
+
+use anyhow::{Context, Result};
+
+use crate::git::{
+    lockfile::LockFile,
+    objects::object_peel,
+    refs::{PackedRef, collect_refs, packed_refs_lock, packed_refs_read, packed_refs_write},
+    repo::repo_find,
+};
+
+/// Move loose refs into `.git/packed-refs`: tags only, or every ref with `all`.
+/// Symbolic refs always stay loose.
+pub fn run(all: bool) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let packed_lock = packed_refs_lock(&repo)?;
+    let mut packed: BTreeMap<String, PackedRef> = packed_refs_read(&repo)?
+        .into_iter()
+        .map(|r| (r.name.clone(), r))
+        .collect();
+    let mut loose = Vec::new();
+
+    for (sha, name) in collect_refs(&repo, "refs")? {
+        let path = repo.gitdir.join(&name);
+        if !path.is_file() || (!all && !name.starts_with("refs/tags/")) {
+            continue;
+        }
+        if fs::read_to_string(&path)?.starts_with("ref: ") {
+            continue;
+        }
+
+        packed.insert(
+            name.clone(),
+            PackedRef {
+                name,
+                sha: sha.clone(),
+                peeled: None,
+            },
+        );
+        loose.push((path, sha));
+    }
+
+    // The header promises every annotated tag carries its peeled value
+    for r in packed.values_mut() {
+        if r.peeled.is_none() {
+            let peeled = object_peel(&repo, &r.sha, None)?;
+            r.peeled = (peeled != r.sha).then_some(peeled);
+        }
+    }
+
+    packed_refs_write(packed_lock, &packed.into_values().collect::<Vec<_>>())?;
+
+    let refs_dir = repo.gitdir.join("refs");
+    for (path, sha) in loose {
+        // A ref updated since it was packed keeps its loose value
+        let _lock = LockFile::acquire(&path)?;
+        if fs::read_to_string(&path)?.trim() != sha {
+            continue;
+        }
+        fs::remove_file(&path).with_context(|| format!("Failed to delete ref {:?}", path))?;
+
+        // Prune directories left empty, keeping refs/ and its top-level namespaces
+        let mut dir = path.parent();
+        while let Some(d) = dir {
+            if d.parent() == Some(refs_dir.as_path()) || d == refs_dir || fs::remove_dir(d).is_err()
+            {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+
+    Ok(())
+}
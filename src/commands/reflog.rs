@@ -0,0 +1,113 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+
+use crate::git::{
+    ident::approxidate,
+    lockfile::LockFile,
+    reflog::{reflog_read, reflog_write},
+    refs::ref_dwim,
+    repo::{GitRepository, repo_find},
+};
+
+const DEFAULT_EXPIRE: &str = "90.days.ago";
+
+fn reflog_refname(repo: &GitRepository, name: &str) -> Result<String> {
+    ref_dwim(repo, name)?.with_context(|| format!("unknown ref: {name}"))
+}
+
+/// Print the reflog of `name`, newest entry first.
+pub fn show(name: &str) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+    let refname = reflog_refname(&repo, name)?;
+
+    for (i, entry) in reflog_read(&repo, &refname)?.iter().rev().enumerate() {
+        // A hand-edited log may hold a short or empty new value
+        let short = entry.new.get(..7).unwrap_or(&entry.new);
+        println!("{short} {name}@{{{i}}}: {}", entry.message);
+    }
+
+    Ok(())
+}
+
+/// Drop entries older than `expire` from the reflogs of `names` (or of every ref with `all`).
+pub fn expire(expire: Option<&str>, all: bool, names: &[String]) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let cutoff = match expire.unwrap_or(DEFAULT_EXPIRE) {
+        "all" => i64::MAX,
+        "never" => i64::MIN,
+        date => approxidate(date)?,
+    };
+
+    let refnames = if all {
+        let mut refnames = Vec::new();
+        let logs_dir = repo.gitdir.join("logs");
+        if logs_dir.is_dir() {
+            collect_logs(&logs_dir, "", &mut refnames)?;
+        }
+        refnames
+    } else {
+        names
+            .iter()
+            .map(|name| reflog_refname(&repo, name))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    for refname in refnames {
+        // The ref stays locked so no entry is appended while the log is rewritten
+        let lock = LockFile::acquire(&repo.gitdir.join(&refname))?;
+        let mut entries = reflog_read(&repo, &refname)?;
+        let before = entries.len();
+        entries.retain(|e| e.timestamp >= cutoff);
+        if entries.len() != before {
+            reflog_write(&repo, &lock, &refname, &entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove single entries given as `<ref>@{<n>}`.
+pub fn delete(specs: &[String]) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    for spec in specs {
+        let parsed = spec
+            .split_once("@{")
+            .and_then(|(name, n)| Some((name, n.strip_suffix('}')?.parse::<usize>().ok()?)));
+        let Some((name, n)) = parsed else {
+            bail!("not a reflog entry: {spec}");
+        };
+
+        let refname = reflog_refname(&repo, if name.is_empty() { "HEAD" } else { name })?;
+        let lock = LockFile::acquire(&repo.gitdir.join(&refname))?;
+        let mut entries = reflog_read(&repo, &refname)?;
+        if n >= entries.len() {
+            bail!("reflog entry {spec} does not exist");
+        }
+        entries.remove(entries.len() - 1 - n);
+        reflog_write(&repo, &lock, &refname, &entries)?;
+    }
+
+    Ok(())
+}
+
+fn collect_logs(dir: &Path, prefix: &str, out: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let refname = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+
+        if entry.path().is_dir() {
+            collect_logs(&entry.path(), &refname, out)?;
+        } else {
+            out.push(refname);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashSet, fs};
+
+use anyhow::{Context, Result};
+
+use crate::git::{
+    kvlm::kvlm_parse,
+    objects::{GitObject, GitObjectType, object_read_raw, object_resolve},
+    pack::{PackEntry, pack_indexes, pack_write},
+    refs::collect_refs,
+    repo::{GitRepository, repo_find},
+    tree::GitTree,
+};
+
+pub const DEFAULT_WINDOW: usize = 10;
+pub const DEFAULT_DEPTH: usize = 50;
+
+pub fn run(delete_redundant: bool, window: usize, depth: usize) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    repack(&repo, delete_redundant, window, depth)
+}
+
+/// Pack every object reachable from HEAD and the refs into a single new pack.
+/// With `delete_redundant`, loose objects and older packs that are fully
+/// contained in the new pack are removed afterwards.
+pub fn repack(repo: &GitRepository, delete_redundant: bool, window: usize, depth: usize) -> Result<()> {
+    let objects = reachable_objects(repo)?;
+    if objects.is_empty() {
+        println!("Nothing new to pack.");
+        return Ok(());
+    }
+
+    let packed: HashSet<String> = objects.iter().map(|o| o.sha.clone()).collect();
+    let old_packs = pack_indexes(repo)?;
+
+    let total = objects.len();
+    let (name, deltas) = pack_write(repo, objects, window, depth)?;
+    println!("Packed {total} objects ({deltas} deltas) into pack-{name}.pack");
+
+    if !delete_redundant {
+        return Ok(());
+    }
+
+    let mut removed = 0usize;
+    for sha in &packed {
+        let dir = repo.gitdir.join("objects").join(&sha[..2]);
+        let path = dir.join(&sha[2..]);
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+            removed += 1;
+            // Only succeeds once the fan-out directory is empty
+            let _ = fs::remove_dir(&dir);
+        }
+    }
+    println!("Removed {removed} redundant loose objects");
+
+    let new_pack = repo
+        .gitdir
+        .join("objects")
+        .join("pack")
+        .join(format!("pack-{name}.pack"));
+    for index in old_packs {
+        if index.pack_path == new_pack || index.pack_path.with_extension("keep").exists() {
+            continue;
+        }
+        if index.shas().all(|sha| packed.contains(&sha)) {
+            fs::remove_file(index.pack_path.with_extension("idx"))?;
+            fs::remove_file(&index.pack_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Walk commits, trees and tags from every ref, collecting each object once.
+fn reachable_objects(repo: &GitRepository) -> Result<Vec<PackEntry>> {
+    let mut pending: Vec<(String, String)> = Vec::new();
+
+    if let Ok(sha) = object_resolve(repo, "HEAD") {
+        pending.push((sha, String::new()));
+    }
+    for (sha, _) in collect_refs(repo, "refs")? {
+        pending.push((sha, String::new()));
+    }
+
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+
+    while let Some((sha, path)) = pending.pop() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+
+        let (object_type, data) = object_read_raw(repo, &sha)?;
+        match object_type {
+            GitObjectType::commit => {
+                let kvlm = kvlm_parse(&data)?;
+                let tree = kvlm.get(b"tree").context("Commit without tree")?;
+                pending.push((String::from_utf8(tree.to_vec())?, String::new()));
+                for parent in kvlm.values(b"parent") {
+                    pending.push((String::from_utf8(parent.to_vec())?, String::new()));
+                }
+            }
+            GitObjectType::tree => {
+                let tree = GitTree::deserialize(&data)?;
+                for entry in &tree.entries {
+                    // Submodule commits live in another repository
+                    if entry.mode.starts_with("16") {
+                        continue;
+                    }
+                    let child_path = if path.is_empty() {
+                        entry.path.clone()
+                    } else {
+                        format!("{path}/{}", entry.path)
+                    };
+                    pending.push((hex::encode(entry.sha), child_path));
+                }
+            }
+            GitObjectType::tag => {
+                let kvlm = kvlm_parse(&data)?;
+                let target = kvlm.get(b"object").context("Tag without object")?;
+                pending.push((String::from_utf8(target.to_vec())?, String::new()));
+            }
+            GitObjectType::blob => {}
+        }
+
+        objects.push(PackEntry {
+            sha,
+            object_type,
+            data,
+            path,
+        });
+    }
+
+    Ok(objects)
+}
@@ -0,0 +1,222 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    commands::commit::{head_commit, head_update},
+    git::{
+        index::{GitIndexEntry, read_index, write_index},
+        objects::{GitObjectType, commit_read, object_find},
+        refs::ref_create,
+        repo::{GitRepository, repo_find},
+        status::{index_mtime, status_compute, worktree_matches},
+        tree::{GitTreeLeaf, tree_flatten},
+        worktree::{
+            index_entry_for, index_entry_unstatted, pathspec_matches, worktree_relpath,
+            worktree_remove, worktree_write_blob,
+        },
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Move the branch only.
+    Soft,
+    /// Move the branch and reset the index.
+    Mixed,
+    /// Move the branch and reset both the index and the worktree.
+    Hard,
+}
+
+/// Point the current branch at `commit` (HEAD by default), resetting the
+/// index and worktree as `mode` says. With `paths`, only the index entries
+/// for those paths are reset to their version in `commit`.
+pub fn run(mode: Option<ResetMode>, commit: Option<&str>, paths: &[PathBuf]) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    // `reset <file>` without `--` when the argument is not a commit
+    if let Some(arg) = commit
+        && paths.is_empty()
+        && object_find(&repo, arg, Some(GitObjectType::commit)).is_err()
+        && fs::symlink_metadata(arg).is_ok()
+    {
+        return run(mode, None, &[PathBuf::from(arg)]);
+    }
+    let rev = commit.unwrap_or("HEAD");
+
+    if !paths.is_empty() {
+        match mode {
+            Some(ResetMode::Soft) => bail!("Cannot do soft reset with paths."),
+            Some(ResetMode::Hard) => bail!("Cannot do hard reset with paths."),
+            _ => return reset_paths(&repo, rev, paths),
+        }
+    }
+    let mode = mode.unwrap_or(ResetMode::Mixed);
+
+    let target = object_find(&repo, rev, Some(GitObjectType::commit))?;
+    let merge_head = repo.gitdir.join("MERGE_HEAD");
+    if mode == ResetMode::Soft && merge_head.exists() {
+        bail!("Cannot do a soft reset in the middle of a merge.");
+    }
+
+    let tree = tree_flatten(&repo, &commit_read(&repo, &target)?.tree()?)?;
+    match mode {
+        ResetMode::Soft => {}
+        ResetMode::Mixed => index_reset(&repo, &tree)?,
+        ResetMode::Hard => worktree_reset(&repo, &tree)?,
+    }
+
+    if let Some(old) = head_commit(&repo)? {
+        ref_create(&repo, "ORIG_HEAD", &old, "")?;
+    }
+    head_update(&repo, &target, &format!("reset: moving to {rev}"))?;
+
+    if mode != ResetMode::Soft {
+        for name in ["MERGE_HEAD", "MERGE_MSG"] {
+            let path = repo.gitdir.join(name);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+
+    match mode {
+        ResetMode::Hard => {
+            let message = String::from_utf8_lossy(&commit_read(&repo, &target)?.kvlm.message)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            println!("HEAD is now at {} {}", &target[..7], message);
+        }
+        ResetMode::Mixed => print_unstaged(&repo)?,
+        ResetMode::Soft => {}
+    }
+
+    Ok(())
+}
+
+/// Replace the index with `tree`. Entries that already had the same content
+/// keep their stat data; the rest are re-hashed on the next status.
+fn index_reset(repo: &GitRepository, tree: &BTreeMap<String, GitTreeLeaf>) -> Result<()> {
+    let mut index = read_index(repo)?;
+    let old: BTreeMap<String, GitIndexEntry> = std::mem::take(&mut index.entries)
+        .into_iter()
+        .filter(|e| e.stage() == 0)
+        .map(|e| (e.path.clone(), e))
+        .collect();
+
+    index.entries = tree
+        .iter()
+        .map(|(path, leaf)| {
+            let sha = hex::encode(leaf.sha);
+            match old.get(path) {
+                Some(e) if e.sha == sha && e.mode == leaf.mode_bits() => e.clone(),
+                _ => index_entry_unstatted(path, leaf.mode_bits(), &sha),
+            }
+        })
+        .collect();
+    index.resolve_undo.clear();
+
+    write_index(repo, &index)
+}
+
+/// Make the index and worktree match `tree`, discarding every local change
+/// to tracked files. Untracked files are left alone.
+fn worktree_reset(repo: &GitRepository, tree: &BTreeMap<String, GitTreeLeaf>) -> Result<()> {
+    let mut index = read_index(repo)?;
+    let index_mtime = index_mtime(repo);
+
+    for entry in &index.entries {
+        if !tree.contains_key(&entry.path) {
+            worktree_remove(repo, &entry.path)?;
+        }
+    }
+
+    for (path, leaf) in tree {
+        let sha = hex::encode(leaf.sha);
+        let current = index
+            .entries
+            .iter()
+            .find(|e| e.path == *path && e.stage() == 0);
+
+        let clean = match (current, fs::symlink_metadata(repo.worktree.join(path))) {
+            (Some(e), Ok(meta)) if e.sha == sha && e.mode == leaf.mode_bits() => {
+                worktree_matches(repo, e, &meta, index_mtime)?
+            }
+            _ => false,
+        };
+        if !clean {
+            worktree_write_blob(repo, path, leaf.mode_bits(), &sha)?;
+        }
+    }
+
+    index.entries = tree
+        .iter()
+        .map(|(path, leaf)| {
+            index_entry_for(repo, path, leaf.mode_bits(), &hex::encode(leaf.sha), 0)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    index.resolve_undo.clear();
+
+    write_index(repo, &index)
+}
+
+/// Reset the index entries under `paths` to their version in `rev`, leaving
+/// HEAD and the worktree alone.
+fn reset_paths(repo: &GitRepository, rev: &str, paths: &[PathBuf]) -> Result<()> {
+    let specs = paths
+        .iter()
+        .map(|p| worktree_relpath(repo, p))
+        .collect::<Result<Vec<_>>>()?;
+    let matches = |path: &str| specs.iter().any(|s| pathspec_matches(s, path));
+
+    // Resetting paths on an unborn branch unstages them
+    let tree = match head_commit(repo)? {
+        None if rev == "HEAD" => BTreeMap::new(),
+        _ => {
+            let target = object_find(repo, rev, Some(GitObjectType::commit))?;
+            tree_flatten(repo, &commit_read(repo, &target)?.tree()?)?
+        }
+    };
+
+    let mut index = read_index(repo)?;
+    let mut entries: BTreeMap<(String, u16), GitIndexEntry> = BTreeMap::new();
+    for entry in std::mem::take(&mut index.entries) {
+        let unchanged = tree
+            .get(&entry.path)
+            .is_some_and(|l| hex::encode(l.sha) == entry.sha && l.mode_bits() == entry.mode);
+        if !matches(&entry.path) || (entry.stage() == 0 && unchanged) {
+            entries.insert((entry.path.clone(), entry.stage()), entry);
+        }
+    }
+    for (path, leaf) in tree.iter().filter(|(p, _)| matches(p)) {
+        entries.entry((path.clone(), 0)).or_insert_with(|| {
+            index_entry_unstatted(path, leaf.mode_bits(), &hex::encode(leaf.sha))
+        });
+    }
+
+    index.entries = entries.into_values().collect();
+    write_index(repo, &index)?;
+
+    print_unstaged(repo)
+}
+
+/// List tracked files whose worktree copy differs from the index.
+fn print_unstaged(repo: &GitRepository) -> Result<()> {
+    let status = status_compute(repo, &read_index(repo)?)?;
+    let unstaged: Vec<_> = status
+        .entries
+        .iter()
+        .filter_map(|e| e.unstaged.map(|kind| (kind, &e.path)))
+        .collect();
+
+    if !unstaged.is_empty() {
+        println!("Unstaged changes after reset:");
+        for (kind, path) in unstaged {
+            println!("{}\t{}", kind.code(), path);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
+use anyhow::{Result, bail};
+
+use crate::git::{
+    index::{GitIndexEntry, read_index, write_index},
+    objects::{GitObjectType, commit_read, object_find},
+    repo::{GitRepository, repo_find},
+    status::head_tree,
+    tree::tree_flatten,
+    worktree::{
+        index_entry_for, index_entry_unstatted, pathspec_matches, worktree_relpath,
+        worktree_remove, worktree_write_blob,
+    },
+};
+
+/// Restore `paths` in the worktree (the default) and/or the index (`staged`).
+/// The worktree is restored from the index and the index from HEAD, unless
+/// `source` names a commit to take both from.
+pub fn run(paths: &[PathBuf], staged: bool, worktree: bool, source: Option<&str>) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+    let worktree = worktree || !staged;
+
+    let specs = paths
+        .iter()
+        .map(|p| worktree_relpath(&repo, p))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut index = read_index(&repo)?;
+    let mut entries: BTreeMap<(String, u16), GitIndexEntry> = std::mem::take(&mut index.entries)
+        .into_iter()
+        .map(|e| ((e.path.clone(), e.stage()), e))
+        .collect();
+
+    // Path -> (mode, sha) of the version being restored
+    let source: BTreeMap<String, (u32, String)> = match source {
+        Some(rev) => source_tree(&repo, rev)?,
+        None if staged => head_tree(&repo)?
+            .into_iter()
+            .map(|(path, leaf)| (path, (leaf.mode_bits(), hex::encode(leaf.sha))))
+            .collect(),
+        None => entries
+            .iter()
+            .filter(|((_, stage), _)| *stage == 0)
+            .map(|((path, _), e)| (path.clone(), (e.mode, e.sha.clone())))
+            .collect(),
+    };
+
+    // Everything a pathspec covers in the source or the index
+    let mut targets = BTreeSet::new();
+    for spec in &specs {
+        let matched: Vec<&String> = source
+            .keys()
+            .chain(entries.keys().map(|(path, _)| path))
+            .filter(|path| pathspec_matches(spec, path))
+            .collect();
+        if matched.is_empty() {
+            bail!("pathspec '{spec}' did not match any file(s) known to git");
+        }
+        targets.extend(matched.into_iter().cloned());
+    }
+
+    if worktree && !staged {
+        let unmerged: Vec<&String> = targets
+            .iter()
+            .filter(|path| entries.keys().any(|(p, stage)| p == *path && *stage != 0))
+            .collect();
+        if let Some(path) = unmerged.first() {
+            bail!("path '{path}' is unmerged");
+        }
+    }
+
+    for path in &targets {
+        let version = source.get(path);
+
+        if worktree {
+            match version {
+                Some((mode, sha)) => worktree_write_blob(&repo, path, *mode, sha)?,
+                None => worktree_remove(&repo, path)?,
+            }
+        }
+
+        if staged {
+            entries.retain(|(p, _), _| p != path);
+            if let Some((mode, sha)) = version {
+                let entry = if worktree {
+                    index_entry_for(&repo, path, *mode, sha, 0)?
+                } else {
+                    index_entry_unstatted(path, *mode, sha)
+                };
+                entries.insert((path.clone(), 0), entry);
+            }
+        } else if let Some((mode, sha)) = version
+            && entries
+                .get(&(path.clone(), 0))
+                .is_some_and(|e| e.sha == *sha && e.mode == *mode)
+        {
+            // The file now matches the index again, so refresh its stat data
+            let entry = index_entry_for(&repo, path, *mode, sha, 0)?;
+            entries.insert((path.clone(), 0), entry);
+        }
+    }
+
+    index.entries = entries.into_values().collect();
+    write_index(&repo, &index)
+}
+
+/// Flattened tree of the commit `rev`, as path -> (mode, sha).
+fn source_tree(repo: &GitRepository, rev: &str) -> Result<BTreeMap<String, (u32, String)>> {
+    let commit = object_find(repo, rev, Some(GitObjectType::commit))?;
+    let tree = tree_flatten(repo, &commit_read(repo, &commit)?.tree()?)?;
+
+    Ok(tree
+        .into_iter()
+        .map(|(path, leaf)| (path, (leaf.mode_bits(), hex::encode(leaf.sha))))
+        .collect())
+}
@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+
+use crate::git::{
+    merge_base::ancestors,
+    objects::{GitCommit, commit_read, object_read},
+    repo::{GitRepository, repo_find},
+    revwalk::{CommitFilter, RevWalkOptions, WalkOrder, rev_specs_parse, rev_walk},
+    tree::GitTree,
+};
+
+pub struct RevListOptions {
+    pub order: WalkOrder,
+    pub max_count: Option<usize>,
+    pub reverse: bool,
+    pub count: bool,
+    pub objects: bool,
+    pub left_right: bool,
+    pub paths: Vec<String>,
+    pub filter: CommitFilter,
+}
+
+pub fn run(revs: &[String], opts: RevListOptions) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+    let specs = rev_specs_parse(&repo, revs)?;
+
+    let walked = rev_walk(
+        &repo,
+        &RevWalkOptions {
+            include: specs.include,
+            exclude: specs.exclude.clone(),
+            order: opts.order,
+            max_count: opts.max_count,
+            reverse: opts.reverse,
+            paths: opts.paths,
+            filter: opts.filter,
+        },
+    )?;
+
+    // Commits reachable from the left side of `A...B` are marked '<'
+    let mut left = HashSet::new();
+    if opts.left_right {
+        for sha in &specs.left {
+            left.extend(ancestors(&repo, sha)?);
+        }
+    }
+    let mark = |sha: &str| match (opts.left_right, left.contains(sha)) {
+        (false, _) => "",
+        (true, true) => "<",
+        (true, false) => ">",
+    };
+
+    if opts.count {
+        if opts.left_right {
+            let lefts = walked.iter().filter(|e| left.contains(&e.sha)).count();
+            println!("{}\t{}", lefts, walked.len() - lefts);
+        } else {
+            println!("{}", walked.len());
+        }
+        return Ok(());
+    }
+
+    for entry in &walked {
+        println!("{}{}", mark(&entry.sha), entry.sha);
+    }
+
+    if opts.objects {
+        for (sha, name) in &specs.tags {
+            println!("{sha} {name}");
+        }
+        let commits: Vec<&GitCommit> = walked.iter().map(|e| &e.commit).collect();
+        list_objects(&repo, &commits, &specs.exclude)?;
+    }
+
+    Ok(())
+}
+
+/// Print every tree and blob reachable from `commits` as `<sha> <path>`,
+/// leaving out what the excluded commits bordering the walk already have.
+fn list_objects(repo: &GitRepository, commits: &[&GitCommit], exclude: &[String]) -> Result<()> {
+    let mut seen = HashSet::new();
+
+    if !exclude.is_empty() {
+        let mut uninteresting = HashSet::new();
+        for sha in exclude {
+            uninteresting.extend(ancestors(repo, sha)?);
+        }
+
+        let mut edges: Vec<String> = exclude.to_vec();
+        for commit in commits {
+            edges.extend(
+                commit
+                    .parents()
+                    .into_iter()
+                    .filter(|p| uninteresting.contains(p)),
+            );
+        }
+        for sha in edges {
+            let tree = commit_read(repo, &sha)?.tree()?;
+            tree_walk(repo, &tree, "", &mut seen, &mut |_, _| {})?;
+        }
+    }
+
+    for commit in commits {
+        tree_walk(repo, &commit.tree()?, "", &mut seen, &mut |sha, path| {
+            println!("{sha} {path}");
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Visit `tree` and everything below it not yet in `seen`, parents first.
+/// Submodule commits are skipped.
+fn tree_walk(
+    repo: &GitRepository,
+    tree: &str,
+    path: &str,
+    seen: &mut HashSet<String>,
+    visit: &mut dyn FnMut(&str, &str),
+) -> Result<()> {
+    if !seen.insert(tree.to_string()) {
+        return Ok(());
+    }
+    visit(tree, path);
+
+    let (_, obj) = object_read(repo, tree)?;
+    let entries = obj
+        .as_any()
+        .downcast_ref::<GitTree>()
+        .with_context(|| format!("Object {tree} is not a tree"))?
+        .entries
+        .clone();
+
+    for entry in entries {
+        let sha = hex::encode(entry.sha);
+        let entry_path = if path.is_empty() {
+            entry.path.clone()
+        } else {
+            format!("{path}/{}", entry.path)
+        };
+
+        if entry.is_tree() {
+            tree_walk(repo, &sha, &entry_path, seen, visit)?;
+        } else if entry.mode_bits() & 0o170000 != 0o160000 && seen.insert(sha.clone()) {
+            visit(&sha, &entry_path);
+        }
+    }
+
+    Ok(())
+}
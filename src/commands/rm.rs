@@ -1,4 +1,7 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result, bail};
 
@@ -14,7 +17,7 @@ pub fn run(paths: &[PathBuf]) -> Result<()> {
 }
 
 pub fn rm(repo: &GitRepository, paths: &[PathBuf], delete: bool, skip_missing: bool) -> Result<()> {
-    let mut index = read_index(&repo)?;
+    let mut index = read_index(repo)?;
 
     let worktree = repo.worktree.canonicalize()?;
 
@@ -32,18 +35,22 @@ pub fn rm(repo: &GitRepository, paths: &[PathBuf], delete: bool, skip_missing: b
     }
     let mut kept_entries = Vec::new();
     let mut remove_files = Vec::new();
+    let mut unmerged = Vec::new();
 
     for e in &index.entries {
         let entry_path = PathBuf::from(&e.path);
         if relpaths.contains(&entry_path) {
             remove_files.push(worktree.join(&entry_path));
+            if e.stage() != 0 {
+                unmerged.push(e.clone());
+            }
         } else {
             kept_entries.push(e.clone());
         }
     }
 
     for relpath in &relpaths {
-        let found_in_index = index.entries.iter().any(|e| PathBuf::from(&e.path) == *relpath);
+        let found_in_index = index.entries.iter().any(|e| relpath == Path::new(&e.path));
         if !found_in_index && !skip_missing {
             bail!("Cannot remove paths not in the index: {:?}", relpath);
         }
@@ -57,6 +64,11 @@ pub fn rm(repo: &GitRepository, paths: &[PathBuf], delete: bool, skip_missing: b
         }
     }
 
+    // Resolving a conflict (`add` goes through here too) keeps its stages
+    // for resolve-undo
+    for e in &unmerged {
+        index.resolve_undo_record(e);
+    }
     index.entries = kept_entries;
     write_index(repo, &index)?;
 
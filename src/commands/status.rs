@@ -1,81 +1,212 @@
-use std::{
-    collections::HashSet,
-    fs,
-    path::{Path, PathBuf},
-};
+use std::fs;
 
 use anyhow::{Context, Result};
+use clap::ValueEnum;
 
 use crate::git::{
-    ignore::{check_ignore, gitignore_read},
     index::read_index,
+    objects::object_resolve,
     repo::{GitRepository, repo_find},
+    status::{RepoStatus, StatusEntry, UnmergedEntry, status_compute},
 };
 
-pub fn run() -> Result<()> {
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum PorcelainVersion {
+    v1,
+    v2,
+}
+
+pub fn run(porcelain: Option<PorcelainVersion>, show_ignored: bool) -> Result<()> {
     let repo = repo_find(".", true)?.unwrap();
     let index = read_index(&repo)?;
 
-    match branch_get_active(&repo)? {
+    let status = status_compute(&repo, &index)?;
+
+    match porcelain {
+        Some(PorcelainVersion::v1) => print_porcelain_v1(&status, show_ignored),
+        Some(PorcelainVersion::v2) => print_porcelain_v2(&status, show_ignored),
+        None => print_long(&repo, &status, show_ignored)?,
+    }
+
+    Ok(())
+}
+
+fn print_long(repo: &GitRepository, status: &RepoStatus, show_ignored: bool) -> Result<()> {
+    match branch_get_active(repo)? {
         Some(branch) => println!("On branch {branch}"),
         None => {
-            let sha = head_resolve(&repo)?;
-            println!("HEAD detached at {sha}");
+            let sha = head_resolve(repo)?;
+            println!("HEAD detached at {}", &sha[..7.min(sha.len())]);
         }
     }
-
-    let rules = gitignore_read(&repo)?;
-
-    let mut tracked: HashSet<PathBuf> = HashSet::new();
-    for entry in &index.entries {
-        tracked.insert(PathBuf::from(&entry.path));
+    if object_resolve(repo, "HEAD").is_err() {
+        println!("\nNo commits yet");
     }
 
-    if !tracked.is_empty() {
-        println!("\nTracked files:");
-        for path in &tracked {
-            println!("  {}", path.display());
+    let staged: Vec<_> = status.entries.iter().filter(|e| e.staged.is_some()).collect();
+    if !staged.is_empty() {
+        println!("\nChanges to be committed:");
+        for e in &staged {
+            println!("\t{:<12}{}", e.staged.unwrap().label(), e.path);
         }
     }
 
-    let mut untracked = Vec::new();
-    for path in worktree_files(&repo.worktree)? {
-        let rel = path.strip_prefix(&repo.worktree).unwrap();
-
-        if tracked.contains(rel) {
-            continue;
+    if !status.unmerged.is_empty() {
+        println!("\nUnmerged paths:");
+        for e in &status.unmerged {
+            println!("\t{:<17}{}", unmerged_label(e), e.path);
         }
-        if check_ignore(&rules, &rel.to_string_lossy())? {
-            continue;
+    }
+
+    let unstaged: Vec<_> = status.entries.iter().filter(|e| e.unstaged.is_some()).collect();
+    if !unstaged.is_empty() {
+        println!("\nChanges not staged for commit:");
+        for e in &unstaged {
+            println!("\t{:<12}{}", e.unstaged.unwrap().label(), e.path);
         }
-        untracked.push(rel.to_path_buf());
     }
 
-    if !untracked.is_empty() {
+    if !status.untracked.is_empty() {
         println!("\nUntracked files:");
-        for path in &untracked {
-            println!("  {}", path.display());
+        for path in &status.untracked {
+            println!("\t{path}");
         }
     }
 
-    let mut ignored = Vec::new();
-    for path in worktree_files(&repo.worktree)? {
-        let rel = path.strip_prefix(&repo.worktree).unwrap();
-        if check_ignore(&rules, &rel.to_string_lossy())? {
-            ignored.push(rel.to_path_buf());
+    if show_ignored && !status.ignored.is_empty() {
+        println!("\nIgnored files:");
+        for path in &status.ignored {
+            println!("\t{path}");
         }
     }
 
-    if !ignored.is_empty() {
-        println!("\nIgnored files:");
-        for path in &ignored {
-            println!("  {}", path.display());
+    if staged.is_empty() && status.unmerged.is_empty() {
+        println!();
+        if !unstaged.is_empty() {
+            println!("no changes added to commit");
+        } else if !status.untracked.is_empty() {
+            println!("nothing added to commit but untracked files present");
+        } else {
+            println!("nothing to commit, working tree clean");
         }
     }
 
     Ok(())
 }
 
+/// Two-letter code for a conflict, e.g. "UU" (both modified) or "DU".
+fn unmerged_code(entry: &UnmergedEntry) -> &'static str {
+    match (&entry.stages[1], &entry.stages[2]) {
+        (Some(_), Some(_)) if entry.stages[0].is_none() => "AA",
+        (Some(_), Some(_)) => "UU",
+        (Some(_), None) if entry.stages[0].is_none() => "AU",
+        (Some(_), None) => "UD",
+        (None, Some(_)) if entry.stages[0].is_none() => "UA",
+        (None, Some(_)) => "DU",
+        (None, None) => "DD",
+    }
+}
+
+fn unmerged_label(entry: &UnmergedEntry) -> &'static str {
+    match unmerged_code(entry) {
+        "AA" => "both added:",
+        "UU" => "both modified:",
+        "AU" => "added by us:",
+        "UD" => "deleted by them:",
+        "UA" => "added by them:",
+        "DU" => "deleted by us:",
+        _ => "both deleted:",
+    }
+}
+
+fn xy(entry: &StatusEntry, clean: char) -> String {
+    let x = entry.staged.map(|k| k.code()).unwrap_or(clean);
+    let y = entry.unstaged.map(|k| k.code()).unwrap_or(clean);
+    format!("{x}{y}")
+}
+
+fn print_porcelain_v1(status: &RepoStatus, show_ignored: bool) {
+    let mut lines: Vec<(&str, String)> = status
+        .entries
+        .iter()
+        .map(|e| (e.path.as_str(), xy(e, ' ')))
+        .chain(
+            status
+                .unmerged
+                .iter()
+                .map(|e| (e.path.as_str(), unmerged_code(e).to_string())),
+        )
+        .collect();
+    lines.sort();
+    for (path, code) in lines {
+        println!("{code} {path}");
+    }
+    for path in &status.untracked {
+        println!("?? {path}");
+    }
+    if show_ignored {
+        for path in &status.ignored {
+            println!("!! {path}");
+        }
+    }
+}
+
+fn print_porcelain_v2(status: &RepoStatus, show_ignored: bool) {
+    for e in &status.entries {
+        let (m_head, h_head) = e
+            .head
+            .as_ref()
+            .map(|(m, s)| (*m, s.as_str()))
+            .unwrap_or((0, NULL_SHA));
+        let (m_index, h_index) = e
+            .index
+            .as_ref()
+            .map(|(m, s)| (*m, s.as_str()))
+            .unwrap_or((0, NULL_SHA));
+        let m_worktree = e.worktree_mode.unwrap_or(0);
+
+        println!(
+            "1 {} N... {:06o} {:06o} {:06o} {} {} {}",
+            xy(e, '.'),
+            m_head,
+            m_index,
+            m_worktree,
+            h_head,
+            h_index,
+            e.path
+        );
+    }
+    for e in &status.unmerged {
+        let (modes, shas): (Vec<String>, Vec<&str>) = e
+            .stages
+            .iter()
+            .map(|s| match s {
+                Some((m, sha)) => (format!("{m:06o}"), sha.as_str()),
+                None => ("000000".to_string(), NULL_SHA),
+            })
+            .unzip();
+        println!(
+            "u {} N... {} {:06o} {} {}",
+            unmerged_code(e),
+            modes.join(" "),
+            e.worktree_mode.unwrap_or(0),
+            shas.join(" "),
+            e.path
+        );
+    }
+    for path in &status.untracked {
+        println!("? {path}");
+    }
+    if show_ignored {
+        for path in &status.ignored {
+            println!("! {path}");
+        }
+    }
+}
+
 pub fn head_resolve(repo: &GitRepository) -> Result<String> {
     let head_path = repo.gitdir.join("HEAD");
     let data = fs::read_to_string(&head_path)
@@ -85,37 +216,12 @@ pub fn head_resolve(repo: &GitRepository) -> Result<String> {
 
 pub fn branch_get_active(repo: &GitRepository) -> Result<Option<String>> {
     let head = head_resolve(repo)?;
-    if head.starts_with("ref: ") {
-        let target = head[5..].to_string(); // strip "ref: "
-        if target.starts_with("refs/heads/") {
-            return Ok(Some(target["refs/heads/".len()..].to_string()));
+    if let Some(target) = head.strip_prefix("ref: ") {
+        if let Some(branch) = target.strip_prefix("refs/heads/") {
+            return Ok(Some(branch.to_string()));
         } else {
-            return Ok(Some(target));
+            return Ok(Some(target.to_string()));
         }
     }
     Ok(None)
 }
-
-fn worktree_files(root: &Path) -> Result<Vec<PathBuf>> {
-    let mut files = Vec::new();
-    collect_files(root, root, &mut files)?;
-    Ok(files)
-}
-
-fn collect_files(base: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_dir() {
-            // Skip .git directory
-            if path.ends_with(".git") {
-                continue;
-            }
-            collect_files(base, &path, files)?;
-        } else {
-            files.push(path);
-        }
-    }
-    Ok(())
-}
@@ -0,0 +1,23 @@
+use anyhow::{Result, bail};
+
+use crate::{
+    commands::checkout::{switch_branch, switch_create, switch_detach},
+    git::{refs::resolve_ref, repo::repo_find},
+};
+
+pub fn run(target: Option<&str>, create: Option<&str>, detach: bool) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    match (create, target) {
+        (Some(branch), start) => switch_create(&repo, branch, start.unwrap_or("HEAD"), "switch"),
+        (None, Some(rev)) if detach => switch_detach(&repo, rev, "switch"),
+        (None, Some(branch)) => {
+            if resolve_ref(&repo, &format!("refs/heads/{branch}")).is_ok() {
+                switch_branch(&repo, branch, "switch")
+            } else {
+                bail!("invalid reference: {branch}\nhint: use --detach to switch to a commit");
+            }
+        }
+        (None, None) => bail!("missing branch or commit argument"),
+    }
+}
@@ -1,28 +1,17 @@
-use std::fs;
-
 use anyhow::Result;
 
 use crate::git::{
-    objects::{GitObject, GitObjectType, GitTag, object_find, object_write},
-    refs::ref_create,
+    ident::{IdentRole, ident_default},
+    objects::{GitObject, GitObjectType, GitTag, object_find, object_read, object_write},
+    refs::{collect_refs, ref_create},
     repo::repo_find,
 };
 
 pub fn list_tags() -> Result<()> {
     let repo = repo_find(".", true)?.unwrap();
 
-    let tags_dir = repo.gitdir.join("refs").join("tags");
-    if !tags_dir.exists() {
-        return Ok(());
-    }
-
-    for entry in fs::read_dir(&tags_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.is_file() {
-            println!("{}", entry.file_name().to_string_lossy());
-        }
+    for (_, refname) in collect_refs(&repo, "refs/tags")? {
+        println!("{}", &refname["refs/tags/".len()..]);
     }
 
     Ok(())
@@ -31,9 +20,10 @@ pub fn list_tags() -> Result<()> {
 pub fn create_tag(name: &str, target: &str, create_tag_object: bool) -> Result<()> {
     let repo = repo_find(".", true)?.unwrap();
 
-    let sha = object_find(&repo, target, Some(GitObjectType::tag))?;
+    let sha = object_find(&repo, target, None)?;
 
     if create_tag_object {
+        let (target_type, _) = object_read(&repo, &sha)?;
         let mut tag = GitTag::init()?;
 
         tag.kvlm
@@ -41,23 +31,21 @@ pub fn create_tag(name: &str, target: &str, create_tag_object: bool) -> Result<(
             .push((b"object".to_vec(), sha.as_bytes().to_vec()));
         tag.kvlm
             .headers
-            .push((b"type".to_vec(), b"commit".to_vec()));
+            .push((b"type".to_vec(), format!("{target_type:?}").into_bytes()));
         tag.kvlm
             .headers
             .push((b"tag".to_vec(), name.as_bytes().to_vec()));
+        let tagger = ident_default(&repo, IdentRole::Committer)?;
         tag.kvlm
             .headers
-            .push((b"tagger".to_vec(), b"Wyag <wyag@example.com>".to_vec()));
-        tag.kvlm.headers.push((
-            Vec::new(), // None key in Python = message
-            b"A tag generated by rust-git\n".to_vec(),
-        ));
+            .push((b"tagger".to_vec(), tagger.to_string().into_bytes()));
+        tag.kvlm.message = b"A tag generated by rust-git\n".to_vec();
 
         let tag_sha = object_write(&repo, &tag, &GitObjectType::tag, true)?;
 
-        ref_create(&repo, &format!("tags/{}", name), &tag_sha)?;
+        ref_create(&repo, &format!("refs/tags/{}", name), &tag_sha, "tag")?;
     } else {
-        ref_create(&repo, &format!("tags/{}", name), &sha)?;
+        ref_create(&repo, &format!("refs/tags/{}", name), &sha, "tag")?;
     }
 
     Ok(())
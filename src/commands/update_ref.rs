@@ -0,0 +1,129 @@
+use std::io::{self, BufRead};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::git::{
+    objects::object_resolve,
+    reflog::NULL_SHA,
+    refs::{RefTransaction, RefUpdate},
+    repo::{GitRepository, repo_find},
+};
+
+/// Point `refname` at `new`, or delete it with `delete`, but only if it is
+/// still at `old` when given. `values` holds `<new> [<old>]`, or just
+/// `[<old>]` when deleting; a zero `<new>` deletes the ref too.
+pub fn run(refname: &str, values: &[String], delete: bool, message: &str) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let (new, old) = match (delete, values) {
+        (true, old) if old.len() <= 1 => (NULL_SHA.to_string(), old.first()),
+        (false, [new, old @ ..]) if old.len() <= 1 => (value_parse(&repo, new)?, old.first()),
+        _ => bail!("usage: update-ref [-m <reason>] (-d <ref> [<old>] | <ref> <new> [<old>])"),
+    };
+
+    let mut transaction = RefTransaction::new();
+    transaction.update(RefUpdate {
+        refname: refname.to_string(),
+        new: Some(new),
+        old: old.map(|v| value_parse(&repo, v)).transpose()?,
+        message: message.to_string(),
+    })?;
+    transaction.commit(&repo)
+}
+
+/// Read update commands from stdin, one per line:
+///
+/// ```text
+/// update <ref> <new> [<old>]
+/// create <ref> <new>
+/// delete <ref> [<old>]
+/// verify <ref> [<old>]
+/// start | prepare | commit | abort
+/// ```
+///
+/// Without `start` everything read is committed as one transaction at the end
+/// of input; a transaction opened with `start` is aborted unless committed.
+pub fn run_stdin(message: &str) -> Result<()> {
+    let repo = repo_find(".", true)?.unwrap();
+
+    let mut transaction = RefTransaction::new();
+    let mut started = false;
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let mut words = line.split(' ');
+        let command = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+
+        let update = |refname: &str, new: Option<&str>, old: Option<&str>| -> Result<RefUpdate> {
+            let parse = |value: &str, what: &str| {
+                value_parse(&repo, value)
+                    .map_err(|_| anyhow!("{command} {refname}: invalid <{what}>: {value}"))
+            };
+            Ok(RefUpdate {
+                refname: refname.to_string(),
+                new: new.map(|v| parse(v, "new-oid")).transpose()?,
+                old: old.map(|v| parse(v, "old-oid")).transpose()?,
+                message: message.to_string(),
+            })
+        };
+
+        match (command, args.as_slice()) {
+            ("update", [refname, new]) => transaction.update(update(refname, Some(new), None)?)?,
+            ("update", [refname, new, old]) => {
+                transaction.update(update(refname, Some(new), Some(old))?)?
+            }
+            ("create", [refname, new]) => {
+                let update = update(refname, Some(new), Some(NULL_SHA))?;
+                if update.new.as_deref() == Some(NULL_SHA) {
+                    bail!("create {refname}: zero <new-oid>");
+                }
+                transaction.update(update)?
+            }
+            ("delete", [refname]) => transaction.update(update(refname, Some(NULL_SHA), None)?)?,
+            ("delete", [refname, old]) => {
+                transaction.update(update(refname, Some(NULL_SHA), Some(old))?)?
+            }
+            ("verify", [refname]) => transaction.update(update(refname, None, Some(NULL_SHA))?)?,
+            ("verify", [refname, old]) => transaction.update(update(refname, None, Some(old))?)?,
+            ("start", []) => {
+                started = true;
+                println!("start: ok");
+            }
+            ("prepare", []) => {
+                transaction.prepare(&repo)?;
+                println!("prepare: ok");
+            }
+            ("commit", []) => {
+                std::mem::take(&mut transaction).commit(&repo)?;
+                started = false;
+                println!("commit: ok");
+            }
+            ("abort", []) => {
+                transaction = RefTransaction::new();
+                started = false;
+                println!("abort: ok");
+            }
+            ("update" | "create" | "delete" | "verify", _) => {
+                bail!("{command}: wrong number of arguments")
+            }
+            ("start" | "prepare" | "commit" | "abort", _) => bail!("{command}: extra input"),
+            _ => bail!("unknown command: {line}"),
+        }
+    }
+
+    if started {
+        // Dropping the transaction releases its locks
+        return Ok(());
+    }
+    transaction.commit(&repo)
+}
+
+/// An object name given for a ref: empty or all zeroes means "no ref",
+/// anything else is resolved to a SHA.
+fn value_parse(repo: &GitRepository, value: &str) -> Result<String> {
+    if value.is_empty() || (value.len() == 40 && value.bytes().all(|b| b == b'0')) {
+        return Ok(NULL_SHA.to_string());
+    }
+    object_resolve(repo, value).map_err(|_| anyhow!("{value}: not a valid SHA1"))
+}
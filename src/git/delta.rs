@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, bail};
+
+/// Read a little-endian base-128 size from the start of a delta.
+fn delta_size(data: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+
+    loop {
+        if shift >= usize::BITS {
+            bail!("Delta: size does not fit in {} bits", usize::BITS);
+        }
+        let byte = *data.get(*pos).ok_or_else(|| anyhow::anyhow!("Delta: truncated size"))?;
+        *pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Apply a git delta (copy/insert instruction stream) to `base`.
+pub fn delta_apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0usize;
+
+    let src_size = delta_size(delta, &mut pos)?;
+    if src_size != base.len() {
+        bail!(
+            "Delta: base size mismatch (expected {src_size}, got {})",
+            base.len()
+        );
+    }
+    let dst_size = delta_size(delta, &mut pos)?;
+
+    let mut out = Vec::with_capacity(dst_size);
+
+    while pos < delta.len() {
+        let cmd = delta[pos];
+        pos += 1;
+
+        if cmd & 0x80 != 0 {
+            // Copy from base: bits 0-3 select offset bytes, bits 4-6 size bytes
+            let mut offset = 0usize;
+            let mut size = 0usize;
+
+            for i in 0..4 {
+                if cmd & (1 << i) != 0 {
+                    let byte = *delta.get(pos).ok_or_else(|| anyhow::anyhow!("Delta: truncated copy"))?;
+                    offset |= (byte as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if cmd & (0x10 << i) != 0 {
+                    let byte = *delta.get(pos).ok_or_else(|| anyhow::anyhow!("Delta: truncated copy"))?;
+                    size |= (byte as usize) << (8 * i);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            let end = offset
+                .checked_add(size)
+                .filter(|&end| end <= base.len())
+                .ok_or_else(|| anyhow::anyhow!("Delta: copy out of range"))?;
+            out.extend_from_slice(&base[offset..end]);
+        } else if cmd != 0 {
+            // Insert the next `cmd` bytes literally
+            let end = pos + cmd as usize;
+            if end > delta.len() {
+                bail!("Delta: truncated insert");
+            }
+            out.extend_from_slice(&delta[pos..end]);
+            pos = end;
+        } else {
+            bail!("Delta: unexpected opcode 0");
+        }
+    }
+
+    if out.len() != dst_size {
+        bail!(
+            "Delta: result size mismatch (expected {dst_size}, got {})",
+            out.len()
+        );
+    }
+
+    Ok(out)
+}
+
+/// Block size used to index the base when searching for copy candidates.
+const DELTA_BLOCK: usize = 16;
+/// Largest copy emitted by a single instruction (encoded as a zero size).
+const DELTA_MAX_COPY: usize = 0x10000;
+/// Largest literal run emitted by a single insert instruction.
+const DELTA_MAX_INSERT: usize = 0x7f;
+
+fn delta_size_write(out: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn delta_insert_flush(out: &mut Vec<u8>, pending: &mut Vec<u8>) {
+    for chunk in pending.chunks(DELTA_MAX_INSERT) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+    pending.clear();
+}
+
+fn delta_copy_write(out: &mut Vec<u8>, offset: usize, size: usize) {
+    let cmd_pos = out.len();
+    let mut cmd = 0x80u8;
+    out.push(0);
+
+    for i in 0..4 {
+        let byte = ((offset >> (8 * i)) & 0xff) as u8;
+        if byte != 0 {
+            cmd |= 1 << i;
+            out.push(byte);
+        }
+    }
+    // A size of 0x10000 is encoded by omitting every size byte
+    if size != DELTA_MAX_COPY {
+        for i in 0..3 {
+            let byte = ((size >> (8 * i)) & 0xff) as u8;
+            if byte != 0 {
+                cmd |= 0x10 << i;
+                out.push(byte);
+            }
+        }
+    }
+
+    out[cmd_pos] = cmd;
+}
+
+/// Encode `target` as a git delta (copy/insert instructions) against `base`.
+pub fn delta_create(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    delta_size_write(&mut out, base.len());
+    delta_size_write(&mut out, target.len());
+
+    // Index every aligned block of the base; the first occurrence wins
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for start in (0..base.len().saturating_sub(DELTA_BLOCK - 1)).step_by(DELTA_BLOCK) {
+        blocks.entry(&base[start..start + DELTA_BLOCK]).or_insert(start);
+    }
+
+    let mut pending = Vec::new();
+    let mut pos = 0usize;
+
+    while pos < target.len() {
+        let found = target
+            .get(pos..pos + DELTA_BLOCK)
+            .and_then(|block| blocks.get(block).copied());
+
+        let Some(base_pos) = found else {
+            pending.push(target[pos]);
+            pos += 1;
+            continue;
+        };
+
+        let mut len = DELTA_BLOCK;
+        while base_pos + len < base.len()
+            && pos + len < target.len()
+            && base[base_pos + len] == target[pos + len]
+        {
+            len += 1;
+        }
+
+        delta_insert_flush(&mut out, &mut pending);
+
+        let mut copied = 0usize;
+        while copied < len {
+            let size = (len - copied).min(DELTA_MAX_COPY);
+            delta_copy_write(&mut out, base_pos + copied, size);
+            copied += size;
+        }
+        pos += len;
+    }
+
+    delta_insert_flush(&mut out, &mut pending);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(base: &[u8], target: &[u8]) {
+        let delta = delta_create(base, target);
+        assert_eq!(delta_apply(base, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = b"The quick brown fox jumps over the lazy dog.\n".repeat(50);
+        round_trip(b"", b"");
+        round_trip(b"", b"all inserted");
+        round_trip(&text, b"");
+        round_trip(&text, &text);
+        round_trip(&text, &[b"prefix\n", &text[..], b"suffix\n"].concat());
+        round_trip(&text, &[&text[..700], b"changed", &text[900..]].concat());
+        round_trip(&text[..300], &[&text[..300], &text[..300]].concat());
+    }
+
+    #[test]
+    fn round_trips_large_copies_and_offsets() {
+        // Copies longer than 0x10000 and offsets with zero bytes in them
+        let base: Vec<u8> = (0..0x30000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let target = [&base[0x10000..], b"x", &base[..0x10000]].concat();
+        let delta = delta_create(&base, &target);
+        assert!(delta.len() < 100);
+        assert_eq!(delta_apply(&base, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn apply_checks_base_size() {
+        let delta = delta_create(b"0123456789abcdef", b"0123456789abcdef!");
+        assert!(delta_apply(b"short", &delta).is_err());
+    }
+
+    #[test]
+    fn apply_rejects_overlong_size() {
+        let mut delta = vec![0xff; 12];
+        delta.extend_from_slice(&[0x01, 0]);
+        assert!(delta_apply(b"", &delta).is_err());
+    }
+
+    #[test]
+    fn apply_hand_written_delta() {
+        // base 10, result 7: copy 4 bytes at offset 2, insert "xyz"
+        let delta = [10, 7, 0x91, 2, 4, 3, b'x', b'y', b'z'];
+        assert_eq!(delta_apply(b"0123456789", &delta).unwrap(), b"2345xyz");
+    }
+}
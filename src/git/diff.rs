@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// One step of an edit script. `a` indexes the old sequence (Equal/Delete),
+/// `b` the new one (Equal/Insert); for Delete `b` is the insertion point in
+/// the new sequence and for Insert `a` the one in the old sequence.
+#[derive(Debug, Clone, Copy)]
+pub struct Edit {
+    pub op: DiffOp,
+    pub a: usize,
+    pub b: usize,
+}
+
+/// Split `data` into lines, each keeping its trailing '\n' (if any).
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Heuristic used by git: a NUL byte in the first 8000 bytes means binary.
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(8000)].contains(&0)
+}
+
+/// Shortest edit script between `a` and `b` (Myers' O((N+M)D) algorithm).
+pub fn myers_diff<T: Eq + std::hash::Hash>(a: &[T], b: &[T]) -> Vec<Edit> {
+    // Compare small integer ids instead of whole elements
+    let mut ids: HashMap<&T, usize> = HashMap::new();
+    let mut a_ids = Vec::with_capacity(a.len());
+    for x in a {
+        let next = ids.len();
+        a_ids.push(*ids.entry(x).or_insert(next));
+    }
+    let mut b_ids = Vec::with_capacity(b.len());
+    for x in b {
+        let next = ids.len();
+        b_ids.push(*ids.entry(x).or_insert(next));
+    }
+
+    // Common prefix and suffix never need the full search
+    let prefix = a_ids.iter().zip(&b_ids).take_while(|(x, y)| x == y).count();
+    let suffix = a_ids[prefix..]
+        .iter()
+        .rev()
+        .zip(b_ids[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    for i in 0..prefix {
+        edits.push(Edit { op: DiffOp::Equal, a: i, b: i });
+    }
+
+    let a_mid = &a_ids[prefix..a.len() - suffix];
+    let b_mid = &b_ids[prefix..b.len() - suffix];
+    for e in myers_core(a_mid, b_mid) {
+        edits.push(Edit {
+            op: e.op,
+            a: e.a + prefix,
+            b: e.b + prefix,
+        });
+    }
+
+    for i in 0..suffix {
+        edits.push(Edit {
+            op: DiffOp::Equal,
+            a: a.len() - suffix + i,
+            b: b.len() - suffix + i,
+        });
+    }
+
+    edits
+}
+
+fn myers_core(a: &[usize], b: &[usize]) -> Vec<Edit> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = n + m;
+    let off = max;
+
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    // trace[d] holds v (for k in -d..=d) as it was before step d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v[(off - d) as usize..=(off + d) as usize].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let i = (k + off) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for d in (0..trace.len() as isize).rev() {
+        let k = x - y;
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            let snap = &trace[d as usize];
+            let get = |k: isize| snap[(k + d) as usize];
+            let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+                k + 1
+            } else {
+                k - 1
+            };
+            let prev_x = get(prev_k);
+            (prev_x, prev_x - prev_k)
+        };
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit {
+                op: DiffOp::Equal,
+                a: x as usize,
+                b: y as usize,
+            });
+        }
+
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit {
+                    op: DiffOp::Insert,
+                    a: x as usize,
+                    b: prev_y as usize,
+                });
+            } else {
+                edits.push(Edit {
+                    op: DiffOp::Delete,
+                    a: prev_x as usize,
+                    b: y as usize,
+                });
+            }
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    // An empty range is reported at the line before it
+    let start = if count == 0 { start } else { start + 1 };
+    if count == 1 {
+        format!("{start}")
+    } else {
+        format!("{start},{count}")
+    }
+}
+
+/// Git's default "function name" for a hunk header: the closest line above
+/// the hunk that starts with a letter, '_' or '$'.
+fn hunk_function<'a>(lines: &[&'a [u8]], before: usize) -> Option<&'a [u8]> {
+    let line = lines[..before.min(lines.len())]
+        .iter()
+        .rev()
+        .find(|l| matches!(l.first(), Some(c) if c.is_ascii_alphabetic() || *c == b'_' || *c == b'$'))?;
+
+    let end = line
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    Some(&line[..end.min(80)])
+}
+
+fn push_line(out: &mut Vec<u8>, marker: u8, line: &[u8]) {
+    out.push(marker);
+    out.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        out.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
+
+/// Unified-diff hunks (`@@ -a,b +c,d @@` plus lines) turning `old` into `new`.
+pub fn unified_diff(old: &[u8], new: &[u8], context: usize) -> Vec<u8> {
+    let a = split_lines(old);
+    let b = split_lines(new);
+    let edits = myers_diff(&a, &b);
+
+    let mut out = Vec::new();
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.op != DiffOp::Equal)
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut i = 0usize;
+    while i < changes.len() {
+        // Extend the hunk while the next change is within 2*context lines
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= 2 * context + 1 {
+            j += 1;
+        }
+
+        let start = changes[i].saturating_sub(context);
+        let end = (changes[j] + context + 1).min(edits.len());
+        let hunk = &edits[start..end];
+
+        let old_start = hunk[0].a;
+        let new_start = hunk[0].b;
+        let old_count = hunk.iter().filter(|e| e.op != DiffOp::Insert).count();
+        let new_count = hunk.iter().filter(|e| e.op != DiffOp::Delete).count();
+
+        out.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@",
+                hunk_range(old_start, old_count),
+                hunk_range(new_start, new_count)
+            )
+            .as_bytes(),
+        );
+        if let Some(func) = hunk_function(&a, old_start) {
+            out.push(b' ');
+            out.extend_from_slice(func);
+        }
+        out.push(b'\n');
+
+        for e in hunk {
+            match e.op {
+                DiffOp::Equal => push_line(&mut out, b' ', a[e.a]),
+                DiffOp::Delete => push_line(&mut out, b'-', a[e.a]),
+                DiffOp::Insert => push_line(&mut out, b'+', b[e.b]),
+            }
+        }
+
+        i = j + 1;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that `edits` turns `a` into `b`, returning the number of changes.
+    fn check_script(a: &[u8], b: &[u8], edits: &[Edit]) -> usize {
+        let old: Vec<u8> = edits
+            .iter()
+            .filter(|e| e.op != DiffOp::Insert)
+            .map(|e| a[e.a])
+            .collect();
+        let new: Vec<u8> = edits
+            .iter()
+            .filter(|e| e.op != DiffOp::Delete)
+            .map(|e| b[e.b])
+            .collect();
+        assert_eq!(old, a);
+        assert_eq!(new, b);
+        for e in edits.iter().filter(|e| e.op == DiffOp::Equal) {
+            assert_eq!(a[e.a], b[e.b]);
+        }
+        edits.iter().filter(|e| e.op != DiffOp::Equal).count()
+    }
+
+    #[test]
+    fn myers_finds_shortest_script() {
+        // The example from Myers' paper: D = 5
+        let (a, b) = (b"ABCABBA", b"CBABAC");
+        assert_eq!(check_script(a, b, &myers_diff(a, b)), 5);
+
+        for (a, b, d) in [
+            (&b""[..], &b""[..], 0),
+            (b"", b"abc", 3),
+            (b"abc", b"", 3),
+            (b"abc", b"abc", 0),
+            (b"abcdef", b"abXdef", 2),
+            (b"xaby", b"xbay", 2),
+            (b"abcabc", b"abc", 3),
+        ] {
+            assert_eq!(check_script(a, b, &myers_diff(a, b)), d, "{a:?} -> {b:?}");
+        }
+    }
+
+    #[test]
+    fn myers_insertion_points() {
+        let edits = myers_diff(b"ac", b"abc");
+        let insert = edits.iter().find(|e| e.op == DiffOp::Insert).unwrap();
+        assert_eq!((insert.a, insert.b), (1, 1));
+
+        let edits = myers_diff(b"abc", b"ac");
+        let delete = edits.iter().find(|e| e.op == DiffOp::Delete).unwrap();
+        assert_eq!((delete.a, delete.b), (1, 1));
+    }
+
+    #[test]
+    fn unified_hunks() {
+        let old: String = (1..=20).map(|i| format!("{i}\n")).collect();
+        let new = format!("{}four\n{}21\n", &old[..6], &old[8..]);
+        assert_eq!(
+            String::from_utf8(unified_diff(old.as_bytes(), new.as_bytes(), 3)).unwrap(),
+            "@@ -1,7 +1,7 @@\n 1\n 2\n 3\n-4\n+four\n 5\n 6\n 7\n\
+             @@ -18,3 +18,4 @@\n 18\n 19\n 20\n+21\n"
+        );
+
+        // Changes close enough to share context form one hunk
+        let close = format!("{}four\n{}", &old[..6], &old[8..]).replace("10\n", "ten\n");
+        let diff = String::from_utf8(unified_diff(old.as_bytes(), close.as_bytes(), 3)).unwrap();
+        assert!(diff.starts_with("@@ -1,13 +1,13 @@\n"));
+        assert_eq!(diff.matches("@@ -").count(), 1);
+
+        assert_eq!(
+            String::from_utf8(unified_diff(b"a\n", b"a", 3)).unwrap(),
+            "@@ -1 +1 @@\n-a\n+a\n\\ No newline at end of file\n"
+        );
+        assert!(unified_diff(old.as_bytes(), old.as_bytes(), 3).is_empty());
+    }
+}
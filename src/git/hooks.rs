@@ -0,0 +1,66 @@
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result};
+
+use crate::git::repo::{GitRepository, repo_config_get};
+
+/// Where hooks live: `core.hooksPath` (relative paths are taken from the
+/// worktree root) or `.git/hooks`.
+fn hooks_dir(repo: &GitRepository) -> PathBuf {
+    match repo_config_get(repo, "core", "hooksPath") {
+        Some(path) => repo.worktree.join(path),
+        None => repo.gitdir.join("hooks"),
+    }
+}
+
+/// The hook called `name`, if one is installed and executable.
+pub fn hook_find(repo: &GitRepository, name: &str) -> Option<PathBuf> {
+    let path = hooks_dir(repo).join(name);
+    let meta = fs::metadata(&path).ok()?;
+    (meta.is_file() && meta.permissions().mode() & 0o111 != 0).then_some(path)
+}
+
+/// Run the hook `name` with `args` from the worktree root, feeding it `stdin`
+/// if given. Returns whether it succeeded; a missing hook always does.
+pub fn hook_run(
+    repo: &GitRepository,
+    name: &str,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+) -> Result<bool> {
+    let Some(path) = hook_find(repo, name) else {
+        return Ok(true);
+    };
+
+    let mut child = Command::new(&path)
+        .args(args)
+        .current_dir(&repo.worktree)
+        .env("GIT_DIR", &repo.gitdir)
+        .env("GIT_INDEX_FILE", repo.gitdir.join("index"))
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .spawn()
+        .with_context(|| format!("cannot run {} hook {}", name, path.display()))?;
+
+    if let Some(data) = stdin
+        && let Some(mut pipe) = child.stdin.take()
+    {
+        // A hook doesn't have to read its input
+        if let Err(err) = pipe.write_all(data)
+            && err.kind() != ErrorKind::BrokenPipe
+        {
+            return Err(err.into());
+        }
+    }
+
+    Ok(child.wait()?.success())
+}
@@ -0,0 +1,266 @@
+use std::{env, fmt};
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use crate::git::repo::{GitRepository, repo_config_get};
+
+/// An author/committer/tagger line: `Name <email> <timestamp> <tz>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    /// Offset from UTC as written by git, e.g. "+0200".
+    pub tz: String,
+}
+
+impl Signature {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let text = String::from_utf8_lossy(data);
+        let open = text.find('<')?;
+        let close = open + text[open..].find('>')?;
+
+        let mut rest = text[close + 1..].split_whitespace();
+        let timestamp = rest.next()?.parse().ok()?;
+        let tz = rest.next().unwrap_or("+0000").to_string();
+
+        Some(Self {
+            name: text[..open].trim().to_string(),
+            email: text[open + 1..close].to_string(),
+            timestamp,
+            tz,
+        })
+    }
+
+    /// "Name <email>"
+    pub fn ident(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+
+    /// The offset in seconds east of UTC.
+    pub fn tz_offset(&self) -> i32 {
+        tz_offset_parse(&self.tz).unwrap_or(0)
+    }
+
+    /// The timestamp in its own timezone, as git's default date format
+    /// ("Thu Oct 15 12:00:00 2026 +0200").
+    pub fn date_default(&self) -> String {
+        let offset = FixedOffset::east_opt(self.tz_offset())
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+        match DateTime::from_timestamp(self.timestamp, 0) {
+            Some(utc) => {
+                utc.with_timezone(&offset)
+                    .format("%a %b %-d %H:%M:%S %Y ")
+                    .to_string()
+                    + &self.tz
+            }
+            None => format!("{} {}", self.timestamp, self.tz),
+        }
+    }
+}
+
+impl fmt::Display for Signature {
+    /// The header form, "Name <email> <timestamp> <tz>".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.ident(), self.timestamp, self.tz)
+    }
+}
+
+/// Whose identity is wanted: they differ only in the environment variables
+/// consulted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentRole {
+    Author,
+    Committer,
+}
+
+impl IdentRole {
+    fn env(&self, field: &str) -> Option<String> {
+        let role = match self {
+            IdentRole::Author => "AUTHOR",
+            IdentRole::Committer => "COMMITTER",
+        };
+        env::var(format!("GIT_{role}_{field}"))
+            .ok()
+            .filter(|v| !v.is_empty())
+    }
+}
+
+/// The current user as author or committer: `GIT_<ROLE>_NAME`/`_EMAIL`/`_DATE`
+/// first, then `user.name`/`user.email` from the repository and global
+/// config, dated now in the local timezone.
+pub fn ident_default(repo: &GitRepository, role: IdentRole) -> Result<Signature> {
+    let name = role
+        .env("NAME")
+        .or_else(|| repo_config_get(repo, "user", "name"));
+    let email = role
+        .env("EMAIL")
+        .or_else(|| repo_config_get(repo, "user", "email"))
+        .or_else(|| env::var("EMAIL").ok());
+
+    let (Some(name), Some(email)) = (name, email) else {
+        let who = match role {
+            IdentRole::Author => "Author",
+            IdentRole::Committer => "Committer",
+        };
+        bail!(
+            "{who} identity unknown\n\n*** Please tell me who you are.\n\nSet user.name and user.email in .git/config or your global git config."
+        );
+    };
+
+    let (timestamp, tz) = ident_date(role)?;
+
+    Ok(Signature {
+        name,
+        email,
+        timestamp,
+        tz,
+    })
+}
+
+/// When the change is made: `GIT_<ROLE>_DATE`, or else now.
+pub fn ident_date(role: IdentRole) -> Result<(i64, String)> {
+    match role.env("DATE") {
+        Some(date) => date_parse(&date),
+        None => Ok(date_now()),
+    }
+}
+
+/// Split "Name <email>" as given to `--author`.
+pub fn ident_parse(text: &str) -> Option<(String, String)> {
+    let open = text.find('<')?;
+    let close = open + text[open..].find('>')?;
+    let name = text[..open].trim();
+    if name.is_empty() || !text[close + 1..].trim().is_empty() {
+        return None;
+    }
+    Some((name.to_string(), text[open + 1..close].to_string()))
+}
+
+/// The current time and local UTC offset.
+pub fn date_now() -> (i64, String) {
+    let now = Utc::now().timestamp();
+    (now, tz_local(now))
+}
+
+/// The local UTC offset in effect at `timestamp`, as "+HHMM".
+pub fn tz_local(timestamp: i64) -> String {
+    let offset = Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map_or(0, |t| t.offset().local_minus_utc());
+    tz_format(offset)
+}
+
+/// Format seconds east of UTC as "+HHMM"/"-HHMM".
+pub fn tz_format(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// Parse a date as accepted by `--date` and `GIT_*_DATE` into a timestamp
+/// and the timezone to record: git's internal "<epoch> <tz>", "@<epoch>",
+/// RFC 2822 and ISO 8601, with local time for dates without an offset.
+/// Anything else is read as a relative date ("yesterday", ...).
+pub fn date_parse(text: &str) -> Result<(i64, String)> {
+    let text = text.trim();
+
+    let (raw, marked) = match text.strip_prefix('@') {
+        Some(raw) => (raw, true),
+        None => (text, false),
+    };
+    let mut parts = raw.split_whitespace();
+    if let Some(Ok(epoch)) = parts.next().map(str::parse::<i64>) {
+        match (parts.next(), parts.next()) {
+            (None, _) if marked => return Ok((epoch, tz_local(epoch))),
+            (Some(tz), None) if tz_offset_parse(tz).is_some() => {
+                return Ok((epoch, tz.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    let zoned = DateTime::parse_from_rfc2822(text)
+        .or_else(|_| DateTime::parse_from_rfc3339(text))
+        .or_else(|_| DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S %z"))
+        .or_else(|_| DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%z"));
+    if let Ok(date) = zoned {
+        return Ok((date.timestamp(), tz_format(date.offset().local_minus_utc())));
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        });
+    if let Some(date) = naive
+        && let Some(local) = Local.from_local_datetime(&date).earliest()
+    {
+        return Ok((
+            local.timestamp(),
+            tz_format(local.offset().local_minus_utc()),
+        ));
+    }
+
+    let timestamp = date_relative(text)?;
+    Ok((timestamp, tz_local(timestamp)))
+}
+
+/// "now", "yesterday" and "<n> <unit>s ago" (also dot-separated).
+fn date_relative(text: &str) -> Result<i64> {
+    let now = Utc::now();
+
+    if text == "now" {
+        return Ok(now.timestamp());
+    }
+    if text == "yesterday" {
+        return Ok((now - Duration::days(1)).timestamp());
+    }
+
+    let words: Vec<&str> = text.split(['.', ' ']).filter(|w| !w.is_empty()).collect();
+    if let [count, unit, "ago"] = words.as_slice()
+        && let Ok(count) = count.parse::<i64>()
+    {
+        let unit = unit.trim_end_matches('s');
+        let seconds = match unit {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 3600,
+            "day" => 86400,
+            "week" => 7 * 86400,
+            "month" => 30 * 86400,
+            "year" => 365 * 86400,
+            _ => bail!("invalid date: {text}"),
+        };
+        return Ok(now.timestamp() - count * seconds);
+    }
+
+    bail!("invalid date: {text}")
+}
+
+/// Parse the approximate dates accepted in `@{<date>}`, `--since` and
+/// `--expire` into a timestamp: anything `date_parse` takes, so dates
+/// without an offset are local time.
+pub fn approxidate(text: &str) -> Result<i64> {
+    date_parse(text).map(|(timestamp, _)| timestamp)
+}
+
+/// Parse "+HHMM"/"-HHMM" into seconds east of UTC.
+pub fn tz_offset_parse(tz: &str) -> Option<i32> {
+    let (sign, digits) = match tz.as_bytes().first()? {
+        b'+' => (1, &tz[1..]),
+        b'-' => (-1, &tz[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
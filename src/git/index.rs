@@ -1,14 +1,33 @@
 use anyhow::{Context, Result, bail};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, Cursor, Read};
 
-use crate::git::repo::GitRepository;
+use crate::git::lockfile::LockFile;
+use crate::git::repo::{GitRepository, repo_config_get};
+
+/// `flags`: the entry must not be checked against the worktree.
+pub const FLAG_ASSUME_VALID: u16 = 0x8000;
+/// `flags`: `flags_extended` follows (index v3 and later).
+pub const FLAG_EXTENDED: u16 = 0x4000;
+/// `flags`: the merge stage bits.
+pub const FLAG_STAGE_MASK: u16 = 0x3000;
+/// `flags`: the length of the path, or 0xfff if it is longer.
+pub const FLAG_NAME_MASK: u16 = 0x0fff;
+
+/// `flags_extended`: sparse checkout left the path out of the worktree.
+pub const FLAG_SKIP_WORKTREE: u16 = 0x4000;
+/// `flags_extended`: `add -N` recorded the path without content.
+pub const FLAG_INTENT_TO_ADD: u16 = 0x2000;
 
 #[derive(Debug, Clone)]
 pub struct GitIndexEntry {
     pub ctime: u32,
+    pub ctime_nsec: u32,
     pub mtime: u32,
+    pub mtime_nsec: u32,
     pub dev: u32,
     pub ino: u32,
     pub mode: u32,
@@ -17,25 +36,187 @@ pub struct GitIndexEntry {
     pub size: u32,
     pub sha: String,
     pub flags: u16,
+    /// Extra flags stored by index v3+ (skip-worktree, intent-to-add).
+    pub flags_extended: u16,
     pub path: String,
 }
 
+impl GitIndexEntry {
+    /// Merge stage: 0 for normal entries, 1-3 (base/ours/theirs) during a conflict.
+    pub fn stage(&self) -> u16 {
+        (self.flags & FLAG_STAGE_MASK) >> 12
+    }
+
+    pub fn assume_valid(&self) -> bool {
+        self.flags & FLAG_ASSUME_VALID != 0
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        self.flags_extended & FLAG_SKIP_WORKTREE != 0
+    }
+
+    pub fn intent_to_add(&self) -> bool {
+        self.flags_extended & FLAG_INTENT_TO_ADD != 0
+    }
+
+    /// `flags` as stored: the assume-valid and stage bits kept, the extended
+    /// bit and name length derived from the entry.
+    fn flags_on_disk(&self) -> u16 {
+        let extended = if self.flags_extended != 0 {
+            FLAG_EXTENDED
+        } else {
+            0
+        };
+        let name_len = self.path.len().min(FLAG_NAME_MASK as usize) as u16;
+        (self.flags & (FLAG_ASSUME_VALID | FLAG_STAGE_MASK)) | extended | name_len
+    }
+}
+
+/// A tree object already built from the index entries under one directory
+/// (the TREE extension), so unchanged directories need not be re-hashed.
+#[derive(Debug, Clone)]
+pub struct CacheTree {
+    /// Directory name within the parent; empty for the root.
+    pub name: String,
+    /// Number of index entries under the directory, or -1 once one of them
+    /// has changed and `sha` is stale.
+    pub entry_count: i64,
+    pub sha: Option<String>,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    /// An invalid node to be filled in the next time a tree is built.
+    pub fn new(name: &str) -> Self {
+        CacheTree {
+            name: name.to_string(),
+            entry_count: -1,
+            sha: None,
+            subtrees: Vec::new(),
+        }
+    }
+
+    /// The tree's SHA, if it still matches the entries.
+    pub fn valid_sha(&self) -> Option<&str> {
+        self.sha.as_deref().filter(|_| self.entry_count >= 0)
+    }
+
+    pub fn subtree_mut(&mut self, name: &str) -> &mut CacheTree {
+        match self.subtrees.iter().position(|t| t.name == name) {
+            Some(i) => &mut self.subtrees[i],
+            None => {
+                self.subtrees.push(CacheTree::new(name));
+                self.subtrees.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Mark every directory containing `path` (relative to this one) stale.
+    pub fn invalidate(&mut self, path: &str) {
+        self.entry_count = -1;
+        self.sha = None;
+        if let Some((dir, rest)) = path.split_once('/')
+            && let Some(sub) = self.subtrees.iter_mut().find(|t| t.name == dir)
+        {
+            sub.invalidate(rest);
+        }
+    }
+}
+
+/// The stages a conflict had before it was resolved (the REUC extension),
+/// as (mode, sha) for base, ours and theirs.
+#[derive(Debug, Clone)]
+pub struct ResolveUndo {
+    pub path: String,
+    pub stages: [Option<(u32, String)>; 3],
+}
+
 #[derive(Debug)]
 pub struct GitIndex {
+    /// On-disk format version (2, 3 or 4), kept when the index is rewritten.
+    pub version: u32,
     pub entries: Vec<GitIndexEntry>,
+    pub cache_tree: Option<CacheTree>,
+    pub resolve_undo: Vec<ResolveUndo>,
+    /// Optional extensions this implementation doesn't use, as (signature,
+    /// data), written back unchanged.
+    pub extensions: Vec<([u8; 4], Vec<u8>)>,
+    /// The entries `cache_tree` was last known to match: as read, or as of
+    /// `cache_tree_set`. Directories whose entries differ are marked stale
+    /// when the index is written.
+    cache_tree_entries: Vec<GitIndexEntry>,
+}
+
+impl GitIndex {
+    /// Remember a conflict stage that is being removed from the index, so the
+    /// conflict can be recreated after it has been resolved.
+    pub fn resolve_undo_record(&mut self, entry: &GitIndexEntry) {
+        let stage = entry.stage() as usize;
+        if stage == 0 {
+            return;
+        }
+        let record = match self.resolve_undo.iter().position(|r| r.path == entry.path) {
+            Some(i) => &mut self.resolve_undo[i],
+            None => {
+                self.resolve_undo.push(ResolveUndo {
+                    path: entry.path.clone(),
+                    stages: Default::default(),
+                });
+                self.resolve_undo.last_mut().unwrap()
+            }
+        };
+        record.stages[stage - 1] = Some((entry.mode, entry.sha.clone()));
+    }
+
+    /// Replace the cache tree with one just built from the current entries.
+    pub fn cache_tree_set(&mut self, tree: CacheTree) {
+        self.cache_tree = Some(tree);
+        self.cache_tree_entries = self.entries.clone();
+    }
+}
+
+/// The version a new index is written in: `index.version`, or 4 with
+/// `feature.manyFiles`, or else 2.
+fn index_version_default(repo: &GitRepository) -> u32 {
+    if let Some(version) = repo_config_get(repo, "index", "version").and_then(|v| v.parse().ok())
+        && (2..=4).contains(&version)
+    {
+        return version;
+    }
+    match repo_config_get(repo, "feature", "manyFiles").as_deref() {
+        Some("true" | "yes" | "on" | "1") => 4,
+        _ => 2,
+    }
 }
 
 pub fn read_index(repo: &GitRepository) -> Result<GitIndex> {
     let index_path = repo.gitdir.join("index");
     if !index_path.exists() {
         return Ok(GitIndex {
+            version: index_version_default(repo),
             entries: Vec::new(),
+            cache_tree: None,
+            resolve_undo: Vec::new(),
+            extensions: Vec::new(),
+            cache_tree_entries: Vec::new(),
         });
     }
 
-    let mut f = File::open(&index_path)
+    let data = fs::read(&index_path)
         .with_context(|| format!("Could not open index at {:?}", index_path))?;
 
+    // Everything is covered by a trailing SHA-1, which is all zeroes when
+    // `index.skipHash` is set
+    if data.len() < 12 + 20 {
+        bail!("index file smaller than expected");
+    }
+    let (data, checksum) = data.split_at(data.len() - 20);
+    let expected: [u8; 20] = Sha1::digest(data).into();
+    if checksum != expected && checksum != [0u8; 20] {
+        bail!("index file corrupt: bad signature");
+    }
+    let mut f = Cursor::new(data);
+
     let mut signature = [0u8; 4];
     f.read_exact(&mut signature)?;
     if &signature != b"DIRC" {
@@ -43,20 +224,20 @@ pub fn read_index(repo: &GitRepository) -> Result<GitIndex> {
     }
 
     let version = f.read_u32::<BigEndian>()?;
-    if version != 2 {
+    if !(2..=4).contains(&version) {
         bail!("Unsupported index version: {version}");
     }
 
     let num_entries = f.read_u32::<BigEndian>()?;
 
-    let mut entries = Vec::with_capacity(num_entries as usize);
+    let mut entries: Vec<GitIndexEntry> = Vec::with_capacity(num_entries as usize);
 
     for _ in 0..num_entries {
         // stat fields
         let ctime = f.read_u32::<BigEndian>()?;
-        f.read_u32::<BigEndian>()?; // ctime nanosec, ignore
+        let ctime_nsec = f.read_u32::<BigEndian>()?;
         let mtime = f.read_u32::<BigEndian>()?;
-        f.read_u32::<BigEndian>()?; // mtime nanosec, ignore
+        let mtime_nsec = f.read_u32::<BigEndian>()?;
         let dev = f.read_u32::<BigEndian>()?;
         let ino = f.read_u32::<BigEndian>()?;
         let mode = f.read_u32::<BigEndian>()?;
@@ -69,27 +250,44 @@ pub fn read_index(repo: &GitRepository) -> Result<GitIndex> {
         let sha = hex::encode(sha_buf);
 
         let flags = f.read_u16::<BigEndian>()?;
+        let flags_extended = if flags & FLAG_EXTENDED != 0 {
+            if version < 3 {
+                bail!("Extended index flags in a version {version} index");
+            }
+            f.read_u16::<BigEndian>()?
+        } else {
+            0
+        };
 
-        // path (null-terminated string, padded to 8-byte boundary)
+        // v4 paths only store what differs from the previous entry's path
         let mut path_bytes = Vec::new();
-        loop {
-            let mut byte = [0u8; 1];
-            f.read_exact(&mut byte)?;
-            if byte[0] == 0 {
-                break;
+        if version == 4 {
+            let strip = varint_read(&mut f)? as usize;
+            let previous = entries.last().map_or("", |e| e.path.as_str()).as_bytes();
+            if strip > previous.len() {
+                bail!("Invalid path prefix in index entry");
             }
-            path_bytes.push(byte[0]);
+            path_bytes.extend_from_slice(&previous[..previous.len() - strip]);
+        }
+        let prefix_len = path_bytes.len();
+        f.read_until(0, &mut path_bytes)?;
+        if path_bytes.pop() != Some(0) {
+            bail!("Unterminated path in index entry");
         }
         let path = String::from_utf8(path_bytes).context("Invalid UTF-8 in index path")?;
 
-        // align to 8 bytes
-        let entry_len = 62 + path.len() + 1; // base + path + null
-        let padding = (8 - (entry_len % 8)) % 8;
-        f.seek(SeekFrom::Current(padding as i64))?;
+        // Before v4, entries are NUL-padded to a multiple of 8 bytes
+        if version < 4 {
+            let entry_len = entry_header_len(flags_extended) + (path.len() - prefix_len) + 1;
+            let padding = (8 - (entry_len % 8)) % 8;
+            f.set_position(f.position() + padding as u64);
+        }
 
         entries.push(GitIndexEntry {
             ctime,
+            ctime_nsec,
             mtime,
+            mtime_nsec,
             dev,
             ino,
             mode,
@@ -98,28 +296,73 @@ pub fn read_index(repo: &GitRepository) -> Result<GitIndex> {
             size,
             sha,
             flags,
+            flags_extended,
             path,
         });
     }
 
-    Ok(GitIndex { entries })
+    let mut index = GitIndex {
+        version,
+        cache_tree_entries: entries.clone(),
+        entries,
+        cache_tree: None,
+        resolve_undo: Vec::new(),
+        extensions: Vec::new(),
+    };
+
+    // Extensions: a 4-byte signature, a 32-bit size, then the data
+    while (f.position() as usize) < data.len() {
+        let mut signature = [0u8; 4];
+        f.read_exact(&mut signature)?;
+        let size = f.read_u32::<BigEndian>()? as usize;
+        let start = f.position() as usize;
+        let ext = data
+            .get(start..start + size)
+            .context("index extension runs past the end of the file")?;
+        f.set_position((start + size) as u64);
+
+        match &signature {
+            b"TREE" => index.cache_tree = cache_tree_read(&mut Cursor::new(ext))?,
+            b"REUC" => index.resolve_undo = resolve_undo_read(ext)?,
+            // Only an uppercase signature means the extension can be ignored
+            _ if signature[0].is_ascii_uppercase() => {
+                index.extensions.push((signature, ext.to_vec()))
+            }
+            _ => bail!(
+                "index uses {} extension, which we do not understand",
+                String::from_utf8_lossy(&signature)
+            ),
+        }
+    }
+
+    Ok(index)
 }
 
 pub fn write_index(repo: &GitRepository, index: &GitIndex) -> Result<()> {
     let index_path = repo.gitdir.join("index");
-    let mut f = File::create(&index_path)?;
+    let mut lock = LockFile::acquire(&index_path)?;
+
+    // Extended flags need at least v3
+    let extended = index.entries.iter().any(|e| e.flags_extended != 0);
+    let version = match index.version {
+        2 if extended => 3,
+        version => version,
+    };
 
-    // headerr
-    f.write_all(b"DIRC")?; // signature
-    f.write_u32::<BigEndian>(2)?; // version
+    let mut f = Vec::new();
+
+    // header
+    f.extend_from_slice(b"DIRC"); // signature
+    f.write_u32::<BigEndian>(version)?;
     f.write_u32::<BigEndian>(index.entries.len() as u32)?;
 
     // entries
+    let mut previous = "";
     for e in &index.entries {
         f.write_u32::<BigEndian>(e.ctime)?;
-        f.write_u32::<BigEndian>(0)?;
+        f.write_u32::<BigEndian>(e.ctime_nsec)?;
         f.write_u32::<BigEndian>(e.mtime)?;
-        f.write_u32::<BigEndian>(0)?;
+        f.write_u32::<BigEndian>(e.mtime_nsec)?;
 
         f.write_u32::<BigEndian>(e.dev)?;
         f.write_u32::<BigEndian>(e.ino)?;
@@ -130,22 +373,486 @@ pub fn write_index(repo: &GitRepository, index: &GitIndex) -> Result<()> {
 
         // sha1 (hex string back to raw 20 bytes)
         let sha_bytes = hex::decode(&e.sha)?;
-        f.write_all(&sha_bytes)?;
+        f.extend_from_slice(&sha_bytes);
 
         // flags
-        f.write_u16::<BigEndian>(e.flags)?;
+        f.write_u16::<BigEndian>(e.flags_on_disk())?;
+        if e.flags_extended != 0 {
+            f.write_u16::<BigEndian>(e.flags_extended)?;
+        }
+
+        if version == 4 {
+            // strip count + the part of the path not shared with the previous one
+            let common = previous
+                .bytes()
+                .zip(e.path.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            varint_write(&mut f, (previous.len() - common) as u64);
+            f.extend_from_slice(&e.path.as_bytes()[common..]);
+            f.push(0);
+            previous = &e.path;
+        } else {
+            // path + null terminator, padded to a multiple of 8 bytes
+            f.extend_from_slice(e.path.as_bytes());
+            f.push(0);
+            let entry_len = entry_header_len(e.flags_extended) + e.path.len() + 1;
+            let padding = (8 - (entry_len % 8)) % 8;
+            f.resize(f.len() + padding, 0);
+        }
+    }
+
+    let entries_end = f.len();
+    let mut extensions: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+    let kept = |sig: &[u8; 4]| index.extensions.iter().any(|(s, _)| s == sig);
+
+    // The entry offset table is rebuilt as a single block starting right
+    // after the header
+    if kept(b"IEOT") && !index.entries.is_empty() {
+        let mut data = Vec::new();
+        data.write_u32::<BigEndian>(1)?;
+        data.write_u32::<BigEndian>(12)?;
+        data.write_u32::<BigEndian>(index.entries.len() as u32)?;
+        extensions.push((*b"IEOT", data));
+    }
+
+    if let Some(tree) = cache_tree_refresh(index) {
+        let mut data = Vec::new();
+        cache_tree_write(&mut data, &tree);
+        extensions.push((*b"TREE", data));
+    }
+    if !index.resolve_undo.is_empty() {
+        extensions.push((*b"REUC", resolve_undo_write(&index.resolve_undo)));
+    }
+
+    extensions.extend(
+        index
+            .extensions
+            .iter()
+            .filter(|(sig, _)| sig != b"IEOT" && sig != b"EOIE")
+            .cloned(),
+    );
+
+    let mut headers = Sha1::new();
+    for (signature, data) in &extensions {
+        f.extend_from_slice(signature);
+        f.write_u32::<BigEndian>(data.len() as u32)?;
+        f.extend_from_slice(data);
+        headers.update(signature);
+        headers.update((data.len() as u32).to_be_bytes());
+    }
+
+    // The end-of-entries marker must come last: where the extensions start,
+    // and a hash of their signatures and sizes
+    if kept(b"EOIE") {
+        f.extend_from_slice(b"EOIE");
+        f.write_u32::<BigEndian>(24)?;
+        f.write_u32::<BigEndian>(entries_end as u32)?;
+        f.extend_from_slice(&headers.finalize());
+    }
+
+    let checksum: [u8; 20] = Sha1::digest(&f).into();
+    f.extend_from_slice(&checksum);
+
+    lock.write_all(&f)?;
+    lock.commit()
+}
+
+/// The cache tree to write with `index`: every directory containing a path
+/// added, removed or changed since the cache tree last matched is marked stale.
+fn cache_tree_refresh(index: &GitIndex) -> Option<CacheTree> {
+    let mut tree = index.cache_tree.clone()?;
+
+    let by_path = |entries: &[GitIndexEntry]| -> BTreeMap<(String, u16), (u32, String, bool)> {
+        entries
+            .iter()
+            .map(|e| {
+                let key = (e.path.clone(), e.stage());
+                (key, (e.mode, e.sha.clone(), e.intent_to_add()))
+            })
+            .collect()
+    };
+    let before = by_path(&index.cache_tree_entries);
+    let after = by_path(&index.entries);
 
-        // path + null terminator
-        f.write_all(e.path.as_bytes())?;
-        f.write_all(&[0])?;
+    for key in before.keys().chain(after.keys()) {
+        if before.get(key) != after.get(key) {
+            tree.invalidate(&key.0);
+        }
+    }
+
+    Some(tree)
+}
+
+/// Read one TREE node and its subtrees: "<name>\0<entry count> <subtree
+/// count>\n", the SHA if the count isn't -1, then the subtrees.
+fn cache_tree_read(f: &mut Cursor<&[u8]>) -> Result<Option<CacheTree>> {
+    if f.position() as usize >= f.get_ref().len() {
+        return Ok(None);
+    }
+
+    let mut name = Vec::new();
+    f.read_until(0, &mut name)?;
+    if name.pop() != Some(0) {
+        bail!("Corrupt cache tree in index");
+    }
+    let mut counts = Vec::new();
+    f.read_until(b'\n', &mut counts)?;
+    let counts = String::from_utf8_lossy(&counts);
+    let (entry_count, subtree_count) = counts
+        .trim_end()
+        .split_once(' ')
+        .and_then(|(e, s)| Some((e.parse::<i64>().ok()?, s.parse::<usize>().ok()?)))
+        .context("Corrupt cache tree in index")?;
+
+    let sha = if entry_count >= 0 {
+        let mut sha = [0u8; 20];
+        f.read_exact(&mut sha)?;
+        Some(hex::encode(sha))
+    } else {
+        None
+    };
+
+    let mut subtrees = Vec::with_capacity(subtree_count);
+    for _ in 0..subtree_count {
+        subtrees.push(cache_tree_read(f)?.context("Corrupt cache tree in index")?);
+    }
+
+    Ok(Some(CacheTree {
+        name: String::from_utf8(name).context("Invalid UTF-8 in cache tree")?,
+        entry_count,
+        sha,
+        subtrees,
+    }))
+}
+
+fn cache_tree_write(out: &mut Vec<u8>, tree: &CacheTree) {
+    out.extend_from_slice(tree.name.as_bytes());
+    out.push(0);
+    let entry_count = if tree.sha.is_some() {
+        tree.entry_count
+    } else {
+        -1
+    };
+    out.extend_from_slice(format!("{} {}\n", entry_count, tree.subtrees.len()).as_bytes());
+    if let Some(sha) = tree.valid_sha()
+        && let Ok(bytes) = hex::decode(sha)
+    {
+        out.extend_from_slice(&bytes);
+    }
+    for sub in &tree.subtrees {
+        cache_tree_write(out, sub);
+    }
+}
+
+/// REUC records: "<path>\0", three octal modes each ending in NUL (0 for a
+/// missing stage), then the SHA of every stage that is present.
+fn resolve_undo_read(data: &[u8]) -> Result<Vec<ResolveUndo>> {
+    fn field(f: &mut Cursor<&[u8]>) -> Result<String> {
+        let mut buf = Vec::new();
+        f.read_until(0, &mut buf)?;
+        if buf.pop() != Some(0) {
+            bail!("Corrupt resolve-undo data in index");
+        }
+        String::from_utf8(buf).context("Invalid UTF-8 in resolve-undo data")
+    }
+
+    let mut f = Cursor::new(data);
+    let mut records = Vec::new();
+
+    while (f.position() as usize) < data.len() {
+        let path = field(&mut f)?;
+        let mut modes = [0u32; 3];
+        for mode in &mut modes {
+            *mode = u32::from_str_radix(&field(&mut f)?, 8)
+                .context("Corrupt resolve-undo data in index")?;
+        }
+
+        let mut stages: [Option<(u32, String)>; 3] = Default::default();
+        for (stage, mode) in stages.iter_mut().zip(modes) {
+            if mode != 0 {
+                let mut sha = [0u8; 20];
+                f.read_exact(&mut sha)?;
+                *stage = Some((mode, hex::encode(sha)));
+            }
+        }
+        records.push(ResolveUndo { path, stages });
+    }
+
+    Ok(records)
+}
+
+fn resolve_undo_write(records: &[ResolveUndo]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for record in records {
+        out.extend_from_slice(record.path.as_bytes());
+        out.push(0);
+        for stage in &record.stages {
+            let mode = stage.as_ref().map_or(0, |(mode, _)| *mode);
+            out.extend_from_slice(format!("{mode:o}").as_bytes());
+            out.push(0);
+        }
+        for (_, sha) in record.stages.iter().flatten() {
+            out.extend_from_slice(&hex::decode(sha).unwrap_or_default());
+        }
+    }
+    out
+}
+
+/// Size of an entry before its path: stat data, SHA and flags.
+fn entry_header_len(flags_extended: u16) -> usize {
+    if flags_extended != 0 { 64 } else { 62 }
+}
+
+/// Read the variable-length integer v4 paths start with (the same encoding
+/// as pack OFS_DELTA offsets).
+fn varint_read(f: &mut Cursor<&[u8]>) -> Result<u64> {
+    let mut byte = f.read_u8()?;
+    let mut value = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = f.read_u8()?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as u64;
+    }
+    Ok(value)
+}
 
-        // pad to multiple of 8 bytes
-        let entry_len = 62 + e.path.len() + 1;
-        let padding = (8 - (entry_len % 8)) % 8;
-        if padding > 0 {
-            f.write_all(&vec![0u8; padding])?;
+fn varint_write(out: &mut Vec<u8>, mut value: u64) {
+    let mut buf = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        buf.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.extend(buf.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{repo::repo_tmp, worktree::index_entry_unstatted};
+
+    const SHA: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    fn index_new(version: u32, entries: Vec<GitIndexEntry>) -> GitIndex {
+        GitIndex {
+            version,
+            entries,
+            cache_tree: None,
+            resolve_undo: Vec::new(),
+            extensions: Vec::new(),
+            cache_tree_entries: Vec::new(),
         }
     }
 
-    Ok(())
+    fn entry(path: &str, flags_extended: u16) -> GitIndexEntry {
+        let mut entry = index_entry_unstatted(path, 0o100644, SHA);
+        entry.flags_extended = flags_extended;
+        entry.mtime = 1_700_000_000;
+        entry.mtime_nsec = 123_456_789;
+        entry
+    }
+
+    fn summary(index: &GitIndex) -> Vec<(String, u16, u16, u32, u32)> {
+        index
+            .entries
+            .iter()
+            .map(|e| {
+                (
+                    e.path.clone(),
+                    e.flags_on_disk(),
+                    e.flags_extended,
+                    e.mtime,
+                    e.mtime_nsec,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn v3_extended_flags_round_trip() {
+        let repo = repo_tmp("index-v3");
+        let index = index_new(
+            3,
+            vec![
+                entry("a.txt", 0),
+                entry("dir/skipped.txt", FLAG_SKIP_WORKTREE),
+                entry("dir/new.txt", FLAG_INTENT_TO_ADD),
+            ],
+        );
+        write_index(&repo, &index).unwrap();
+
+        let read = read_index(&repo).unwrap();
+        assert_eq!(read.version, 3);
+        assert_eq!(summary(&read), summary(&index));
+        assert!(read.entries[1].skip_worktree());
+        assert!(read.entries[2].intent_to_add());
+        assert!(!read.entries[0].skip_worktree() && !read.entries[0].intent_to_add());
+    }
+
+    #[test]
+    fn v2_with_extended_flags_is_written_as_v3() {
+        let repo = repo_tmp("index-v2-upgrade");
+        write_index(&repo, &index_new(2, vec![entry("a", 0)])).unwrap();
+        assert_eq!(read_index(&repo).unwrap().version, 2);
+
+        write_index(&repo, &index_new(2, vec![entry("a", FLAG_INTENT_TO_ADD)])).unwrap();
+        let read = read_index(&repo).unwrap();
+        assert_eq!(read.version, 3);
+        assert!(read.entries[0].intent_to_add());
+    }
+
+    #[test]
+    fn v4_prefix_compression_round_trip() {
+        let repo = repo_tmp("index-v4");
+        let paths = [
+            "README",
+            "src/git/index.rs",
+            "src/git/index_test.rs",
+            "src/git/pack.rs",
+            "src/main.rs",
+            "zzz",
+        ];
+        let mut entries: Vec<GitIndexEntry> = paths.iter().map(|p| entry(p, 0)).collect();
+        entries[3].flags_extended = FLAG_SKIP_WORKTREE;
+        let index = index_new(4, entries);
+        write_index(&repo, &index).unwrap();
+
+        let read = read_index(&repo).unwrap();
+        assert_eq!(read.version, 4);
+        assert_eq!(summary(&read), summary(&index));
+
+        // "src/git/index_test.rs" follows "src/git/index.rs": strip 3 bytes
+        // (".rs"), then only "_test.rs" is stored
+        let data = fs::read(repo.gitdir.join("index")).unwrap();
+        let needle = [&[3u8][..], b"_test.rs\0"].concat();
+        assert!(data.windows(needle.len()).any(|w| w == needle));
+        assert!(!data.windows(9).any(|w| w == b"index_tes"));
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 20, u32::MAX as u64] {
+            let mut out = Vec::new();
+            varint_write(&mut out, value);
+            assert_eq!(varint_read(&mut Cursor::new(&out[..])).unwrap(), value);
+        }
+    }
+
+    /// Append an extension to the index file on disk, fixing up its checksum.
+    fn extension_append(repo: &GitRepository, signature: &[u8; 4], ext: &[u8]) {
+        let path = repo.gitdir.join("index");
+        let mut data = fs::read(&path).unwrap();
+        data.truncate(data.len() - 20);
+        data.extend_from_slice(signature);
+        data.extend_from_slice(&(ext.len() as u32).to_be_bytes());
+        data.extend_from_slice(ext);
+        let checksum: [u8; 20] = Sha1::digest(&data).into();
+        data.extend_from_slice(&checksum);
+        fs::write(&path, data).unwrap();
+    }
+
+    #[test]
+    fn tree_and_resolve_undo_round_trip() {
+        let repo = repo_tmp("index-tree-reuc");
+        let mut index = index_new(2, vec![entry("a", 0), entry("dir/b", 0), entry("dir/c", 0)]);
+        write_index(&repo, &index).unwrap();
+
+        let mut dir = CacheTree::new("dir");
+        dir.entry_count = 2;
+        dir.sha = Some("1111111111111111111111111111111111111111".to_string());
+        let mut root = CacheTree::new("");
+        root.entry_count = 3;
+        root.sha = Some("2222222222222222222222222222222222222222".to_string());
+        root.subtrees = vec![dir, CacheTree::new("stale")];
+        index.cache_tree_set(root);
+        index.resolve_undo = vec![ResolveUndo {
+            path: "dir/b".to_string(),
+            stages: [
+                None,
+                Some((0o100644, SHA.to_string())),
+                Some((
+                    0o100755,
+                    "3333333333333333333333333333333333333333".to_string(),
+                )),
+            ],
+        }];
+        write_index(&repo, &index).unwrap();
+
+        let read = read_index(&repo).unwrap();
+        assert_eq!(
+            format!("{:?}", read.cache_tree),
+            format!("{:?}", index.cache_tree)
+        );
+        assert_eq!(
+            format!("{:?}", read.resolve_undo),
+            format!("{:?}", index.resolve_undo)
+        );
+
+        // Changing an entry leaves its directories stale, and only those
+        let mut changed = read;
+        changed.entries[2].sha = "4444444444444444444444444444444444444444".to_string();
+        write_index(&repo, &changed).unwrap();
+        let tree = read_index(&repo).unwrap().cache_tree.unwrap();
+        assert_eq!(tree.valid_sha(), None);
+        assert_eq!(tree.subtrees[0].valid_sha(), None);
+
+        let mut changed = read_index(&repo).unwrap();
+        changed.cache_tree_set(index.cache_tree.clone().unwrap());
+        write_index(&repo, &changed).unwrap();
+        changed.entries[0].sha = "4444444444444444444444444444444444444444".to_string();
+        write_index(&repo, &changed).unwrap();
+        let tree = read_index(&repo).unwrap().cache_tree.unwrap();
+        assert_eq!(tree.valid_sha(), None);
+        assert_eq!(
+            tree.subtrees[0].valid_sha(),
+            Some("1111111111111111111111111111111111111111")
+        );
+    }
+
+    #[test]
+    fn optional_extensions_are_kept() {
+        let repo = repo_tmp("index-optional-ext");
+        write_index(&repo, &index_new(2, vec![entry("a", 0)])).unwrap();
+        extension_append(&repo, b"ZZZZ", b"opaque data");
+
+        let index = read_index(&repo).unwrap();
+        assert_eq!(index.extensions, vec![(*b"ZZZZ", b"opaque data".to_vec())]);
+        write_index(&repo, &index).unwrap();
+        assert_eq!(
+            read_index(&repo).unwrap().extensions,
+            vec![(*b"ZZZZ", b"opaque data".to_vec())]
+        );
+    }
+
+    #[test]
+    fn required_unknown_extension_is_rejected() {
+        let repo = repo_tmp("index-required-ext");
+        write_index(&repo, &index_new(2, vec![entry("a", 0)])).unwrap();
+        extension_append(&repo, b"zzzz", b"must understand");
+
+        let err = read_index(&repo).unwrap_err().to_string();
+        assert_eq!(err, "index uses zzzz extension, which we do not understand");
+    }
+
+    #[test]
+    fn checksum_is_verified() {
+        let repo = repo_tmp("index-checksum");
+        write_index(&repo, &index_new(2, vec![entry("a", 0)])).unwrap();
+        let path = repo.gitdir.join("index");
+        let data = fs::read(&path).unwrap();
+
+        let mut corrupt = data.clone();
+        corrupt[12] ^= 1;
+        fs::write(&path, &corrupt).unwrap();
+        let err = read_index(&repo).unwrap_err().to_string();
+        assert_eq!(err, "index file corrupt: bad signature");
+
+        // index.skipHash writes an all-zero checksum
+        let mut skipped = data.clone();
+        let len = skipped.len();
+        skipped[len - 20..].fill(0);
+        fs::write(&path, &skipped).unwrap();
+        assert_eq!(read_index(&repo).unwrap().entries.len(), 1);
+    }
 }
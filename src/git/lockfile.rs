@@ -0,0 +1,93 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+/// Exclusive hold on `<file>.lock` while `<file>` is rewritten. Data goes to
+/// the lock file, which `commit` renames over the file; a lock dropped
+/// without being committed is removed and leaves the file untouched.
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    /// Create `<path>.lock`, failing if another process holds it.
+    pub fn acquire(path: &Path) -> Result<LockFile> {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".lock");
+        let lock_path = PathBuf::from(name);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => bail!(
+                "Unable to create '{}': File exists.\n\n\
+                 Another git process seems to be running in this repository, e.g.\n\
+                 an editor opened by 'git commit'. Please make sure all processes\n\
+                 are terminated then try again. If it still fails, a git process\n\
+                 may have crashed in this repository earlier:\n\
+                 remove the file manually to continue.",
+                lock_path.display()
+            ),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Unable to create '{}'", lock_path.display()));
+            }
+        };
+
+        Ok(LockFile {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// The file this lock protects.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let file = self.file.as_mut().context("lock file already released")?;
+        file.write_all(data)
+            .with_context(|| format!("Failed to write {:?}", self.lock_path))
+    }
+
+    /// Flush the new contents to disk and move them into place.
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().context("lock file already released")?;
+        let result = file
+            .sync_all()
+            .and_then(|_| fs::rename(&self.lock_path, &self.path));
+        if result.is_err() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+        result.with_context(|| format!("Failed to write {:?}", self.path))
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Replace `path` with `data` under its lock file.
+pub fn lockfile_write(path: &Path, data: &[u8]) -> Result<()> {
+    let mut lock = LockFile::acquire(path)?;
+    lock.write_all(data)?;
+    lock.commit()
+}
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+
+use crate::git::{
+    diff::{DiffOp, is_binary, myers_diff, split_lines},
+    objects::object_read_raw,
+    repo::GitRepository,
+    tree::GitTreeLeaf,
+};
+
+/// Result of a line-level three-way merge.
+pub struct MergedContent {
+    pub data: Vec<u8>,
+    pub conflicts: usize,
+}
+
+fn push_lines(out: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        out.extend_from_slice(line);
+    }
+}
+
+fn push_conflict_side(out: &mut Vec<u8>, lines: &[&[u8]]) {
+    push_lines(out, lines);
+    if lines.last().is_some_and(|l| !l.ends_with(b"\n")) {
+        out.push(b'\n');
+    }
+}
+
+/// For each line of `base`, the index of the matching line in `other` (if kept).
+fn base_matches(base: &[&[u8]], other: &[&[u8]]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; base.len()];
+    for e in myers_diff(base, other) {
+        if e.op == DiffOp::Equal {
+            matches[e.a] = Some(e.b);
+        }
+    }
+    matches
+}
+
+/// Three-way merge of `ours` and `theirs` against `base` (diff3 style),
+/// writing conflict markers labelled `ours_label`/`theirs_label`.
+pub fn merge_content(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+) -> MergedContent {
+    let o = split_lines(base);
+    let a = split_lines(ours);
+    let b = split_lines(theirs);
+    let match_a = base_matches(&o, &a);
+    let match_b = base_matches(&o, &b);
+
+    let mut out = Vec::new();
+    let mut conflicts = 0usize;
+    let (mut i, mut j, mut k) = (0usize, 0usize, 0usize);
+
+    loop {
+        // Lines unchanged on both sides
+        while i < o.len() && match_a[i] == Some(j) && match_b[i] == Some(k) {
+            out.extend_from_slice(o[i]);
+            i += 1;
+            j += 1;
+            k += 1;
+        }
+        if i == o.len() && j == a.len() && k == b.len() {
+            break;
+        }
+
+        // Next base line that both sides still contain
+        let (ni, nj, nk) = (i..o.len())
+            .find_map(|n| match (match_a[n], match_b[n]) {
+                (Some(x), Some(y)) if x >= j && y >= k => Some((n, x, y)),
+                _ => None,
+            })
+            .unwrap_or((o.len(), a.len(), b.len()));
+
+        let (oc, ac, bc) = (&o[i..ni], &a[j..nj], &b[k..nk]);
+        if ac == oc {
+            push_lines(&mut out, bc);
+        } else if bc == oc || ac == bc {
+            push_lines(&mut out, ac);
+        } else {
+            conflicts += 1;
+            out.extend_from_slice(format!("<<<<<<< {ours_label}\n").as_bytes());
+            push_conflict_side(&mut out, ac);
+            out.extend_from_slice(b"=======\n");
+            push_conflict_side(&mut out, bc);
+            out.extend_from_slice(format!(">>>>>>> {theirs_label}\n").as_bytes());
+        }
+
+        (i, j, k) = (ni, nj, nk);
+    }
+
+    MergedContent {
+        data: out,
+        conflicts,
+    }
+}
+
+/// Outcome of merging one path.
+pub enum MergeEntry {
+    /// Cleanly merged to this mode and blob SHA.
+    Clean(u32, String),
+    /// Cleanly merged to content that still needs to be written as a blob.
+    Merged(u32, Vec<u8>),
+    /// Conflicted: base/ours/theirs versions (mode, sha) and the worktree content.
+    Conflict {
+        stages: [Option<(u32, String)>; 3],
+        worktree: Option<(u32, Vec<u8>)>,
+        reason: &'static str,
+    },
+}
+
+fn leaf_id(leaf: Option<&GitTreeLeaf>) -> Option<(u32, String)> {
+    leaf.map(|l| (l.mode_bits(), hex::encode(l.sha)))
+}
+
+/// Three-way merge of flattened trees. Paths deleted by the merge are omitted.
+pub fn merge_trees(
+    repo: &GitRepository,
+    base: &BTreeMap<String, GitTreeLeaf>,
+    ours: &BTreeMap<String, GitTreeLeaf>,
+    theirs: &BTreeMap<String, GitTreeLeaf>,
+    ours_label: &str,
+    theirs_label: &str,
+) -> Result<BTreeMap<String, MergeEntry>> {
+    let paths: BTreeSet<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+    let mut result = BTreeMap::new();
+
+    for path in paths {
+        let o = leaf_id(base.get(path));
+        let a = leaf_id(ours.get(path));
+        let b = leaf_id(theirs.get(path));
+
+        let chosen = if a == b || b == o {
+            a.clone()
+        } else if a == o {
+            b.clone()
+        } else {
+            // Changed differently on both sides
+            match (&a, &b) {
+                (Some((a_mode, a_sha)), Some((b_mode, b_sha)))
+                    if *a_mode != 0o160000 && *b_mode != 0o160000 =>
+                {
+                    let base_data = match &o {
+                        Some((_, sha)) => object_read_raw(repo, sha)?.1,
+                        None => Vec::new(),
+                    };
+                    let ours_data = object_read_raw(repo, a_sha)?.1;
+                    let theirs_data = object_read_raw(repo, b_sha)?.1;
+
+                    // Whichever side changed the mode wins
+                    let mode = match &o {
+                        Some((o_mode, _)) if o_mode == a_mode => *b_mode,
+                        _ => *a_mode,
+                    };
+
+                    if a_sha == b_sha {
+                        result.insert(path.clone(), MergeEntry::Clean(mode, a_sha.clone()));
+                        continue;
+                    }
+
+                    let binary = [&base_data, &ours_data, &theirs_data]
+                        .iter()
+                        .any(|d| is_binary(d));
+                    let merged = if binary {
+                        None
+                    } else {
+                        Some(merge_content(
+                            &base_data,
+                            &ours_data,
+                            &theirs_data,
+                            ours_label,
+                            theirs_label,
+                        ))
+                    };
+
+                    // Both sides adding the path is reported as such by git
+                    let added = o.is_none();
+                    let entry = match merged {
+                        Some(m) if m.conflicts == 0 => MergeEntry::Merged(mode, m.data),
+                        Some(m) => MergeEntry::Conflict {
+                            stages: [o.clone(), a.clone(), b.clone()],
+                            worktree: Some((mode, m.data)),
+                            reason: if added { "add/add" } else { "content" },
+                        },
+                        None => MergeEntry::Conflict {
+                            stages: [o.clone(), a.clone(), b.clone()],
+                            worktree: Some((*a_mode, ours_data)),
+                            reason: if added { "add/add" } else { "binary" },
+                        },
+                    };
+                    result.insert(path.clone(), entry);
+                    continue;
+                }
+                _ => {
+                    // Modify/delete, add/add of submodules and the like: keep
+                    // whichever side still has content in the worktree
+                    let survivor = a.as_ref().or(b.as_ref());
+                    let worktree = match survivor {
+                        Some((mode, sha)) if *mode != 0o160000 => {
+                            Some((*mode, object_read_raw(repo, sha)?.1))
+                        }
+                        _ => None,
+                    };
+                    let reason = if a.is_none() || b.is_none() {
+                        "modify/delete"
+                    } else {
+                        "add/add"
+                    };
+                    result.insert(
+                        path.clone(),
+                        MergeEntry::Conflict {
+                            stages: [o.clone(), a.clone(), b.clone()],
+                            worktree,
+                            reason,
+                        },
+                    );
+                    continue;
+                }
+            }
+        };
+
+        if let Some((mode, sha)) = chosen {
+            result.insert(path.clone(), MergeEntry::Clean(mode, sha));
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let m = merge_content(
+            base.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            "ours",
+            "theirs",
+        );
+        (String::from_utf8(m.data).unwrap(), m.conflicts)
+    }
+
+    #[test]
+    fn clean_merges() {
+        let base = "1\n2\n3\n4\n5\n6\n7\n";
+        // Edits far apart on each side
+        assert_eq!(
+            merge(base, "one\n2\n3\n4\n5\n6\n7\n", "1\n2\n3\n4\n5\n6\nseven\n"),
+            ("one\n2\n3\n4\n5\n6\nseven\n".to_string(), 0)
+        );
+        // The same edit on both sides
+        assert_eq!(
+            merge(
+                base,
+                "1\n2\nthree\n4\n5\n6\n7\n",
+                "1\n2\nthree\n4\n5\n6\n7\n"
+            ),
+            ("1\n2\nthree\n4\n5\n6\n7\n".to_string(), 0)
+        );
+        // A deletion on one side, an insertion elsewhere on the other
+        assert_eq!(
+            merge(base, "1\n2\n4\n5\n6\n7\n", "1\n2\n3\n4\n5\n6\n7\n8\n"),
+            ("1\n2\n4\n5\n6\n7\n8\n".to_string(), 0)
+        );
+        // Only one side changed
+        assert_eq!(merge(base, base, ""), (String::new(), 0));
+    }
+
+    #[test]
+    fn conflicting_edits() {
+        let (data, conflicts) = merge("1\n2\n3\n", "1\nours\n3\n", "1\ntheirs\n3\n");
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            data,
+            "1\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n3\n"
+        );
+
+        // Both sides adding different content: each side still gets its own
+        // line even without a newline at the end
+        let (data, conflicts) = merge("", "a\nb", "a\nc");
+        assert_eq!(conflicts, 1);
+        assert_eq!(data, "<<<<<<< ours\na\nb\n=======\na\nc\n>>>>>>> theirs\n");
+
+        let (_, conflicts) = merge("1\n2\n3\n4\n5\n", "x\n2\n3\n4\ny\n", "z\n2\n3\n4\nw\n");
+        assert_eq!(conflicts, 2);
+    }
+}
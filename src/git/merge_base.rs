@@ -0,0 +1,276 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+};
+
+use anyhow::Result;
+
+use crate::git::{objects::commit_read, repo::GitRepository};
+
+/// Every commit reachable from `sha`, including itself.
+pub fn ancestors(repo: &GitRepository, sha: &str) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([sha.to_string()]);
+
+    while let Some(sha) = queue.pop_front() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+        queue.extend(commit_read(repo, &sha)?.parents());
+    }
+
+    Ok(seen)
+}
+
+/// Whether `ancestor` is reachable from `descendant` (a commit is its own
+/// ancestor).
+pub fn is_ancestor(repo: &GitRepository, ancestor: &str, descendant: &str) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([descendant.to_string()]);
+
+    while let Some(sha) = queue.pop_front() {
+        if sha == ancestor {
+            return Ok(true);
+        }
+        if seen.insert(sha.clone()) {
+            queue.extend(commit_read(repo, &sha)?.parents());
+        }
+    }
+
+    Ok(false)
+}
+
+/// Best common ancestors of `a` and `b`, newest first: common ancestors that
+/// are not themselves ancestors of another one. Criss-cross histories have
+/// more than one.
+pub fn merge_bases(repo: &GitRepository, a: &str, b: &str) -> Result<Vec<String>> {
+    merge_bases_many(repo, a, &[b.to_string()])
+}
+
+/// Best common ancestors of `one` and a hypothetical merge of all `others`.
+pub fn merge_bases_many(repo: &GitRepository, one: &str, others: &[String]) -> Result<Vec<String>> {
+    if others.iter().any(|o| o == one) {
+        return Ok(vec![one.to_string()]);
+    }
+
+    let candidates = paint_down_to_common(repo, one, others)?;
+    remove_redundant(repo, candidates)
+}
+
+/// Best common ancestors of all of `commits` at once, as needed for an
+/// octopus merge.
+pub fn merge_bases_octopus(repo: &GitRepository, commits: &[String]) -> Result<Vec<String>> {
+    let Some((first, rest)) = commits.split_first() else {
+        return Ok(Vec::new());
+    };
+
+    let mut bases = vec![first.clone()];
+    for commit in rest {
+        let mut next = Vec::new();
+        for base in &bases {
+            for found in merge_bases(repo, base, commit)? {
+                if !next.contains(&found) {
+                    next.push(found);
+                }
+            }
+        }
+        bases = remove_redundant(repo, next)?;
+        if bases.is_empty() {
+            break;
+        }
+    }
+
+    Ok(bases)
+}
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+
+/// Walk down from `one` and `others` newest first, painting each commit with
+/// the side(s) it is reachable from. Commits reached from both sides are
+/// candidates; everything below them is stale and stops the walk.
+fn paint_down_to_common(repo: &GitRepository, one: &str, others: &[String]) -> Result<Vec<String>> {
+    let mut walk = PaintWalk {
+        repo,
+        commits: HashMap::new(),
+        flags: HashMap::new(),
+        queue: BinaryHeap::new(),
+        queued: HashSet::new(),
+        nonstale: 0,
+    };
+    let mut result = Vec::new();
+
+    walk.paint(one, PARENT1)?;
+    for other in others {
+        walk.paint(other, PARENT2)?;
+    }
+
+    while walk.nonstale > 0 {
+        let Some((_, sha)) = walk.queue.pop() else {
+            break;
+        };
+        walk.queued.remove(&sha);
+        let mut current = walk.flags[&sha];
+        if current & STALE == 0 {
+            walk.nonstale -= 1;
+        }
+
+        if current == PARENT1 | PARENT2 {
+            if !result.contains(&sha) {
+                result.push(sha.clone());
+            }
+            current |= STALE;
+            walk.flags.insert(sha.clone(), current);
+        }
+
+        let parents = walk.commit(&sha)?.1.clone();
+        for parent in parents {
+            walk.paint(&parent, current)?;
+        }
+    }
+
+    Ok(result)
+}
+
+/// State of `paint_down_to_common`: the flags painted so far, and the queue
+/// of commits whose flags still have to reach their parents.
+struct PaintWalk<'a> {
+    repo: &'a GitRepository,
+    /// Committer time and parents of every commit met, read once.
+    commits: HashMap<String, (i64, Vec<String>)>,
+    flags: HashMap<String, u8>,
+    queue: BinaryHeap<(i64, String)>,
+    /// Commits in `queue`, each queued at most once.
+    queued: HashSet<String>,
+    /// How many queued commits are not STALE; the walk ends at zero.
+    nonstale: usize,
+}
+
+impl PaintWalk<'_> {
+    fn commit(&mut self, sha: &str) -> Result<&(i64, Vec<String>)> {
+        if !self.commits.contains_key(sha) {
+            let commit = commit_read(self.repo, sha)?;
+            let info = (commit.committer_time(), commit.parents());
+            self.commits.insert(sha.to_string(), info);
+        }
+        Ok(&self.commits[sha])
+    }
+
+    /// Add `flag` to `sha`, queueing it unless it is queued already.
+    fn paint(&mut self, sha: &str, flag: u8) -> Result<()> {
+        let old = self.flags.get(sha).copied().unwrap_or_default();
+        let new = old | flag;
+        if new == old {
+            return Ok(());
+        }
+        self.flags.insert(sha.to_string(), new);
+
+        if self.queued.contains(sha) {
+            if old & STALE == 0 && new & STALE != 0 {
+                self.nonstale -= 1;
+            }
+        } else {
+            let time = self.commit(sha)?.0;
+            self.queue.push((time, sha.to_string()));
+            self.queued.insert(sha.to_string());
+            if new & STALE == 0 {
+                self.nonstale += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drop candidates that are ancestors of other candidates, keeping the rest
+/// newest first.
+fn remove_redundant(repo: &GitRepository, candidates: Vec<String>) -> Result<Vec<String>> {
+    let mut keep = Vec::new();
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        let mut redundant = false;
+        for (j, other) in candidates.iter().enumerate() {
+            if i != j && other != candidate && is_ancestor(repo, candidate, other)? {
+                redundant = true;
+                break;
+            }
+        }
+        if !redundant && !keep.contains(candidate) {
+            keep.push(candidate.clone());
+        }
+    }
+
+    let mut dated = Vec::with_capacity(keep.len());
+    for sha in keep {
+        dated.push((commit_read(repo, &sha)?.committer_time(), sha));
+    }
+    dated.sort_by_key(|(time, _)| Reverse(*time));
+    Ok(dated.into_iter().map(|(_, sha)| sha).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{
+        objects::{GitObjectType, object_hash},
+        repo::repo_tmp,
+    };
+
+    /// Write a commit with `parents`, committed at `time`.
+    fn commit(repo: &GitRepository, parents: &[&str], time: i64) -> String {
+        let mut data = String::from("tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n");
+        for parent in parents {
+            data.push_str(&format!("parent {parent}\n"));
+        }
+        data.push_str(&format!("author A <a@example.com> {time} +0000\n"));
+        data.push_str(&format!(
+            "committer A <a@example.com> {time} +0000\n\n{time}\n"
+        ));
+        object_hash(repo, data.into_bytes(), &GitObjectType::commit).unwrap()
+    }
+
+    #[test]
+    fn criss_cross_has_two_bases() {
+        let repo = repo_tmp("merge-base-criss-cross");
+        //   a1 - a2
+        //  /  \ /
+        // o    X
+        //  \  / \
+        //   b1 - b2
+        let o = commit(&repo, &[], 1);
+        let a1 = commit(&repo, &[&o], 2);
+        let b1 = commit(&repo, &[&o], 3);
+        let a2 = commit(&repo, &[&a1, &b1], 4);
+        let b2 = commit(&repo, &[&b1, &a1], 5);
+
+        let mut painted = paint_down_to_common(&repo, &a2, std::slice::from_ref(&b2)).unwrap();
+        painted.sort();
+        let mut expected = vec![a1.clone(), b1.clone()];
+        expected.sort();
+        assert_eq!(painted, expected);
+
+        // Newest first, and the older common ancestor is not a best base
+        assert_eq!(
+            merge_bases(&repo, &a2, &b2).unwrap(),
+            vec![b1.clone(), a1.clone()]
+        );
+        assert_eq!(merge_bases(&repo, &a1, &b1).unwrap(), vec![o.clone()]);
+        assert_eq!(merge_bases(&repo, &a2, &a1).unwrap(), vec![a1.clone()]);
+
+        assert!(is_ancestor(&repo, &o, &b2).unwrap());
+        assert!(!is_ancestor(&repo, &a2, &b2).unwrap());
+    }
+
+    #[test]
+    fn unrelated_histories_have_no_base() {
+        let repo = repo_tmp("merge-base-unrelated");
+        let a = commit(&repo, &[], 1);
+        let b = commit(&repo, &[], 2);
+        assert!(merge_bases(&repo, &a, &b).unwrap().is_empty());
+        assert!(
+            merge_bases_octopus(&repo, &[a.clone(), b])
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
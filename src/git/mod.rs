@@ -4,4 +4,17 @@ pub mod kvlm;
 pub mod tree;
 pub mod refs;
 pub mod index;
-pub mod ignore;
\ No newline at end of file
+pub mod ignore;
+pub mod delta;
+pub mod pack;
+pub mod status;
+pub mod diff;
+pub mod merge;
+pub mod worktree;
+pub mod reflog;
+pub mod revision;
+pub mod ident;
+pub mod revwalk;
+pub mod merge_base;
+pub mod hooks;
+pub mod lockfile;
\ No newline at end of file
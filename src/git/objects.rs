@@ -10,12 +10,15 @@ use std::fs;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::git::ident::Signature;
 use crate::git::kvlm::Kvlm;
 use crate::git::kvlm::kvlm_parse;
 use crate::git::kvlm::kvlm_serialize;
-use crate::git::refs::resolve_ref;
-use crate::git::refs::resolve_sha;
+use crate::git::pack::pack_contains;
+use crate::git::pack::pack_object_read;
+use crate::git::revision::revision_resolve;
 use crate::git::repo::GitRepository;
 use crate::git::tree::GitTree;
 
@@ -95,6 +98,35 @@ impl GitObject for GitCommit {
     }
 }
 
+impl GitCommit {
+    /// SHA of the commit's root tree.
+    pub fn tree(&self) -> Result<String> {
+        let tree = self.kvlm.get(b"tree").context("Commit has no tree header")?;
+        Ok(String::from_utf8(tree.to_vec())?)
+    }
+
+    /// SHAs of the commit's parents, in header order.
+    pub fn parents(&self) -> Vec<String> {
+        self.kvlm
+            .values(b"parent")
+            .map(|p| String::from_utf8_lossy(p).to_string())
+            .collect()
+    }
+
+    pub fn author(&self) -> Option<Signature> {
+        self.kvlm.get(b"author").and_then(Signature::parse)
+    }
+
+    pub fn committer(&self) -> Option<Signature> {
+        self.kvlm.get(b"committer").and_then(Signature::parse)
+    }
+
+    /// Commit time in seconds since the epoch (0 if the header is malformed).
+    pub fn committer_time(&self) -> i64 {
+        self.committer().map_or(0, |c| c.timestamp)
+    }
+}
+
 impl GitObject for GitTag {
     fn serialize(&self) -> Result<Vec<u8>> {
         Ok(kvlm_serialize(&self.kvlm))
@@ -114,75 +146,90 @@ impl GitObject for GitTag {
     }
 }
 
-/// Resolve a "name" (HEAD, branch, tag, SHA) to a full 40-hex SHA1.
+/// Resolve a "name" (HEAD, branch, tag, SHA or any revision expression such
+/// as `HEAD~2` or `v1.0^{tree}`) to a full 40-hex SHA1.
 pub fn object_resolve(repo: &GitRepository, name: &str) -> Result<String> {
-    if name.chars().all(|c| c.is_ascii_hexdigit()) && (4..=40).contains(&name.len()) {
-        return resolve_sha(repo, name);
-    }
-
-    match name {
-        "HEAD" => {
-            let head_path = repo.gitdir.join("HEAD");
-            let data = fs::read_to_string(&head_path)
-                .with_context(|| format!("Failed to read {:?}", head_path))?;
-
-            if data.starts_with("ref: ") {
-                let refname = data[5..].trim();
-                return resolve_ref(repo, refname);
-            } else {
-                return Ok(data.trim().to_string());
-            }
-        }
-        _ => {
-            let ref_path = repo.gitdir.join(name);
-            if ref_path.exists() {
-                let sha = fs::read_to_string(&ref_path)?.trim().to_string();
-                return Ok(sha);
-            }
-
-            for prefix in &["refs/heads", "refs/tags", "refs/remotes"] {
-                let ref_path = repo.gitdir.join(prefix).join(name);
-                if ref_path.exists() {
-                    let sha = fs::read_to_string(&ref_path)?.trim().to_string();
-                    return Ok(sha);
-                }
-            }
-        }
-    }
-
-    bail!("Not a valid object name: {name}")
+    revision_resolve(repo, name)
 }
 
 pub fn object_find(repo: &GitRepository, name: &str, fmt: Option<GitObjectType>) -> Result<String> {
     let sha = object_resolve(repo, name)?;
 
-    if let Some(expected) = fmt {
-        let (got_type, _) = object_read(&repo, &sha)?;
+    match fmt {
+        Some(_) => object_peel(repo, &sha, fmt),
+        None => Ok(sha),
+    }
+}
 
-        if got_type != expected {
-            bail!(
+/// Follow annotated tags (and commit -> tree) from `sha` until an object of
+/// the `expected` type is reached, or with no type until the first object
+/// that is not a tag.
+pub fn object_peel(
+    repo: &GitRepository,
+    sha: &str,
+    expected: Option<GitObjectType>,
+) -> Result<String> {
+    let mut sha = sha.to_string();
+
+    loop {
+        let (got_type, obj) = object_read(repo, &sha)?;
+        let done = match expected {
+            Some(expected) => got_type == expected,
+            None => got_type != GitObjectType::tag,
+        };
+        if done {
+            return Ok(sha);
+        }
+
+        let next = match got_type {
+            GitObjectType::tag => obj
+                .as_any()
+                .downcast_ref::<GitTag>()
+                .context("Failed to downcast to GitTag")?
+                .kvlm
+                .get(b"object")
+                .with_context(|| format!("Tag {sha} has no object header"))?
+                .to_vec(),
+            GitObjectType::commit if expected == Some(GitObjectType::tree) => obj
+                .as_any()
+                .downcast_ref::<GitCommit>()
+                .context("Failed to downcast to GitCommit")?
+                .kvlm
+                .get(b"tree")
+                .with_context(|| format!("Commit {sha} has no tree header"))?
+                .to_vec(),
+            // Without an expected type every non-tag has already been returned
+            _ => bail!(
                 "Object {} is not of expected type {:?}, got {:?}",
                 sha,
-                expected,
+                expected.unwrap_or(got_type),
                 got_type
-            );
-        }
+            ),
+        };
+
+        sha = String::from_utf8(next)?;
     }
+}
 
-    Ok(sha)
+/// Read the commit `sha`, failing if it is another kind of object.
+pub fn commit_read(repo: &GitRepository, sha: &str) -> Result<GitCommit> {
+    let (object_type, data) = object_read_raw(repo, sha)?;
+    if object_type != GitObjectType::commit {
+        bail!("Object {sha} is not a commit, got {:?}", object_type);
+    }
+    GitCommit::deserialize(&data)
 }
 
 pub fn object_hash(repo: &GitRepository, data: Vec<u8>, type_name: &GitObjectType) -> Result<String> {
-
-    let obj: Box<dyn GitObject> = match &type_name {
+    let obj: Box<dyn GitObject> = match type_name {
         GitObjectType::blob => Box::new(GitBlob::deserialize(&data)?),
         GitObjectType::commit => Box::new(GitCommit::deserialize(&data)?),
         GitObjectType::tree => Box::new(GitTree::deserialize(&data)?),
         GitObjectType::tag => Box::new(GitTag::deserialize(&data)?),
     };
 
-    return object_write(&repo, obj.as_ref(), &type_name, true);
-} 
+    object_write(repo, obj.as_ref(), type_name, true)
+}
 
 pub fn object_write(
     repo: &GitRepository,
@@ -199,10 +246,9 @@ pub fn object_write(
     let hash_bytes = hasher.finalize();
     let hash_hex = hex::encode(hash_bytes);
 
-    if write {
-        let dir_path = repo.gitdir.join("objects").join(&hash_hex[..2]);
-        let file_path = dir_path.join(&hash_hex[2..]);
-
+    let dir_path = repo.gitdir.join("objects").join(&hash_hex[..2]);
+    let file_path = dir_path.join(&hash_hex[2..]);
+    if write && !file_path.exists() && !pack_contains(repo, &hash_hex)? {
         fs::create_dir_all(&dir_path)
             .with_context(|| format!("Failed to create directory {:?}", dir_path))?;
 
@@ -210,17 +256,43 @@ pub fn object_write(
         encoder.write_all(&store_data)?;
         let compressed = encoder.finish()?;
 
-        let mut file = File::create(&file_path)
-            .with_context(|| format!("Failed to create file {:?}", file_path))?;
-        file.write_all(&compressed)?;
+        // Written to a temporary file and renamed, so readers never see a
+        // partial object
+        static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let tmp_path = dir_path.join(format!(
+            "tmp_obj_{}_{}",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = File::create_new(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(&compressed)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, &file_path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result.with_context(|| format!("Failed to write object {:?}", file_path))?;
     }
 
     Ok(hash_hex)
 }
 
-pub fn object_read(repo: &GitRepository, sha: &str) -> Result<(GitObjectType, Box<dyn GitObject>)> {
+/// Read an object's type and raw content, from a loose file or a packfile.
+pub fn object_read_raw(repo: &GitRepository, sha: &str) -> Result<(GitObjectType, Vec<u8>)> {
+    if sha.len() < 3 {
+        bail!("Not a valid object name: {sha}");
+    }
     let path = repo.gitdir.join("objects").join(&sha[..2]).join(&sha[2..]);
 
+    if !path.exists() {
+        if let Some(found) = pack_object_read(repo, sha)? {
+            return Ok(found);
+        }
+        bail!("Object {sha} not found in loose objects or packfiles");
+    }
+
     let compressed =
         fs::read(&path).with_context(|| format!("Failed to read object file at {:?}", path))?;
 
@@ -233,7 +305,6 @@ pub fn object_read(repo: &GitRepository, sha: &str) -> Result<(GitObjectType, Bo
         .position(|&b| b == 0)
         .context("Invalid object format: missing header null byte")?;
     let header = &decompressed[..null_pos];
-    let content = &decompressed[null_pos + 1..];
 
     let header_str = String::from_utf8_lossy(header);
     let mut header_parts = header_str.split_whitespace();
@@ -241,20 +312,37 @@ pub fn object_read(repo: &GitRepository, sha: &str) -> Result<(GitObjectType, Bo
         .next()
         .context("Invalid object header: missing type")?;
 
-    match type_name {
-        "blob" => {
-            let obj = GitBlob::deserialize(content)?;
+    let object_type = match type_name {
+        "blob" => GitObjectType::blob,
+        "commit" => GitObjectType::commit,
+        "tree" => GitObjectType::tree,
+        "tag" => GitObjectType::tag,
+        _ => bail!("Unknown object type: {}", type_name),
+    };
+
+    decompressed.drain(..=null_pos);
+    Ok((object_type, decompressed))
+}
+
+pub fn object_read(repo: &GitRepository, sha: &str) -> Result<(GitObjectType, Box<dyn GitObject>)> {
+    let (object_type, content) = object_read_raw(repo, sha)?;
+
+    match object_type {
+        GitObjectType::blob => {
+            let obj = GitBlob::deserialize(&content)?;
             Ok((GitObjectType::blob, Box::new(obj)))
         }
-        "commit" => {
-            let obj = GitCommit::deserialize(content)?;
+        GitObjectType::commit => {
+            let obj = GitCommit::deserialize(&content)?;
             Ok((GitObjectType::commit, Box::new(obj)))
         }
-        "tree" => {
-            let obj = GitTree::deserialize(content)?;
+        GitObjectType::tree => {
+            let obj = GitTree::deserialize(&content)?;
             Ok((GitObjectType::tree, Box::new(obj)))
         }
-        "tag" => Err(anyhow::anyhow!("Tag object not yet implemented")),
-        _ => Err(anyhow::anyhow!("Unknown object type: {}", type_name)),
+        GitObjectType::tag => {
+            let obj = GitTag::deserialize(&content)?;
+            Ok((GitObjectType::tag, Box::new(obj)))
+        }
     }
 }
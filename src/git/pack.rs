@@ -0,0 +1,763 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use flate2::{Compression, Crc, read::ZlibDecoder, write::ZlibEncoder};
+use sha1::{Digest, Sha1};
+
+use crate::git::{
+    delta::{delta_apply, delta_create},
+    objects::{GitObjectType, object_read_raw},
+    repo::GitRepository,
+};
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// A parsed pack index (v2) together with the path of its packfile.
+#[derive(Debug)]
+pub struct PackIndex {
+    pub pack_path: PathBuf,
+    fanout: [u32; 256],
+    shas: Vec<[u8; 20]>,
+    crcs: Vec<u32>,
+    offsets: Vec<u64>,
+    /// (offset, position) pairs sorted by offset, used to find where an entry ends.
+    by_offset: Vec<(u64, usize)>,
+}
+
+impl PackIndex {
+    pub fn read(idx_path: &Path) -> Result<Self> {
+        let data =
+            fs::read(idx_path).with_context(|| format!("Failed to read pack index {:?}", idx_path))?;
+        let mut r = &data[..];
+
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic != IDX_MAGIC {
+            bail!("Unsupported pack index (v1 or corrupt): {:?}", idx_path);
+        }
+        let version = r.read_u32::<BigEndian>()?;
+        if version != 2 {
+            bail!("Unsupported pack index version: {version}");
+        }
+
+        let mut fanout = [0u32; 256];
+        for slot in fanout.iter_mut() {
+            *slot = r.read_u32::<BigEndian>()?;
+        }
+        let count = fanout[255] as usize;
+
+        let mut shas = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut sha = [0u8; 20];
+            r.read_exact(&mut sha)?;
+            shas.push(sha);
+        }
+
+        let mut crcs = Vec::with_capacity(count);
+        for _ in 0..count {
+            crcs.push(r.read_u32::<BigEndian>()?);
+        }
+
+        let mut small = Vec::with_capacity(count);
+        for _ in 0..count {
+            small.push(r.read_u32::<BigEndian>()?);
+        }
+
+        // Offsets with the MSB set index into the 64-bit offset table
+        let large_count = small.iter().filter(|&&o| o & 0x8000_0000 != 0).count();
+        let mut large = Vec::with_capacity(large_count);
+        for _ in 0..large_count {
+            large.push(r.read_u64::<BigEndian>()?);
+        }
+
+        let mut offsets = Vec::with_capacity(count);
+        for o in small {
+            if o & 0x8000_0000 != 0 {
+                let i = (o & 0x7fff_ffff) as usize;
+                offsets.push(*large.get(i).context("Pack index: bad 64-bit offset index")?);
+            } else {
+                offsets.push(o as u64);
+            }
+        }
+
+        let mut by_offset: Vec<(u64, usize)> =
+            offsets.iter().enumerate().map(|(i, &o)| (o, i)).collect();
+        by_offset.sort_unstable();
+
+        Ok(PackIndex {
+            pack_path: idx_path.with_extension("pack"),
+            fanout,
+            shas,
+            crcs,
+            offsets,
+            by_offset,
+        })
+    }
+
+    /// Range of positions whose SHA starts with `first_byte`.
+    fn fanout_range(&self, first_byte: u8) -> (usize, usize) {
+        let start = if first_byte == 0 {
+            0
+        } else {
+            self.fanout[first_byte as usize - 1] as usize
+        };
+        (start, self.fanout[first_byte as usize] as usize)
+    }
+
+    /// Position of `sha` in this index, if present.
+    pub fn find(&self, sha: &[u8; 20]) -> Option<usize> {
+        let (start, end) = self.fanout_range(sha[0]);
+        self.shas[start..end]
+            .binary_search(sha)
+            .ok()
+            .map(|i| start + i)
+    }
+
+    /// Every object name stored in this index.
+    pub fn shas(&self) -> impl Iterator<Item = String> + '_ {
+        self.shas.iter().map(hex::encode)
+    }
+
+    /// All full SHAs in this index starting with the hex `prefix`.
+    pub fn find_prefix(&self, prefix: &str) -> Vec<String> {
+        let Some(first_byte) = prefix.get(..2).and_then(|p| u8::from_str_radix(p, 16).ok()) else {
+            return Vec::new();
+        };
+
+        let (start, end) = self.fanout_range(first_byte);
+        self.shas[start..end]
+            .iter()
+            .map(hex::encode)
+            .filter(|sha| sha.starts_with(prefix))
+            .collect()
+    }
+
+    /// Read the raw (still compressed) bytes of the entry at `pos`, checking its CRC32.
+    fn entry_raw(&self, pack: &mut File, pos: usize) -> Result<Vec<u8>> {
+        let start = self.offsets[pos];
+        let i = self
+            .by_offset
+            .binary_search(&(start, pos))
+            .map_err(|_| anyhow::anyhow!("Pack index: offset table inconsistent"))?;
+        let end = match self.by_offset.get(i + 1) {
+            Some(&(next, _)) => next,
+            None => pack.metadata()?.len().saturating_sub(20),
+        };
+        if end <= start {
+            bail!("Pack index: bad entry bounds at offset {start}");
+        }
+
+        let mut raw = vec![0u8; (end - start) as usize];
+        pack.seek(SeekFrom::Start(start))?;
+        pack.read_exact(&mut raw)?;
+
+        let mut crc = Crc::new();
+        crc.update(&raw);
+        if crc.sum() != self.crcs[pos] {
+            bail!(
+                "Pack entry {} in {:?} is corrupt (CRC mismatch)",
+                hex::encode(self.shas[pos]),
+                self.pack_path
+            );
+        }
+
+        Ok(raw)
+    }
+
+    /// Position of the entry starting at `offset` (used for OFS_DELTA bases).
+    fn position_at(&self, offset: u64) -> Result<usize> {
+        let i = self
+            .by_offset
+            .binary_search_by_key(&offset, |&(o, _)| o)
+            .map_err(|_| anyhow::anyhow!("Pack: no entry at offset {offset}"))?;
+        Ok(self.by_offset[i].1)
+    }
+
+    /// Read and fully resolve the object at `pos`.
+    fn read_at(
+        &self,
+        repo: &GitRepository,
+        pack: &mut File,
+        pos: usize,
+    ) -> Result<(GitObjectType, Vec<u8>)> {
+        let raw = self.entry_raw(pack, pos)?;
+
+        // Type and inflated size: 3 type bits + 4 size bits, then 7 size bits per byte
+        let mut p = 0usize;
+        let mut byte = *raw.first().context("Pack: truncated entry header")?;
+        p += 1;
+        let kind = (byte >> 4) & 0x07;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            if shift >= usize::BITS {
+                bail!("Pack: entry size does not fit in {} bits", usize::BITS);
+            }
+            byte = *raw.get(p).context("Pack: truncated entry header")?;
+            p += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        match kind {
+            OBJ_COMMIT | OBJ_TREE | OBJ_BLOB | OBJ_TAG => {
+                let data = inflate(&raw[p..], size)?;
+                Ok((pack_type(kind)?, data))
+            }
+            OBJ_OFS_DELTA => {
+                // Negative offset: big-endian base-128 with an implicit +1 per continuation
+                let mut byte = *raw.get(p).context("Pack: truncated delta offset")?;
+                p += 1;
+                let mut rel = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    if rel >> 57 != 0 {
+                        bail!("Pack: delta base offset out of range");
+                    }
+                    byte = *raw.get(p).context("Pack: truncated delta offset")?;
+                    p += 1;
+                    rel = ((rel + 1) << 7) | (byte & 0x7f) as u64;
+                }
+
+                let base_offset = self.offsets[pos]
+                    .checked_sub(rel)
+                    .context("Pack: delta base offset out of range")?;
+                let base_pos = self.position_at(base_offset)?;
+                let (base_type, base) = self.read_at(repo, pack, base_pos)?;
+
+                let delta = inflate(&raw[p..], size)?;
+                Ok((base_type, delta_apply(&base, &delta)?))
+            }
+            OBJ_REF_DELTA => {
+                let mut base_sha = [0u8; 20];
+                base_sha.copy_from_slice(raw.get(p..p + 20).context("Pack: truncated delta base")?);
+                p += 20;
+
+                let (base_type, base) = match self.find(&base_sha) {
+                    Some(base_pos) => self.read_at(repo, pack, base_pos)?,
+                    None => object_read_raw(repo, &hex::encode(base_sha))?,
+                };
+
+                let delta = inflate(&raw[p..], size)?;
+                Ok((base_type, delta_apply(&base, &delta)?))
+            }
+            other => bail!("Pack: unknown object type {other}"),
+        }
+    }
+}
+
+fn pack_type(kind: u8) -> Result<GitObjectType> {
+    match kind {
+        OBJ_COMMIT => Ok(GitObjectType::commit),
+        OBJ_TREE => Ok(GitObjectType::tree),
+        OBJ_BLOB => Ok(GitObjectType::blob),
+        OBJ_TAG => Ok(GitObjectType::tag),
+        other => bail!("Pack: {other} is not a base object type"),
+    }
+}
+
+fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(size);
+    ZlibDecoder::new(data).read_to_end(&mut out)?;
+    if out.len() != size {
+        bail!("Pack: inflated size mismatch (expected {size}, got {})", out.len());
+    }
+    Ok(out)
+}
+
+/// Every `*.idx` under `objects/pack`, read once per repository.
+pub fn pack_indexes(repo: &GitRepository) -> Result<&[PackIndex]> {
+    if let Some(indexes) = repo.packs.get() {
+        return Ok(indexes);
+    }
+    let indexes = pack_indexes_load(repo)?;
+    Ok(repo.packs.get_or_init(|| indexes))
+}
+
+fn pack_indexes_load(repo: &GitRepository) -> Result<Vec<PackIndex>> {
+    let pack_dir = repo.gitdir.join("objects").join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut indexes = Vec::new();
+    for entry in fs::read_dir(&pack_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").exists()
+        {
+            indexes.push(PackIndex::read(&path)?);
+        }
+    }
+
+    Ok(indexes)
+}
+
+/// Look up `sha` in every packfile, returning its type and inflated content.
+pub fn pack_object_read(
+    repo: &GitRepository,
+    sha: &str,
+) -> Result<Option<(GitObjectType, Vec<u8>)>> {
+    let mut raw = [0u8; 20];
+    if hex::decode_to_slice(sha, &mut raw).is_err() {
+        return Ok(None);
+    }
+
+    for index in pack_indexes(repo)? {
+        if let Some(pos) = index.find(&raw) {
+            let mut pack = File::open(&index.pack_path)
+                .with_context(|| format!("Failed to open pack {:?}", index.pack_path))?;
+            return index.read_at(repo, &mut pack, pos).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether any packfile holds `sha`.
+pub fn pack_contains(repo: &GitRepository, sha: &str) -> Result<bool> {
+    let mut raw = [0u8; 20];
+    if hex::decode_to_slice(sha, &mut raw).is_err() {
+        return Ok(false);
+    }
+    Ok(pack_indexes(repo)?
+        .iter()
+        .any(|index| index.find(&raw).is_some()))
+}
+
+/// All packed object SHAs starting with the hex `prefix`.
+pub fn pack_find_prefix(repo: &GitRepository, prefix: &str) -> Result<Vec<String>> {
+    let mut matches = Vec::new();
+    for index in pack_indexes(repo)? {
+        matches.extend(index.find_prefix(prefix));
+    }
+    Ok(matches)
+}
+
+/// An object queued for writing into a new packfile.
+pub struct PackEntry {
+    pub sha: String,
+    pub object_type: GitObjectType,
+    pub data: Vec<u8>,
+    /// Path the object was reached through, used to group similar objects.
+    pub path: String,
+}
+
+fn pack_type_number(object_type: GitObjectType) -> u8 {
+    match object_type {
+        GitObjectType::commit => OBJ_COMMIT,
+        GitObjectType::tree => OBJ_TREE,
+        GitObjectType::blob => OBJ_BLOB,
+        GitObjectType::tag => OBJ_TAG,
+    }
+}
+
+fn pack_entry_header(out: &mut Vec<u8>, kind: u8, mut size: usize) {
+    let mut byte = (kind << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+    while size != 0 {
+        out.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    out.push(byte);
+}
+
+fn pack_ofs_write(out: &mut Vec<u8>, mut ofs: u64) {
+    let mut buf = vec![(ofs & 0x7f) as u8];
+    ofs >>= 7;
+    while ofs != 0 {
+        ofs -= 1;
+        buf.push(0x80 | (ofs & 0x7f) as u8);
+        ofs >>= 7;
+    }
+    buf.reverse();
+    out.extend_from_slice(&buf);
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Sort key grouping objects likely to delta well against each other:
+/// same type, same file name, largest first.
+fn pack_sort_key(entry: &PackEntry) -> (u8, String, std::cmp::Reverse<usize>) {
+    let name = entry.path.rsplit('/').next().unwrap_or("").to_string();
+    (
+        pack_type_number(entry.object_type),
+        name,
+        std::cmp::Reverse(entry.data.len()),
+    )
+}
+
+/// Choose a delta base for each object from the `window` objects before it.
+/// Returns, per object, the index of its base and the encoded delta.
+fn pack_find_deltas(
+    objects: &[PackEntry],
+    window: usize,
+    max_depth: usize,
+) -> Vec<Option<(usize, Vec<u8>)>> {
+    let mut bases: Vec<Option<(usize, Vec<u8>)>> = Vec::with_capacity(objects.len());
+    let mut depths = vec![0usize; objects.len()];
+
+    for (i, target) in objects.iter().enumerate() {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+
+        for j in i.saturating_sub(window)..i {
+            let base = &objects[j];
+            if base.object_type != target.object_type || depths[j] >= max_depth {
+                continue;
+            }
+            // Don't bother with bases wildly smaller than the target
+            if base.data.len() < target.data.len() / 32 {
+                continue;
+            }
+
+            let limit = match &best {
+                Some((_, delta)) => delta.len(),
+                None => target.data.len() / 2,
+            };
+            let delta = delta_create(&base.data, &target.data);
+            if delta.len() < limit {
+                best = Some((j, delta));
+            }
+        }
+
+        if let Some((j, _)) = &best {
+            depths[i] = depths[*j] + 1;
+        }
+        bases.push(best);
+    }
+
+    bases
+}
+
+/// Write `objects` as a new delta-compressed packfile plus a v2 index under
+/// `objects/pack`. Returns the pack checksum (the `pack-<sha>` name) and the
+/// number of objects stored as deltas.
+pub fn pack_write(
+    repo: &GitRepository,
+    mut objects: Vec<PackEntry>,
+    window: usize,
+    max_depth: usize,
+) -> Result<(String, usize)> {
+    objects.sort_by_cached_key(pack_sort_key);
+    let deltas = pack_find_deltas(&objects, window, max_depth);
+
+    let mut pack = Vec::new();
+    pack.extend_from_slice(b"PACK");
+    pack.write_u32::<BigEndian>(2)?;
+    pack.write_u32::<BigEndian>(objects.len() as u32)?;
+
+    let mut offsets = Vec::with_capacity(objects.len());
+    let mut crcs = Vec::with_capacity(objects.len());
+    let mut delta_count = 0usize;
+
+    // Bases always precede their deltas, so every delta can be an OFS_DELTA
+    for (i, obj) in objects.iter().enumerate() {
+        let offset = pack.len() as u64;
+        let mut entry = Vec::new();
+
+        match &deltas[i] {
+            Some((base, delta)) => {
+                pack_entry_header(&mut entry, OBJ_OFS_DELTA, delta.len());
+                pack_ofs_write(&mut entry, offset - offsets[*base]);
+                entry.extend_from_slice(&deflate(delta)?);
+                delta_count += 1;
+            }
+            None => {
+                pack_entry_header(&mut entry, pack_type_number(obj.object_type), obj.data.len());
+                entry.extend_from_slice(&deflate(&obj.data)?);
+            }
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        crcs.push(crc.sum());
+        offsets.push(offset);
+        pack.extend_from_slice(&entry);
+    }
+
+    let mut entries = Vec::with_capacity(objects.len());
+    for (i, obj) in objects.iter().enumerate() {
+        let mut raw = [0u8; 20];
+        hex::decode_to_slice(&obj.sha, &mut raw)
+            .with_context(|| format!("Invalid object name {}", obj.sha))?;
+        entries.push((raw, offsets[i], crcs[i]));
+    }
+
+    let name = pack_store(repo, pack, entries)?;
+    Ok((name, delta_count))
+}
+
+/// Finish `pack` (header and entries) with its checksum and write it under
+/// `objects/pack` along with a v2 index of `entries`, given as (object name,
+/// offset, CRC32 of the raw entry). Returns the pack checksum.
+fn pack_store(
+    repo: &GitRepository,
+    mut pack: Vec<u8>,
+    mut entries: Vec<([u8; 20], u64, u32)>,
+) -> Result<String> {
+    let pack_sha: [u8; 20] = Sha1::digest(&pack).into();
+    pack.extend_from_slice(&pack_sha);
+
+    // Index: everything sorted by object name
+    entries.sort_by_key(|e| e.0);
+
+    let mut idx = Vec::new();
+    idx.extend_from_slice(&IDX_MAGIC);
+    idx.write_u32::<BigEndian>(2)?;
+
+    let mut fanout = [0u32; 256];
+    for (raw, _, _) in &entries {
+        fanout[raw[0] as usize] += 1;
+    }
+    let mut running = 0u32;
+    for count in fanout.iter_mut() {
+        running += *count;
+        *count = running;
+    }
+    for count in fanout {
+        idx.write_u32::<BigEndian>(count)?;
+    }
+
+    for (raw, _, _) in &entries {
+        idx.extend_from_slice(raw);
+    }
+    for (_, _, crc) in &entries {
+        idx.write_u32::<BigEndian>(*crc)?;
+    }
+
+    let mut large = Vec::new();
+    for &(_, offset, _) in &entries {
+        if offset < 0x8000_0000 {
+            idx.write_u32::<BigEndian>(offset as u32)?;
+        } else {
+            idx.write_u32::<BigEndian>(0x8000_0000 | large.len() as u32)?;
+            large.push(offset);
+        }
+    }
+    for offset in large {
+        idx.write_u64::<BigEndian>(offset)?;
+    }
+
+    idx.extend_from_slice(&pack_sha);
+    let idx_sha: [u8; 20] = Sha1::digest(&idx).into();
+    idx.extend_from_slice(&idx_sha);
+
+    let name = hex::encode(pack_sha);
+    let pack_dir = repo.gitdir.join("objects").join("pack");
+    fs::create_dir_all(&pack_dir)
+        .with_context(|| format!("Failed to create directory {:?}", pack_dir))?;
+
+    // The pack must be complete before its index makes it visible
+    let pack_path = pack_dir.join(format!("pack-{name}.pack"));
+    let idx_path = pack_dir.join(format!("pack-{name}.idx"));
+    for (path, data) in [(&pack_path, &pack), (&idx_path, &idx)] {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data).with_context(|| format!("Failed to write {:?}", tmp))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to write {:?}", path))?;
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{
+        objects::{GitBlob, object_hash, object_write},
+        repo::repo_tmp,
+    };
+
+    fn blob_sha(repo: &GitRepository, data: &[u8]) -> String {
+        let blob = GitBlob {
+            data: data.to_vec(),
+        };
+        object_write(repo, &blob, &GitObjectType::blob, false).unwrap()
+    }
+
+    fn blob_entry(repo: &GitRepository, data: &[u8]) -> PackEntry {
+        PackEntry {
+            sha: blob_sha(repo, data),
+            object_type: GitObjectType::blob,
+            data: data.to_vec(),
+            path: "file.txt".to_string(),
+        }
+    }
+
+    /// Append one raw entry to `pack`, recording it for the index.
+    fn entry_push(
+        pack: &mut Vec<u8>,
+        entries: &mut Vec<([u8; 20], u64, u32)>,
+        sha: &str,
+        entry: Vec<u8>,
+    ) {
+        let mut raw = [0u8; 20];
+        hex::decode_to_slice(sha, &mut raw).unwrap();
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        entries.push((raw, pack.len() as u64, crc.sum()));
+        pack.extend_from_slice(&entry);
+    }
+
+    #[test]
+    fn idx_lookup_and_ofs_delta() {
+        let repo = repo_tmp("pack-idx");
+        let base = "line\n".repeat(200);
+        let changed = format!("{base}one more line\n");
+        let objects = vec![
+            blob_entry(&repo, base.as_bytes()),
+            blob_entry(&repo, changed.as_bytes()),
+            blob_entry(&repo, b"unrelated"),
+        ];
+        let shas: Vec<String> = objects.iter().map(|o| o.sha.clone()).collect();
+
+        let (name, deltas) = pack_write(&repo, objects, 10, 50).unwrap();
+        assert_eq!(deltas, 1);
+
+        let idx_path = repo
+            .gitdir
+            .join("objects/pack")
+            .join(format!("pack-{name}.idx"));
+        let index = PackIndex::read(&idx_path).unwrap();
+        let mut listed: Vec<String> = index.shas().collect();
+        listed.sort();
+        let mut expected = shas.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        for sha in &shas {
+            let mut raw = [0u8; 20];
+            hex::decode_to_slice(sha, &mut raw).unwrap();
+            assert!(index.find(&raw).is_some());
+            assert_eq!(index.find_prefix(&sha[..6]), vec![sha.clone()]);
+        }
+        assert_eq!(index.find(&[0xab; 20]), None);
+
+        let (kind, data) = pack_object_read(&repo, &shas[1]).unwrap().unwrap();
+        assert_eq!(kind, GitObjectType::blob);
+        assert_eq!(data, changed.as_bytes());
+        assert_eq!(
+            pack_find_prefix(&repo, &shas[0][..4]).unwrap(),
+            vec![shas[0].clone()]
+        );
+    }
+
+    #[test]
+    fn corrupt_entry_headers_are_rejected() {
+        let repo = repo_tmp("pack-corrupt-header");
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK");
+        pack.write_u32::<BigEndian>(2).unwrap();
+        pack.write_u32::<BigEndian>(2).unwrap();
+        let mut entries = Vec::new();
+
+        // A size with more continuation bytes than fit in a usize
+        let mut entry = vec![0xb0];
+        entry.extend_from_slice(&[0xff; 12]);
+        entry.push(0x01);
+        entry_push(&mut pack, &mut entries, &"1".repeat(40), entry);
+
+        // An OFS_DELTA offset too large for 64 bits
+        let mut entry = vec![0x65];
+        entry.extend_from_slice(&[0xff; 12]);
+        entry.push(0x01);
+        entry_push(&mut pack, &mut entries, &"2".repeat(40), entry);
+
+        pack_store(&repo, pack, entries).unwrap();
+        assert!(pack_object_read(&repo, &"1".repeat(40)).is_err());
+        assert!(pack_object_read(&repo, &"2".repeat(40)).is_err());
+    }
+
+    #[test]
+    fn ref_delta_in_pack_and_loose() {
+        let repo = repo_tmp("pack-ref-delta");
+        let base = b"base content shared by both deltas\n".repeat(20);
+        let loose = b"a loose base object\n".repeat(20);
+        let target = [&base[..], b"tail\n"].concat();
+        let loose_target = [b"head\n", &loose[..]].concat();
+
+        let base_sha = blob_sha(&repo, &base);
+        let loose_sha = object_hash(&repo, loose.clone(), &GitObjectType::blob).unwrap();
+        let target_sha = blob_sha(&repo, &target);
+        let loose_target_sha = blob_sha(&repo, &loose_target);
+
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK");
+        pack.write_u32::<BigEndian>(2).unwrap();
+        pack.write_u32::<BigEndian>(3).unwrap();
+        let mut entries = Vec::new();
+
+        let mut entry = Vec::new();
+        pack_entry_header(&mut entry, OBJ_BLOB, base.len());
+        entry.extend_from_slice(&deflate(&base).unwrap());
+        entry_push(&mut pack, &mut entries, &base_sha, entry);
+
+        for (base_sha, base, target_sha, target) in [
+            (&base_sha, &base, &target_sha, &target),
+            (&loose_sha, &loose, &loose_target_sha, &loose_target),
+        ] {
+            let delta = delta_create(base, target);
+            let mut entry = Vec::new();
+            pack_entry_header(&mut entry, OBJ_REF_DELTA, delta.len());
+            entry.extend_from_slice(&hex::decode(base_sha).unwrap());
+            entry.extend_from_slice(&deflate(&delta).unwrap());
+            entry_push(&mut pack, &mut entries, target_sha, entry);
+        }
+        pack_store(&repo, pack, entries).unwrap();
+        // Packs are listed once per repository, and writing the loose base
+        // already did that
+        let repo = GitRepository::new(&repo.worktree, false).unwrap();
+
+        let (_, data) = pack_object_read(&repo, &target_sha).unwrap().unwrap();
+        assert_eq!(data, target);
+        let (_, data) = pack_object_read(&repo, &loose_target_sha).unwrap().unwrap();
+        assert_eq!(data, loose_target);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let repo = repo_tmp("pack-round-trip");
+        let tree = b"100644 file.txt\0aaaaaaaaaaaaaaaaaaaa".to_vec();
+        let mut objects = vec![PackEntry {
+            sha: object_hash(&repo, tree.clone(), &GitObjectType::tree).unwrap(),
+            object_type: GitObjectType::tree,
+            data: tree,
+            path: String::new(),
+        }];
+        // A chain of versions, each a small edit of the previous one
+        let mut text = "first line\n".repeat(100);
+        for i in 0..8 {
+            text.push_str(&format!("version {i}\n"));
+            objects.push(blob_entry(&repo, text.as_bytes()));
+        }
+        let expected: Vec<(String, GitObjectType, Vec<u8>)> = objects
+            .iter()
+            .map(|o| (o.sha.clone(), o.object_type, o.data.clone()))
+            .collect();
+
+        // Depth 2 forces some versions to be stored whole
+        let (_, deltas) = pack_write(&repo, objects, 10, 2).unwrap();
+        assert!(deltas > 0 && deltas < 8);
+
+        // Read through a fresh handle, with the loose copies gone
+        fs::remove_dir_all(repo.gitdir.join("objects").join(&expected[0].0[..2])).unwrap();
+        let repo = GitRepository::new(&repo.worktree, false).unwrap();
+        for (sha, object_type, data) in expected {
+            assert_eq!(object_read_raw(&repo, &sha).unwrap(), (object_type, data));
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use crate::git::{
+    ident::{IdentRole, Signature, date_now, ident_default},
+    lockfile::{LockFile, lockfile_write},
+    repo::GitRepository,
+};
+
+pub const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+
+/// One line of a reflog: `<old> <new> <ident> <timestamp> <tz>\t<message>`.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub ident: String,
+    pub timestamp: i64,
+    pub tz: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> Option<Self> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = head.split_once(' ')?;
+        let (new, rest) = rest.split_once(' ')?;
+        // The identity may contain spaces; timestamp and tz are the last two fields
+        let (rest, tz) = rest.rsplit_once(' ')?;
+        let (ident, timestamp) = rest.rsplit_once(' ')?;
+
+        Some(Self {
+            old: old.to_string(),
+            new: new.to_string(),
+            ident: ident.to_string(),
+            timestamp: timestamp.parse().ok()?,
+            tz: tz.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    fn serialize(&self) -> String {
+        format!(
+            "{} {} {} {} {}\t{}\n",
+            self.old, self.new, self.ident, self.timestamp, self.tz, self.message
+        )
+    }
+}
+
+fn reflog_path(repo: &GitRepository, refname: &str) -> PathBuf {
+    repo.gitdir.join("logs").join(refname)
+}
+
+/// Whether updates to `refname` are logged: HEAD, branches and remote-tracking
+/// refs always are, anything else only once it already has a reflog.
+fn reflog_wanted(repo: &GitRepository, refname: &str) -> bool {
+    refname == "HEAD"
+        || refname.starts_with("refs/heads/")
+        || refname.starts_with("refs/remotes/")
+        || reflog_path(repo, refname).exists()
+}
+
+/// Append a reflog line recording that `refname` moved from `old` to `new`.
+/// Appends are serialised by `lock`, which must be the lock held on `refname`.
+pub fn reflog_append(
+    repo: &GitRepository,
+    lock: &LockFile,
+    refname: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    debug_assert_eq!(lock.path(), repo.gitdir.join(refname));
+    if !reflog_wanted(repo, refname) {
+        return Ok(());
+    }
+    let entry = reflog_entry(repo, old, new, message);
+
+    let path = reflog_path(repo, refname);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open reflog {:?}", path))?;
+    file.write_all(entry.serialize().as_bytes())?;
+
+    Ok(())
+}
+
+/// Like `reflog_append`, but the log with the new line is only written to
+/// `logs/<refname>.lock`; committing the returned lock applies it. `None`
+/// when `refname` is not logged.
+pub fn reflog_append_staged(
+    repo: &GitRepository,
+    lock: &LockFile,
+    refname: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> Result<Option<LockFile>> {
+    debug_assert_eq!(lock.path(), repo.gitdir.join(refname));
+    if !reflog_wanted(repo, refname) {
+        return Ok(None);
+    }
+
+    let path = reflog_path(repo, refname);
+    let mut log_lock = LockFile::acquire(&path)?;
+    if path.exists() {
+        let data = fs::read(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        log_lock.write_all(&data)?;
+    }
+    log_lock.write_all(reflog_entry(repo, old, new, message).serialize().as_bytes())?;
+    Ok(Some(log_lock))
+}
+
+/// The reflog line for a move of a ref from `old` to `new`, by the committer.
+fn reflog_entry(repo: &GitRepository, old: Option<&str>, new: &str, message: &str) -> ReflogEntry {
+    let committer = ident_default(repo, IdentRole::Committer).unwrap_or_else(|_| {
+        let (timestamp, tz) = date_now();
+        Signature {
+            name: "unknown".to_string(),
+            email: "unknown".to_string(),
+            timestamp,
+            tz,
+        }
+    });
+
+    ReflogEntry {
+        old: old.unwrap_or(NULL_SHA).to_string(),
+        new: new.to_string(),
+        ident: committer.ident(),
+        timestamp: committer.timestamp,
+        tz: committer.tz,
+        // A reflog message is a single line
+        message: message.lines().next().unwrap_or_default().to_string(),
+    }
+}
+
+/// Entries of the reflog of `refname`, oldest first (empty if there is none).
+pub fn reflog_read(repo: &GitRepository, refname: &str) -> Result<Vec<ReflogEntry>> {
+    let path = reflog_path(repo, refname);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(data.lines().filter_map(ReflogEntry::parse).collect())
+}
+
+/// Replace the reflog of `refname` with `entries`. `lock` must be the lock
+/// held on `refname`, taken before the entries were read, so that no append
+/// is lost.
+pub fn reflog_write(
+    repo: &GitRepository,
+    lock: &LockFile,
+    refname: &str,
+    entries: &[ReflogEntry],
+) -> Result<()> {
+    debug_assert_eq!(lock.path(), repo.gitdir.join(refname));
+    let path = reflog_path(repo, refname);
+    let data: String = entries.iter().map(ReflogEntry::serialize).collect();
+    lockfile_write(&path, data.as_bytes())
+}
+
+/// Remove the reflog of `refname`, if any.
+pub fn reflog_delete(repo: &GitRepository, refname: &str) -> Result<()> {
+    let path = reflog_path(repo, refname);
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+/// Move the reflog of `old` to `new` (used when renaming a branch).
+pub fn reflog_rename(repo: &GitRepository, old: &str, new: &str) -> Result<()> {
+    let from = reflog_path(repo, old);
+    if !from.exists() {
+        return Ok(());
+    }
+    let to = reflog_path(repo, new);
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)?;
+    Ok(())
+}
+
+/// The value `refname` had `n` moves ago (`<ref>@{n}`).
+pub fn reflog_nth(repo: &GitRepository, refname: &str, n: usize) -> Result<String> {
+    let entries = reflog_read(repo, refname)?;
+    match entries.iter().rev().nth(n) {
+        Some(entry) => Ok(entry.new.clone()),
+        None => bail!("log for '{refname}' only has {} entries", entries.len()),
+    }
+}
+
+/// The value `refname` had at time `timestamp` (`<ref>@{<date>}`).
+pub fn reflog_at(repo: &GitRepository, refname: &str, timestamp: i64) -> Result<String> {
+    let entries = reflog_read(repo, refname)?;
+    let Some(first) = entries.first() else {
+        bail!("log for '{refname}' is empty");
+    };
+
+    match entries.iter().rev().find(|e| e.timestamp <= timestamp) {
+        Some(entry) => Ok(entry.new.clone()),
+        // Before the log starts: the value the oldest entry replaced
+        None if first.old != NULL_SHA => Ok(first.old.clone()),
+        None => Ok(first.new.clone()),
+    }
+}
@@ -1,8 +1,14 @@
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fs, path::Path};
 
 use anyhow::{Context, Result, bail};
 
-use crate::git::repo::GitRepository;
+use crate::git::{
+    lockfile::LockFile,
+    objects::object_read_raw,
+    pack::pack_find_prefix,
+    reflog::{NULL_SHA, reflog_append, reflog_append_staged, reflog_delete},
+    repo::GitRepository,
+};
 
 /// Expand abbreviated SHA by searching objects
 pub fn resolve_sha(repo: &GitRepository, short: &str) -> Result<String> {
@@ -10,18 +16,27 @@ pub fn resolve_sha(repo: &GitRepository, short: &str) -> Result<String> {
         return Ok(short.to_string());
     }
 
-    let dir = repo.gitdir.join("objects").join(&short[..2]);
-    if !dir.exists() {
-        bail!("No objects found for prefix {short}");
-    }
+    let short = short.to_ascii_lowercase();
+    let short = short.as_str();
 
     let mut matches = Vec::new();
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let name = entry.file_name().into_string().unwrap();
 
-        let candidate = format!("{}{}", &short[..2], name);
-        if candidate.starts_with(short) {
+    let dir = repo.gitdir.join("objects").join(&short[..2]);
+    if dir.exists() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().unwrap();
+
+            let candidate = format!("{}{}", &short[..2], name);
+            if candidate.starts_with(short) {
+                matches.push(candidate);
+            }
+        }
+    }
+
+    // Objects may also live in packfiles (and may be duplicated between the two)
+    for candidate in pack_find_prefix(repo, short)? {
+        if !matches.contains(&candidate) {
             matches.push(candidate);
         }
     }
@@ -33,46 +48,572 @@ pub fn resolve_sha(repo: &GitRepository, short: &str) -> Result<String> {
     }
 }
 
-/// Resolve a symbolic ref like "refs/heads/main"
+/// A ref stored in `.git/packed-refs`, with the object an annotated tag
+/// ultimately points to when known.
+#[derive(Debug, Clone)]
+pub struct PackedRef {
+    pub name: String,
+    pub sha: String,
+    pub peeled: Option<String>,
+}
+
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+/// Parse `.git/packed-refs` (empty if the file does not exist).
+pub fn packed_refs_read(repo: &GitRepository) -> Result<Vec<PackedRef>> {
+    let path = repo.gitdir.join("packed-refs");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let data = fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+    let mut refs: Vec<PackedRef> = Vec::new();
+
+    for line in data.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(peeled) = line.strip_prefix('^') {
+            let Some(last) = refs.last_mut() else {
+                bail!("packed-refs: peel line without a ref");
+            };
+            last.peeled = Some(peeled.trim().to_string());
+            continue;
+        }
+
+        let Some((sha, name)) = line.split_once(' ') else {
+            bail!("packed-refs: malformed line {line:?}");
+        };
+        refs.push(PackedRef {
+            name: name.trim().to_string(),
+            sha: sha.to_string(),
+            peeled: None,
+        });
+    }
+
+    Ok(refs)
+}
+
+/// Lock `.git/packed-refs`; take it before reading the refs to be rewritten.
+pub fn packed_refs_lock(repo: &GitRepository) -> Result<LockFile> {
+    LockFile::acquire(&repo.gitdir.join("packed-refs"))
+}
+
+/// Replace `.git/packed-refs` with `refs` (sorted by name) through its lock.
+pub fn packed_refs_write(mut lock: LockFile, refs: &[PackedRef]) -> Result<()> {
+    lock.write_all(packed_refs_serialize(refs).as_bytes())?;
+    lock.commit()
+}
+
+/// The contents of a packed-refs file holding `refs`, sorted by name.
+fn packed_refs_serialize(refs: &[PackedRef]) -> String {
+    let mut refs = refs.to_vec();
+    refs.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out = String::from(PACKED_REFS_HEADER);
+    for r in &refs {
+        out.push_str(&format!("{} {}\n", r.sha, r.name));
+        if let Some(peeled) = &r.peeled {
+            out.push_str(&format!("^{peeled}\n"));
+        }
+    }
+    out
+}
+
+/// Resolve a ref like "refs/heads/main" to a SHA, looking at the loose ref
+/// file first and `.git/packed-refs` second. Symbolic refs are followed.
 pub fn resolve_ref(repo: &GitRepository, refname: &str) -> Result<String> {
     let ref_path = repo.gitdir.join(refname);
-    if ref_path.exists() {
-        let sha = fs::read_to_string(&ref_path)?.trim().to_string();
-        Ok(sha)
-    } else {
-        bail!("Invalid ref: {refname}")
+    if ref_path.is_file() {
+        let data = fs::read_to_string(&ref_path)?.trim().to_string();
+        return match data.strip_prefix("ref: ") {
+            Some(target) => resolve_ref(repo, target.trim()),
+            None => Ok(data),
+        };
+    }
+
+    match packed_refs_read(repo)?.into_iter().find(|r| r.name == refname) {
+        Some(r) => Ok(r.sha),
+        None => bail!("Invalid ref: {refname}"),
+    }
+}
+
+/// Expand a ref name as typed by a user to the full ref it names, trying in
+/// git's order `$GIT_DIR/<name>`, `refs/<name>`, `refs/tags/<name>`,
+/// `refs/heads/<name>`, `refs/remotes/<name>` and `refs/remotes/<name>/HEAD`.
+/// Names matching more than one ref are an error.
+pub fn ref_dwim(repo: &GitRepository, name: &str) -> Result<Option<String>> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    // Only pseudo-refs (HEAD, ORIG_HEAD...) and full ref names live directly in $GIT_DIR
+    let top_level = name.starts_with("refs/")
+        || name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+
+    let mut candidates = Vec::new();
+    if top_level {
+        candidates.push(name.to_string());
+    }
+    candidates.extend([
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ]);
+
+    let matches: Vec<String> = candidates
+        .into_iter()
+        .filter(|refname| resolve_ref(repo, refname).is_ok())
+        .collect();
+
+    match matches.as_slice() {
+        [] => Ok(None),
+        [refname] => Ok(Some(refname.clone())),
+        _ => bail!(
+            "refname '{name}' is ambiguous, it could be any of: {}",
+            matches.join(", ")
+        ),
+    }
+}
+
+/// Every ref below `prefix` (e.g. "refs" or "refs/heads") as `(sha, refname)`,
+/// merging loose and packed refs (loose ones win) and sorted by name.
+pub fn collect_refs(repo: &GitRepository, prefix: &str) -> Result<Vec<(String, String)>> {
+    let mut refs: BTreeMap<String, String> = BTreeMap::new();
+
+    for r in packed_refs_read(repo)? {
+        if r.name.starts_with(&format!("{prefix}/")) {
+            refs.insert(r.name, r.sha);
+        }
+    }
+
+    let base = repo.gitdir.join(prefix);
+    if base.is_dir() {
+        for name in collect_loose_refs(&base, prefix)? {
+            // Dangling symbolic refs are skipped
+            if let Ok(sha) = resolve_ref(repo, &name) {
+                refs.insert(name, sha);
+            }
+        }
     }
+
+    Ok(refs.into_iter().map(|(name, sha)| (sha, name)).collect())
 }
 
-pub fn collect_refs(base: &Path, prefix: &str) -> Result<Vec<(String, String)>> {
+fn collect_loose_refs(base: &Path, prefix: &str) -> Result<Vec<String>> {
     let mut refs = Vec::new();
 
     for entry in fs::read_dir(base)? {
         let entry = entry?;
         let path = entry.path();
+        let refname = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
 
         if path.is_dir() {
-            let subprefix = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
-            refs.extend(collect_refs(&path, &subprefix)?);
-        } else {
-            let sha = fs::read_to_string(&path)
-                .with_context(|| format!("Failed to read {:?}", path))?
-                .trim()
-                .to_string();
-            let refname = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
-            refs.push((sha, refname));
+            refs.extend(collect_loose_refs(&path, &refname)?);
+        } else if !refname.ends_with(".lock") {
+            refs.push(refname);
         }
     }
 
     Ok(refs)
 }
 
-pub fn ref_create(repo: &GitRepository, ref_name: &str, sha: &str) -> Result<()> {
+/// Point `ref_name` at `sha`, recording the move in its reflog (and in HEAD's
+/// when HEAD is a symbolic ref to it) with `message`.
+pub fn ref_create(repo: &GitRepository, ref_name: &str, sha: &str, message: &str) -> Result<()> {
+    // The ref is locked before its old value is read, and the reflog is
+    // written before the new value becomes visible
+    let mut lock = LockFile::acquire(&repo.gitdir.join(ref_name))?;
+    let old = resolve_ref(repo, ref_name).ok();
+    lock.write_all(format!("{sha}\n").as_bytes())?;
+
+    reflog_append(repo, &lock, ref_name, old.as_deref(), sha, message)?;
+    head_reflog_append(repo, ref_name, old.as_deref(), sha, message)?;
+    lock.commit()
+}
+
+/// Log the move of `target` in HEAD's reflog too when HEAD is a symbolic ref
+/// to it, holding HEAD's lock while appending.
+fn head_reflog_append(
+    repo: &GitRepository,
+    target: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    if let Some(lock) = head_lock_for(repo, target)? {
+        reflog_append(repo, &lock, "HEAD", old, new, message)?;
+    }
+    Ok(())
+}
+
+/// HEAD's lock when HEAD is a symbolic ref to `target`, so that its reflog
+/// can be written too.
+fn head_lock_for(repo: &GitRepository, target: &str) -> Result<Option<LockFile>> {
+    // Checked before locking, so a detached HEAD locked elsewhere in the same
+    // transaction is left alone
+    if target == "HEAD" || head_symbolic(repo)?.as_deref() != Some(target) {
+        return Ok(None);
+    }
+    let lock = LockFile::acquire(&repo.gitdir.join("HEAD"))?;
+    if head_symbolic(repo)?.as_deref() != Some(target) {
+        return Ok(None);
+    }
+    Ok(Some(lock))
+}
+
+/// The ref HEAD points to, or `None` when HEAD is detached.
+pub fn head_symbolic(repo: &GitRepository) -> Result<Option<String>> {
+    let path = repo.gitdir.join("HEAD");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read_to_string(&path)?;
+    Ok(data.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+}
+
+/// Make HEAD a symbolic ref to `ref_name`, logging the move in HEAD's reflog.
+pub fn head_set_symbolic(repo: &GitRepository, ref_name: &str, message: &str) -> Result<()> {
+    let mut lock = LockFile::acquire(&repo.gitdir.join("HEAD"))?;
+    let old = resolve_ref(repo, "HEAD").ok();
+    lock.write_all(format!("ref: {ref_name}\n").as_bytes())?;
+
+    if let Ok(new) = resolve_ref(repo, ref_name) {
+        reflog_append(repo, &lock, "HEAD", old.as_deref(), &new, message)?;
+    }
+    lock.commit()
+}
+
+/// Delete a ref, both its loose file and its `.git/packed-refs` entry.
+pub fn ref_delete(repo: &GitRepository, ref_name: &str) -> Result<()> {
     let path = repo.gitdir.join(ref_name);
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+    // The loose ref is locked before packed-refs, like in a transaction
+    let _lock = LockFile::acquire(&path)?;
+    let loose = path.is_file();
+
+    let packed_lock = packed_refs_lock(repo)?;
+    let mut packed = packed_refs_read(repo)?;
+    let before = packed.len();
+    packed.retain(|r| r.name != ref_name);
+    let in_packed = packed.len() != before;
+
+    if !loose && !in_packed {
+        bail!("Invalid ref: {ref_name}");
+    }
+
+    if in_packed {
+        packed_refs_write(packed_lock, &packed)?;
+    }
+    if loose {
+        fs::remove_file(&path).with_context(|| format!("Failed to delete ref {:?}", path))?;
+    }
+    reflog_delete(repo, ref_name)
+}
+
+/// Whether `name` is acceptable as a ref component (a subset of git's check-ref-format).
+pub fn ref_name_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && name != "@"
+        && !name.split('/').any(|part| part.starts_with('.'))
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+}
+
+/// One change in a `RefTransaction`. A `new` value of `NULL_SHA` deletes the
+/// ref and `None` only verifies it; an `old` value of `NULL_SHA` requires the
+/// ref not to exist and `None` accepts whatever it points to.
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    pub refname: String,
+    pub new: Option<String>,
+    pub old: Option<String>,
+    pub message: String,
+}
+
+/// A batch of ref updates applied all together or not at all. `prepare`
+/// locks every ref (following symbolic refs), and packed-refs when a ref is
+/// deleted, and checks the old values; `commit` then writes them. Dropping a
+/// transaction releases its locks without changing anything.
+#[derive(Default)]
+pub struct RefTransaction {
+    updates: Vec<RefUpdate>,
+    /// For each update once prepared: the ref written, its lock and its
+    /// value when it was locked.
+    locked: Option<Vec<(String, LockFile, Option<String>)>>,
+    /// Held from `prepare` on when an update deletes a ref.
+    packed_lock: Option<LockFile>,
+}
+
+impl RefTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, update: RefUpdate) -> Result<()> {
+        if self.locked.is_some() {
+            bail!("prepared transactions can only be closed");
+        }
+        self.updates.push(update);
+        Ok(())
+    }
+
+    /// Lock all refs and verify their old values. Nothing is written yet.
+    pub fn prepare(&mut self, repo: &GitRepository) -> Result<()> {
+        if self.locked.is_some() {
+            return Ok(());
+        }
+
+        let mut locked: Vec<(String, LockFile, Option<String>)> = Vec::new();
+        for update in &self.updates {
+            let name = &update.refname;
+            let pseudo_ref = name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+            if !ref_name_valid(name) || !(name.starts_with("refs/") || pseudo_ref) {
+                bail!("refusing to update ref with bad name '{name}'");
+            }
+
+            let target = ref_deref(repo, name)?;
+            if locked.iter().any(|(t, _, _)| *t == target) {
+                bail!("multiple updates for ref '{name}' not allowed");
+            }
+            let lock = LockFile::acquire(&repo.gitdir.join(&target))?;
+            let current = resolve_ref(repo, &target).ok();
+
+            match (update.old.as_deref(), &current) {
+                (Some(NULL_SHA), Some(_)) => {
+                    bail!("cannot lock ref '{name}': reference already exists")
+                }
+                (Some(NULL_SHA), None) | (None, _) => {}
+                (Some(_), None) => {
+                    bail!("cannot lock ref '{name}': unable to resolve reference '{target}'")
+                }
+                (Some(old), Some(current)) if old != current => {
+                    bail!("cannot lock ref '{name}': is at {current} but expected {old}")
+                }
+                (Some(_), Some(_)) => {}
+            }
+
+            if let Some(new) = update.new.as_deref()
+                && new != NULL_SHA
+                && object_read_raw(repo, new).is_err()
+            {
+                bail!(
+                    "cannot update ref '{name}': trying to write ref '{target}' with nonexistent object {new}"
+                );
+            }
+
+            locked.push((target, lock, current));
+        }
+
+        // Deleted refs may have a packed copy, so packed-refs is locked after
+        // the loose refs
+        if self
+            .updates
+            .iter()
+            .any(|u| u.new.as_deref() == Some(NULL_SHA))
+        {
+            self.packed_lock = Some(packed_refs_lock(repo)?);
+        }
+        self.locked = Some(locked);
+        Ok(())
+    }
+
+    /// Prepare if needed, then apply every update. New values, reflog lines
+    /// and the rewritten packed-refs all go to lock files first, and those
+    /// are only moved into place once every update has been written, so a
+    /// failure before then just releases the locks.
+    pub fn commit(mut self, repo: &GitRepository) -> Result<()> {
+        self.prepare(repo)?;
+        let locked = self.locked.take().unwrap_or_default();
+
+        let mut written: Vec<LockFile> = Vec::new();
+        let mut reflogs: Vec<LockFile> = Vec::new();
+        let mut deleted: Vec<(String, LockFile)> = Vec::new();
+        let mut head_lock = None;
+        for (update, (target, mut lock, current)) in self.updates.iter().zip(locked) {
+            match update.new.as_deref() {
+                None => {}
+                Some(NULL_SHA) => deleted.push((target, lock)),
+                Some(new) => {
+                    let (old, message) = (current.as_deref(), update.message.as_str());
+                    lock.write_all(format!("{new}\n").as_bytes())?;
+                    let log = reflog_append_staged(repo, &lock, &target, old, new, message)?;
+                    reflogs.extend(log);
+                    if let Some(head) = head_lock_for(repo, &target)? {
+                        let log = reflog_append_staged(repo, &head, "HEAD", old, new, message)?;
+                        reflogs.extend(log);
+                        head_lock = Some(head);
+                    }
+                    written.push(lock);
+                }
+            }
+        }
+
+        // Deleted refs lose their packed copy in a single rewrite
+        let packed = packed_refs_read(repo)?;
+        let packed_changed = packed
+            .iter()
+            .any(|r| deleted.iter().any(|(target, _)| *target == r.name));
+        let packed_lock = match self.packed_lock.take() {
+            Some(mut lock) if packed_changed => {
+                let kept: Vec<PackedRef> = packed
+                    .into_iter()
+                    .filter(|r| !deleted.iter().any(|(target, _)| *target == r.name))
+                    .collect();
+                lock.write_all(packed_refs_serialize(&kept).as_bytes())?;
+                Some(lock)
+            }
+            _ => None,
+        };
+
+        // Everything is staged; the reflogs go in before the values they log
+        for lock in reflogs.into_iter().chain(written).chain(packed_lock) {
+            lock.commit()?;
+        }
+        for (target, lock) in deleted {
+            ref_remove_loose(repo, &target, lock)?;
+        }
+        drop(head_lock);
+        Ok(())
+    }
+}
+
+/// The ref `name` ends up at once symbolic refs (like HEAD) are followed.
+fn ref_deref(repo: &GitRepository, name: &str) -> Result<String> {
+    let mut name = name.to_string();
+    for _ in 0..5 {
+        let path = repo.gitdir.join(&name);
+        if !path.is_file() {
+            break;
+        }
+        match fs::read_to_string(&path)?.strip_prefix("ref: ") {
+            Some(target) => name = target.trim().to_string(),
+            None => break,
+        }
+    }
+    Ok(name)
+}
+
+/// Remove the loose file of `target` and its reflog, then release `lock`,
+/// the lock held on it.
+fn ref_remove_loose(repo: &GitRepository, target: &str, lock: LockFile) -> Result<()> {
+    let path = repo.gitdir.join(target);
+    if path.is_file() {
+        fs::remove_file(&path).with_context(|| format!("Failed to delete ref {:?}", path))?;
     }
-    fs::write(&path, format!("{sha}\n"))
-        .with_context(|| format!("Failed to write ref {:?}", path))?;
+    reflog_delete(repo, target)?;
+    drop(lock);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{
+        objects::{GitObjectType, object_hash},
+        reflog::reflog_read,
+        repo::repo_tmp,
+    };
+
+    fn update(refname: &str, new: &str) -> RefUpdate {
+        RefUpdate {
+            refname: refname.to_string(),
+            new: Some(new.to_string()),
+            old: None,
+            message: "test".to_string(),
+        }
+    }
+
+    #[test]
+    fn prepare_locks_packed_refs_for_deletions() {
+        let repo = repo_tmp("refs-prepare-packed");
+        let blob = object_hash(&repo, b"a".to_vec(), &GitObjectType::blob).unwrap();
+        ref_create(&repo, "refs/tags/t", &blob, "").unwrap();
+        let lock_path = repo.gitdir.join("packed-refs.lock");
+
+        let mut transaction = RefTransaction::new();
+        transaction.update(update("refs/tags/t", &blob)).unwrap();
+        transaction.prepare(&repo).unwrap();
+        assert!(!lock_path.exists());
+        drop(transaction);
+
+        let mut transaction = RefTransaction::new();
+        transaction.update(update("refs/tags/t", NULL_SHA)).unwrap();
+        transaction.prepare(&repo).unwrap();
+        assert!(lock_path.exists());
+        drop(transaction);
+        assert!(!lock_path.exists());
+        assert_eq!(resolve_ref(&repo, "refs/tags/t").unwrap(), blob);
+    }
+
+    #[test]
+    fn failed_commit_leaves_refs_and_reflogs_alone() {
+        let repo = repo_tmp("refs-failed-commit");
+        let a = object_hash(&repo, b"a".to_vec(), &GitObjectType::blob).unwrap();
+        let b = object_hash(&repo, b"b".to_vec(), &GitObjectType::blob).unwrap();
+        ref_create(&repo, "refs/heads/master", &a, "one").unwrap();
+        ref_create(&repo, "refs/heads/gone", &a, "one").unwrap();
+        let tag = PackedRef {
+            name: "refs/tags/t".to_string(),
+            sha: a.clone(),
+            peeled: None,
+        };
+        packed_refs_write(packed_refs_lock(&repo).unwrap(), &[tag]).unwrap();
+        let packed = fs::read(repo.gitdir.join("packed-refs")).unwrap();
+        // A directory where its reflog goes makes the last update fail
+        fs::create_dir_all(repo.gitdir.join("logs/refs/heads/broken")).unwrap();
+
+        let mut transaction = RefTransaction::new();
+        transaction
+            .update(update("refs/heads/gone", NULL_SHA))
+            .unwrap();
+        transaction.update(update("refs/heads/master", &b)).unwrap();
+        transaction
+            .update(RefUpdate {
+                new: None,
+                old: Some(a.clone()),
+                ..update("refs/tags/t", "")
+            })
+            .unwrap();
+        transaction.update(update("refs/heads/broken", &b)).unwrap();
+        assert!(transaction.commit(&repo).is_err());
+
+        assert_eq!(resolve_ref(&repo, "refs/heads/master").unwrap(), a);
+        assert_eq!(resolve_ref(&repo, "refs/heads/gone").unwrap(), a);
+        assert!(resolve_ref(&repo, "refs/heads/broken").is_err());
+        // Only verified, so the packed tag is not written out as a loose ref
+        assert!(!repo.gitdir.join("refs/tags/t").exists());
+        assert_eq!(fs::read(repo.gitdir.join("packed-refs")).unwrap(), packed);
+        assert!(!repo.gitdir.join("packed-refs.lock").exists());
+        assert!(!repo.gitdir.join("refs/heads/master.lock").exists());
+        assert!(!repo.gitdir.join("logs/refs/heads/master.lock").exists());
+        assert_eq!(reflog_read(&repo, "refs/heads/master").unwrap().len(), 1);
+        assert_eq!(reflog_read(&repo, "HEAD").unwrap().len(), 1);
+        assert_eq!(reflog_read(&repo, "refs/heads/gone").unwrap().len(), 1);
+
+        fs::remove_dir(repo.gitdir.join("logs/refs/heads/broken")).unwrap();
+        let mut transaction = RefTransaction::new();
+        transaction
+            .update(update("refs/heads/gone", NULL_SHA))
+            .unwrap();
+        transaction.update(update("refs/heads/master", &b)).unwrap();
+        transaction.update(update("refs/tags/t", NULL_SHA)).unwrap();
+        transaction.commit(&repo).unwrap();
+
+        assert_eq!(resolve_ref(&repo, "refs/heads/master").unwrap(), b);
+        assert!(resolve_ref(&repo, "refs/heads/gone").is_err());
+        assert!(resolve_ref(&repo, "refs/tags/t").is_err());
+        assert_eq!(reflog_read(&repo, "HEAD").unwrap().len(), 2);
+        assert!(reflog_read(&repo, "refs/heads/gone").unwrap().is_empty());
+    }
+}
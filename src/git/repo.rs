@@ -1,19 +1,27 @@
 use anyhow::{Context, Result};
 use ini::Ini;
+use std::cell::OnceCell;
 use std::env;
 use std::fs::{self, create_dir};
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
+use crate::git::{lockfile::lockfile_write, pack::PackIndex};
+
 #[derive(Debug)]
 pub struct GitRepository {
     pub worktree: PathBuf,
     pub gitdir: PathBuf,
     pub config: Option<RepositoryConfig>,
+    /// Pack indexes under `objects/pack`, loaded on first lookup.
+    pub packs: OnceCell<Vec<PackIndex>>,
 }
 
 #[derive(Debug)]
 pub struct RepositoryConfig {
     pub repository_format_version: u8,
+    /// Everything in `.git/config`, for settings looked up by commands.
+    pub values: Ini,
 }
 
 impl GitRepository {
@@ -38,21 +46,21 @@ impl GitRepository {
             None
         };
 
-        if !force {
-            if let Some(cfg) = &config {
-                if cfg.repository_format_version != 0 {
-                    anyhow::bail!(
-                        "Unsupported repositoryformatversion: {}",
-                        cfg.repository_format_version
-                    );
-                }
-            }
+        if !force
+            && let Some(cfg) = &config
+            && cfg.repository_format_version != 0
+        {
+            anyhow::bail!(
+                "Unsupported repositoryformatversion: {}",
+                cfg.repository_format_version
+            );
         }
 
         Ok(GitRepository {
             worktree,
             gitdir,
             config,
+            packs: OnceCell::new(),
         })
     }
 
@@ -82,11 +90,15 @@ impl GitRepository {
             "Unnamed repository; edit this file 'description' to name the repository.\n",
         )?;
 
-        fs::write(repo.repo_file("HEAD"), "ref: refs/heads/master\n")?;
+        lockfile_write(&repo.repo_file("HEAD"), b"ref: refs/heads/master\n")?;
 
-        fs::write(
-            repo.repo_file("config"),
-            "[core]\n\trepositoryformatversion = 0\n\tfilemode = false\n\tbare = false\n",
+        let filemode = filemode_probe(&repo.repo_file("description"));
+        lockfile_write(
+            &repo.repo_file("config"),
+            format!(
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = {filemode}\n\tbare = false\n"
+            )
+            .as_bytes(),
         )?;
 
         Ok(repo)
@@ -107,23 +119,27 @@ impl GitRepository {
     }
 }
 
-fn read_config(path: &Path) -> Result<RepositoryConfig> {
-    let content = fs::read_to_string(path)?;
-    let mut version: Option<u8> = None;
-
-    for line in content.lines() {
-        let trimmed = line.trim();
+/// Whether the filesystem keeps the executable bit: flip it on `path` and
+/// see whether the change sticks, then put it back.
+fn filemode_probe(path: &Path) -> bool {
+    let Ok(before) = fs::symlink_metadata(path).map(|m| m.permissions().mode()) else {
+        return false;
+    };
+    let flipped = fs::set_permissions(path, fs::Permissions::from_mode(before ^ 0o100)).is_ok()
+        && fs::symlink_metadata(path).is_ok_and(|m| m.permissions().mode() != before);
+    flipped && fs::set_permissions(path, fs::Permissions::from_mode(before)).is_ok()
+}
 
-        if trimmed.starts_with("repositoryformatversion") {
-            if let Some(eq_pos) = trimmed.find('=') {
-                let num_str = trimmed[(eq_pos + 1)..].trim();
-                version = Some(num_str.parse()?);
-            }
-        }
-    }
+fn read_config(path: &Path) -> Result<RepositoryConfig> {
+    let values = Ini::load_from_file(path)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let version = gitconfig_lookup(&values, "core", "repositoryformatversion")
+        .map(|v| v.parse())
+        .transpose()?;
 
     Ok(RepositoryConfig {
         repository_format_version: version.unwrap_or(0),
+        values,
     })
 }
 
@@ -160,26 +176,20 @@ pub fn gitconfig_read() -> Result<Ini> {
         }
     });
 
-    let configfiles = vec![
+    let configfiles = [
         PathBuf::from(format!("{xdg_config_home}/git/config")),
-        PathBuf::from(match env::var("HOMEPATH") {
-            Ok(home) => format!("{}/.gitconfig", home),
-            Err(_) => "~/.gitconfig".to_string(),
-        }),
+        dirs::home_dir().unwrap_or_default().join(".gitconfig"),
     ];
 
     let mut merged = Ini::new();
 
     for path in configfiles {
-        if path.exists() {
-            if let Ok(cfg) = Ini::load_from_file(&path) {
-                for (sec, prop) in &cfg {
-                    let section = sec.clone();
-                    for (k, v) in prop.iter() {
-                        merged
-                            .with_section(section.clone())
-                            .set(k, v);
-                    }
+        if path.exists()
+            && let Ok(cfg) = Ini::load_from_file(&path)
+        {
+            for (sec, prop) in &cfg {
+                for (k, v) in prop.iter() {
+                    merged.with_section(sec).set(k, v);
                 }
             }
         }
@@ -188,11 +198,36 @@ pub fn gitconfig_read() -> Result<Ini> {
     Ok(merged)
 }
 
-pub fn gitconfig_user_get(config: &Ini) -> Option<String> {
-    if let Some(section) = config.section(Some("user")) {
-        if let (Some(name), Some(email)) = (section.get("name"), section.get("email")) {
-            return Some(format!("{name} <{email}>"));
-        }
-    }
-    None
+/// `section.key` in `config`; both names are case-insensitive like in git.
+fn gitconfig_lookup(config: &Ini, section: &str, key: &str) -> Option<String> {
+    config
+        .iter()
+        .filter(|(sec, _)| sec.is_some_and(|s| s.eq_ignore_ascii_case(section)))
+        .flat_map(|(_, prop)| prop.iter())
+        .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v.to_string())
+        .next_back()
+}
+
+/// The setting `section.key`, with the repository's `.git/config` taking
+/// precedence over the global config files.
+pub fn repo_config_get(repo: &GitRepository, section: &str, key: &str) -> Option<String> {
+    repo.config
+        .as_ref()
+        .and_then(|c| gitconfig_lookup(&c.values, section, key))
+        .or_else(|| {
+            gitconfig_read()
+                .ok()
+                .and_then(|c| gitconfig_lookup(&c, section, key))
+        })
+}
+
+/// A fresh repository in the temp directory for tests, named after `name`.
+#[cfg(test)]
+pub fn repo_tmp(name: &str) -> GitRepository {
+    let dir = env::temp_dir().join(format!("rust-git-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    GitRepository::create(&dir).unwrap();
+    GitRepository::new(&dir, false).unwrap()
 }
@@ -0,0 +1,233 @@
+use std::collections::{BinaryHeap, HashSet};
+
+use anyhow::{Context, Result, anyhow, bail};
+use regex::Regex;
+
+use crate::git::{
+    ident::approxidate,
+    index::read_index,
+    objects::{GitObjectType, commit_read, object_peel, object_read},
+    reflog::{reflog_at, reflog_nth},
+    refs::{collect_refs, head_symbolic, ref_dwim, resolve_ref, resolve_sha},
+    repo::GitRepository,
+    tree::GitTree,
+};
+
+/// Evaluate a revision expression (see gitrevisions(7)) to an object SHA:
+///
+/// - `<sha>`, `<refname>`, `@` (HEAD), `<ref>@{<n>}`, `<ref>@{<date>}`
+/// - `<rev>~<n>`, `<rev>^<n>`, `<rev>^{<type>}`, `<rev>^{}`, `<rev>^{/<text>}`
+/// - `<rev>:<path>`, `:<path>`, `:<stage>:<path>` and `:/<text>`
+///
+/// `<text>` is a regular expression matched against commit messages.
+pub fn revision_resolve(repo: &GitRepository, expr: &str) -> Result<String> {
+    if let Some(text) = expr.strip_prefix(":/") {
+        let mut starts: Vec<String> = resolve_ref(repo, "HEAD").into_iter().collect();
+        starts.extend(collect_refs(repo, "refs")?.into_iter().map(|(sha, _)| sha));
+        return commit_search(repo, &starts, text);
+    }
+
+    if let Some(rest) = expr.strip_prefix(':') {
+        return index_lookup(repo, rest);
+    }
+
+    if let Some(colon) = find_outside_braces(expr, &[':']) {
+        let (rev, path) = (&expr[..colon], &expr[colon + 1..]);
+        let tree = object_peel(repo, &revision_resolve(repo, rev)?, Some(GitObjectType::tree))?;
+        return tree_lookup(repo, &tree, path)
+            .with_context(|| format!("path '{path}' does not exist in '{rev}'"));
+    }
+
+    let split = find_outside_braces(expr, &['~', '^']).unwrap_or(expr.len());
+    let mut sha = rev_base(repo, &expr[..split])?;
+    let mut rest = &expr[split..];
+
+    while let Some(op) = rest.chars().next() {
+        if op != '~' && op != '^' {
+            bail!("Not a valid object name: {expr}");
+        }
+        rest = &rest[1..];
+
+        if op == '^' && rest.starts_with('{') {
+            let close = rest
+                .find('}')
+                .ok_or_else(|| anyhow!("Not a valid object name: {expr}"))?;
+            sha = peel_spec(repo, &sha, &rest[1..close])?;
+            rest = &rest[close + 1..];
+            continue;
+        }
+
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let n: usize = if digits == 0 {
+            1
+        } else {
+            rest[..digits].parse()?
+        };
+        rest = &rest[digits..];
+
+        let commit = object_peel(repo, &sha, Some(GitObjectType::commit))?;
+        let missing = || format!("revision {expr} does not exist");
+        sha = match op {
+            '~' => {
+                let mut current = commit;
+                for _ in 0..n {
+                    current = commit_read(repo, &current)?
+                        .parents()
+                        .into_iter()
+                        .next()
+                        .with_context(missing)?;
+                }
+                current
+            }
+            _ if n == 0 => commit,
+            _ => commit_read(repo, &commit)?
+                .parents()
+                .into_iter()
+                .nth(n - 1)
+                .with_context(missing)?,
+        };
+    }
+
+    Ok(sha)
+}
+
+/// Resolve the part of an expression before any `~`, `^` or `:` operator.
+fn rev_base(repo: &GitRepository, name: &str) -> Result<String> {
+    if name == "@" {
+        return resolve_ref(repo, "HEAD");
+    }
+
+    // <ref>@{<n>} and <ref>@{<date>} look the ref up in its reflog
+    if let Some(open) = name.find("@{")
+        && let Some(spec) = name[open + 2..].strip_suffix('}')
+    {
+        let base = &name[..open];
+        let refname = if base.is_empty() {
+            head_symbolic(repo)?.unwrap_or_else(|| "HEAD".to_string())
+        } else {
+            ref_dwim(repo, base)?.with_context(|| format!("Not a valid object name: {name}"))?
+        };
+
+        return match spec.parse::<usize>() {
+            Ok(n) => reflog_nth(repo, &refname, n),
+            Err(_) => reflog_at(repo, &refname, approxidate(spec)?),
+        };
+    }
+
+    let is_hex = name.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex && name.len() == 40 {
+        return Ok(name.to_ascii_lowercase());
+    }
+
+    if let Some(refname) = ref_dwim(repo, name)? {
+        return resolve_ref(repo, &refname);
+    }
+
+    if is_hex && (4..40).contains(&name.len()) {
+        return resolve_sha(repo, name);
+    }
+
+    bail!("Not a valid object name: {name}")
+}
+
+/// `^{<spec>}`: peel to a type, `^{}` peels tags, `^{/<text>}` searches history.
+fn peel_spec(repo: &GitRepository, sha: &str, spec: &str) -> Result<String> {
+    if let Some(text) = spec.strip_prefix('/') {
+        let commit = object_peel(repo, sha, Some(GitObjectType::commit))?;
+        return commit_search(repo, &[commit], text);
+    }
+
+    match spec {
+        "object" => Ok(sha.to_string()),
+        "" => object_peel(repo, sha, None),
+        "commit" => object_peel(repo, sha, Some(GitObjectType::commit)),
+        "tree" => object_peel(repo, sha, Some(GitObjectType::tree)),
+        "blob" => object_peel(repo, sha, Some(GitObjectType::blob)),
+        "tag" => object_peel(repo, sha, Some(GitObjectType::tag)),
+        _ => bail!("unknown peel type: ^{{{spec}}}"),
+    }
+}
+
+/// `:<path>` or `:<stage>:<path>`: a blob from the index.
+fn index_lookup(repo: &GitRepository, spec: &str) -> Result<String> {
+    let (stage, path) = match spec.split_once(':') {
+        Some((n, path)) if matches!(n, "0" | "1" | "2" | "3") => (n.parse::<u16>()?, path),
+        _ => (0, spec),
+    };
+
+    read_index(repo)?
+        .entries
+        .into_iter()
+        .find(|e| e.path == path && e.stage() == stage)
+        .map(|e| e.sha)
+        .with_context(|| format!("path '{path}' is not in the index at stage {stage}"))
+}
+
+/// Follow `path` down from the tree `tree`; an empty path names the tree itself.
+fn tree_lookup(repo: &GitRepository, tree: &str, path: &str) -> Result<String> {
+    let mut sha = tree.to_string();
+
+    for name in path.split('/').filter(|c| !c.is_empty()) {
+        let (_, obj) = object_read(repo, &sha)?;
+        let tree = obj
+            .as_any()
+            .downcast_ref::<GitTree>()
+            .with_context(|| format!("{name}: not a directory"))?;
+        let entry = tree
+            .entries
+            .iter()
+            .find(|e| e.path == name)
+            .with_context(|| format!("no entry {name}"))?;
+        sha = hex::encode(entry.sha);
+    }
+
+    Ok(sha)
+}
+
+/// The newest commit reachable from `starts` whose message matches the
+/// regular expression `pattern`.
+fn commit_search(repo: &GitRepository, starts: &[String], pattern: &str) -> Result<String> {
+    let regex =
+        Regex::new(pattern).with_context(|| format!("invalid regular expression '{pattern}'"))?;
+    let mut queue = BinaryHeap::new();
+    let mut seen = HashSet::new();
+
+    for sha in starts {
+        // Tags may point at trees or blobs, which have no history
+        if let Ok(commit) = object_peel(repo, sha, Some(GitObjectType::commit)) {
+            queue.push((commit_read(repo, &commit)?.committer_time(), commit));
+        }
+    }
+
+    while let Some((_, sha)) = queue.pop() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+
+        let commit = commit_read(repo, &sha)?;
+        if regex.is_match(&String::from_utf8_lossy(&commit.kvlm.message)) {
+            return Ok(sha);
+        }
+        for parent in commit.parents() {
+            if !seen.contains(&parent) {
+                queue.push((commit_read(repo, &parent)?.committer_time(), parent));
+            }
+        }
+    }
+
+    bail!("no commit message matches '{pattern}'")
+}
+
+/// Position of the first of `chars` that is not inside `{...}`.
+fn find_outside_braces(expr: &str, chars: &[char]) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            c if depth == 0 && chars.contains(&c) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
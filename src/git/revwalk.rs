@@ -0,0 +1,514 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::git::{
+    merge_base::{ancestors, merge_bases},
+    objects::{GitCommit, GitObjectType, commit_read, object_peel, object_read, object_read_raw},
+    refs::{collect_refs, resolve_ref},
+    repo::GitRepository,
+    revision::revision_resolve,
+    tree::{GitTree, GitTreeLeaf},
+};
+
+/// Order in which a walk emits commits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// Newest commit date first (git's default).
+    #[default]
+    Date,
+    /// No parent before all of its children, otherwise by commit date.
+    DateOrder,
+    /// No parent before all of its children, keeping lines of history together.
+    Topo,
+}
+
+/// Conditions a commit must meet to be shown. Patterns of the same kind are
+/// alternatives; different kinds must all match.
+#[derive(Debug, Clone, Default)]
+pub struct CommitFilter {
+    /// Matched against the author's "Name <email>".
+    pub author: Vec<Regex>,
+    /// Matched against the committer's "Name <email>".
+    pub committer: Vec<Regex>,
+    /// Matched against the commit message.
+    pub grep: Vec<Regex>,
+    /// Commits older than this are neither shown nor walked past.
+    pub since: Option<i64>,
+    /// Commits newer than this are skipped.
+    pub until: Option<i64>,
+}
+
+impl CommitFilter {
+    fn matches(&self, commit: &GitCommit) -> bool {
+        let any = |patterns: &[Regex], text: &str| {
+            patterns.is_empty() || patterns.iter().any(|re| re.is_match(text))
+        };
+
+        let author = commit.author().map(|s| s.ident()).unwrap_or_default();
+        let committer = commit.committer().map(|s| s.ident()).unwrap_or_default();
+        let message = String::from_utf8_lossy(&commit.kvlm.message);
+
+        any(&self.author, &author)
+            && any(&self.committer, &committer)
+            && any(&self.grep, &message)
+            && self
+                .until
+                .is_none_or(|until| commit.committer_time() <= until)
+    }
+}
+
+/// Revision arguments sorted into commits to walk from and commits whose
+/// history is left out.
+#[derive(Debug, Clone, Default)]
+pub struct RevSpecs {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// The left-hand sides of `A...B`, whose commits `--left-right` marks.
+    pub left: Vec<String>,
+    /// Annotated tags among the included revisions, with the names they
+    /// were given by.
+    pub tags: Vec<(String, String)>,
+}
+
+/// Parse revision arguments: `<rev>`, `^<rev>`, `A..B`, `A...B` (an empty
+/// side means HEAD), and `--all`, `--branches`, `--tags` and `--not`, which
+/// flips the meaning of the arguments after it.
+pub fn rev_specs_parse(repo: &GitRepository, args: &[String]) -> Result<RevSpecs> {
+    let mut specs = RevSpecs::default();
+    let mut negated = false;
+
+    // The commit a side of an argument names, and the object it names directly
+    let resolve = |rev: &str| -> Result<(String, String)> {
+        let rev = if rev.is_empty() { "HEAD" } else { rev };
+        let sha = revision_resolve(repo, rev)?;
+        Ok((object_peel(repo, &sha, Some(GitObjectType::commit))?, sha))
+    };
+
+    for arg in args {
+        let refs_prefix = match arg.as_str() {
+            "--not" => {
+                negated = !negated;
+                continue;
+            }
+            "--all" => Some("refs"),
+            "--branches" => Some("refs/heads"),
+            "--tags" => Some("refs/tags"),
+            _ => None,
+        };
+
+        let (mut include, mut exclude) = (Vec::new(), Vec::new());
+        let mut named: Vec<(String, String)> = Vec::new();
+        if let Some(prefix) = refs_prefix {
+            let mut refs = collect_refs(repo, prefix)?;
+            if prefix == "refs"
+                && let Ok(head) = resolve_ref(repo, "HEAD")
+            {
+                refs.push((head, "HEAD".to_string()));
+            }
+            for (sha, name) in refs {
+                // Refs may name trees or blobs, which have no history
+                if let Ok(commit) = object_peel(repo, &sha, Some(GitObjectType::commit)) {
+                    include.push(commit);
+                }
+                let short = name.strip_prefix("refs/tags/").unwrap_or(&name);
+                named.push((sha, short.to_string()));
+            }
+        } else if let Some((a, b)) = arg.split_once("...") {
+            let ((left, left_sha), (right, right_sha)) = (resolve(a)?, resolve(b)?);
+            exclude.extend(merge_bases(repo, &left, &right)?);
+            specs.left.push(left.clone());
+            include.extend([left, right]);
+            named.extend([(left_sha, a.to_string()), (right_sha, b.to_string())]);
+        } else if let Some((a, b)) = arg.split_once("..") {
+            exclude.push(resolve(a)?.0);
+            let (right, right_sha) = resolve(b)?;
+            include.push(right);
+            named.push((right_sha, b.to_string()));
+        } else if let Some(rev) = arg.strip_prefix('^') {
+            exclude.push(resolve(rev)?.0);
+        } else {
+            let (commit, sha) = resolve(arg)?;
+            include.push(commit);
+            named.push((sha, arg.clone()));
+        }
+
+        if negated {
+            std::mem::swap(&mut include, &mut exclude);
+        } else {
+            for (sha, name) in named {
+                if let Ok((GitObjectType::tag, _)) = object_read_raw(repo, &sha) {
+                    specs.tags.push((sha, name));
+                }
+            }
+        }
+        specs.include.extend(include);
+        specs.exclude.extend(exclude);
+    }
+
+    Ok(specs)
+}
+
+/// What to walk and how to emit it.
+#[derive(Debug, Clone, Default)]
+pub struct RevWalkOptions {
+    /// Commits to start from.
+    pub include: Vec<String>,
+    /// Commits whose history (themselves included) is left out.
+    pub exclude: Vec<String>,
+    pub order: WalkOrder,
+    /// Stop after this many commits (applied before `reverse`).
+    pub max_count: Option<usize>,
+    pub reverse: bool,
+    /// Only show commits that change these paths, simplifying history the
+    /// way git does by default.
+    pub paths: Vec<String>,
+    pub filter: CommitFilter,
+}
+
+/// A commit produced by a walk.
+pub struct WalkedCommit {
+    pub sha: String,
+    pub commit: GitCommit,
+    /// The parents as seen in the walked history: for commits hidden by path
+    /// limiting or filters, their nearest shown ancestors instead.
+    pub parents: Vec<String>,
+}
+
+/// Walk the history reachable from `opts.include`. Traversal is iterative, so
+/// arbitrarily long histories are fine.
+pub fn rev_walk(repo: &GitRepository, opts: &RevWalkOptions) -> Result<Vec<WalkedCommit>> {
+    let limit = opts.max_count.unwrap_or(usize::MAX);
+    let mut uninteresting = HashSet::new();
+    for sha in &opts.exclude {
+        if !uninteresting.contains(sha) {
+            uninteresting.extend(ancestors(repo, sha)?);
+        }
+    }
+
+    let mut walker = Walker {
+        repo,
+        opts,
+        uninteresting,
+        followed: HashMap::new(),
+        shown: HashSet::new(),
+    };
+
+    let walked = match opts.order {
+        WalkOrder::Date => walker.walk_by_date(limit)?,
+        WalkOrder::DateOrder | WalkOrder::Topo => {
+            let mut sorted = walker.walk_topo()?;
+            sorted.truncate(limit);
+            sorted
+        }
+    };
+
+    let mut out: Vec<WalkedCommit> = walked
+        .into_iter()
+        .map(|(sha, commit)| WalkedCommit {
+            parents: walker.rewrite_parents(&sha),
+            sha,
+            commit,
+        })
+        .collect();
+
+    if opts.reverse {
+        out.reverse();
+    }
+    Ok(out)
+}
+
+struct Walker<'a> {
+    repo: &'a GitRepository,
+    opts: &'a RevWalkOptions,
+    /// Commits reachable from the excluded ones.
+    uninteresting: HashSet<String>,
+    /// The parents walked from each visited commit.
+    followed: HashMap<String, Vec<String>>,
+    shown: HashSet<String>,
+}
+
+impl Walker<'_> {
+    /// Decide whether `commit` is shown and return the parents to walk next.
+    fn visit(&mut self, sha: &str, commit: &GitCommit) -> Result<Vec<String>> {
+        let mut parents = commit.parents();
+        parents.retain(|p| !self.uninteresting.contains(p));
+
+        let (show, follow) = match self.opts.filter.since {
+            Some(since) if commit.committer_time() < since => (false, Vec::new()),
+            _ if self.opts.paths.is_empty() => (true, parents),
+            _ => self.simplify(commit, parents)?,
+        };
+
+        if show && self.opts.filter.matches(commit) {
+            self.shown.insert(sha.to_string());
+        }
+        self.followed.insert(sha.to_string(), follow.clone());
+        Ok(follow)
+    }
+
+    /// TREESAME simplification: a commit is shown if it changes the paths
+    /// compared to all of its parents; a commit that matches one of its
+    /// parents takes all of its history from that parent.
+    fn simplify(&self, commit: &GitCommit, parents: Vec<String>) -> Result<(bool, Vec<String>)> {
+        let tree = commit.tree()?;
+        let paths = &self.opts.paths;
+
+        if parents.is_empty() {
+            let changed = tree_changed(self.repo, None, Some(&tree), "", paths)?;
+            return Ok((changed, parents));
+        }
+
+        for parent in &parents {
+            let parent_tree = commit_read(self.repo, parent)?.tree()?;
+            if !tree_changed(self.repo, Some(&parent_tree), Some(&tree), "", paths)? {
+                return Ok((false, vec![parent.clone()]));
+            }
+        }
+
+        Ok((true, parents))
+    }
+
+    /// Pop the newest commit from a date-ordered queue until `limit` are shown.
+    fn walk_by_date(&mut self, limit: usize) -> Result<Vec<(String, GitCommit)>> {
+        let mut queue = ReadyQueue::ByDate(BinaryHeap::new(), 0);
+        let mut pending: HashMap<String, GitCommit> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+
+        for sha in &self.opts.include {
+            if !self.uninteresting.contains(sha) && seen.insert(sha.clone()) {
+                let commit = commit_read(self.repo, sha)?;
+                queue.push(commit.committer_time(), sha.clone());
+                pending.insert(sha.clone(), commit);
+            }
+        }
+
+        while out.len() < limit
+            && let Some(sha) = queue.pop()
+        {
+            let commit = pending.remove(&sha).expect("queued commit");
+            for parent in self.visit(&sha, &commit)? {
+                if seen.insert(parent.clone()) {
+                    let parent_commit = commit_read(self.repo, &parent)?;
+                    queue.push(parent_commit.committer_time(), parent.clone());
+                    pending.insert(parent, parent_commit);
+                }
+            }
+            if self.shown.contains(&sha) {
+                out.push((sha, commit));
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Read every reachable commit, then emit them children-first (Kahn's algorithm).
+    fn walk_topo(&mut self) -> Result<Vec<(String, GitCommit)>> {
+        let mut commits: HashMap<String, GitCommit> = HashMap::new();
+        let mut children: HashMap<String, usize> = HashMap::new();
+        let mut stack: Vec<String> = self.opts.include.clone();
+        stack.retain(|sha| !self.uninteresting.contains(sha));
+
+        while let Some(sha) = stack.pop() {
+            if commits.contains_key(&sha) {
+                continue;
+            }
+            let commit = commit_read(self.repo, &sha)?;
+            for parent in self.visit(&sha, &commit)? {
+                *children.entry(parent.clone()).or_default() += 1;
+                if !commits.contains_key(&parent) {
+                    stack.push(parent);
+                }
+            }
+            commits.insert(sha, commit);
+        }
+
+        // Tips are the starting points nobody else in the walk points to
+        let mut tips: Vec<String> = Vec::new();
+        for sha in &self.opts.include {
+            if commits.contains_key(sha) && !children.contains_key(sha) && !tips.contains(sha) {
+                tips.push(sha.clone());
+            }
+        }
+        tips.sort_by_key(|sha| Reverse(commits[sha].committer_time()));
+
+        let mut out = Vec::with_capacity(commits.len());
+        let mut ready = match self.opts.order {
+            WalkOrder::Topo => ReadyQueue::Lifo(Vec::new()),
+            _ => ReadyQueue::ByDate(BinaryHeap::new(), 0),
+        };
+
+        // The stack pops in reverse, so push the newest tip last
+        for sha in tips.into_iter().rev() {
+            ready.push(commits[&sha].committer_time(), sha);
+        }
+
+        while let Some(sha) = ready.pop() {
+            let commit = commits.remove(&sha).expect("walked commit");
+            for parent in &self.followed[&sha] {
+                let Some(count) = children.get_mut(parent) else {
+                    continue;
+                };
+                *count -= 1;
+                if *count == 0
+                    && let Some(parent_commit) = commits.get(parent)
+                {
+                    ready.push(parent_commit.committer_time(), parent.clone());
+                }
+            }
+            if self.shown.contains(&sha) {
+                out.push((sha, commit));
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// The nearest shown ancestors of `sha` along the walked edges. Parents
+    /// the walk never reached are kept as they are.
+    fn rewrite_parents(&self, sha: &str) -> Vec<String> {
+        let mut out: Vec<String> = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack: Vec<&String> = self.followed[sha].iter().rev().collect();
+
+        while let Some(current) = stack.pop() {
+            if !seen.insert(current) {
+                continue;
+            }
+            match self.followed.get(current) {
+                Some(parents) if !self.shown.contains(current) => {
+                    stack.extend(parents.iter().rev());
+                }
+                _ => {
+                    if !out.contains(current) {
+                        out.push(current.clone());
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Whether the entries matching `paths` differ between two trees (a missing
+/// tree counts as empty). `prefix` is the path of the trees being compared.
+fn tree_changed(
+    repo: &GitRepository,
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    paths: &[String],
+) -> Result<bool> {
+    if old == new {
+        return Ok(false);
+    }
+
+    let old_entries = old.map_or(Ok(Vec::new()), |sha| tree_entries(repo, sha))?;
+    let new_entries = new.map_or(Ok(Vec::new()), |sha| tree_entries(repo, sha))?;
+
+    let mut names: Vec<&str> = old_entries
+        .iter()
+        .chain(&new_entries)
+        .map(|e| e.path.as_str())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+
+    for name in names {
+        let old_entry = old_entries.iter().find(|e| e.path == name);
+        let new_entry = new_entries.iter().find(|e| e.path == name);
+        if let (Some(a), Some(b)) = (old_entry, new_entry)
+            && a.sha == b.sha
+            && a.mode_bits() == b.mode_bits()
+        {
+            continue;
+        }
+
+        let path = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{prefix}/{name}")
+        };
+
+        // A file on either side changed if the pathspec covers it...
+        let has_blob = [old_entry, new_entry]
+            .into_iter()
+            .flatten()
+            .any(|e| !e.is_tree());
+        if has_blob && path_relevant(&path, false, paths) {
+            return Ok(true);
+        }
+
+        // ...and a directory if anything covered inside it did
+        let subtree =
+            |e: Option<&GitTreeLeaf>| e.filter(|e| e.is_tree()).map(|e| hex::encode(e.sha));
+        let (old_sub, new_sub) = (subtree(old_entry), subtree(new_entry));
+        if (old_sub.is_some() || new_sub.is_some())
+            && path_relevant(&path, true, paths)
+            && tree_changed(repo, old_sub.as_deref(), new_sub.as_deref(), &path, paths)?
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Whether `path` is one of `paths`, lies below one, or (for a directory)
+/// may contain one. Pathspecs with glob characters are matched as patterns.
+fn path_relevant(path: &str, is_tree: bool, paths: &[String]) -> bool {
+    paths.iter().any(|spec| {
+        let spec = spec.trim_end_matches('/');
+        if spec.is_empty() || spec == "." {
+            return true;
+        }
+        if spec.contains(['*', '?', '[']) {
+            return is_tree || wildmatch::WildMatch::new(spec).matches(path);
+        }
+
+        path == spec
+            || path.starts_with(&format!("{spec}/"))
+            || (is_tree && spec.starts_with(&format!("{path}/")))
+    })
+}
+
+fn tree_entries(repo: &GitRepository, sha: &str) -> Result<Vec<GitTreeLeaf>> {
+    let (_, obj) = object_read(repo, sha)?;
+    let tree = obj
+        .as_any()
+        .downcast_ref::<GitTree>()
+        .with_context(|| format!("Object {sha} is not a tree"))?;
+    Ok(tree.entries.clone())
+}
+
+/// Commits whose children have all been emitted.
+enum ReadyQueue {
+    Lifo(Vec<String>),
+    /// Newest first; ties broken by insertion order.
+    ByDate(BinaryHeap<(i64, Reverse<usize>, String)>, usize),
+}
+
+impl ReadyQueue {
+    fn push(&mut self, time: i64, sha: String) {
+        match self {
+            ReadyQueue::Lifo(stack) => stack.push(sha),
+            ReadyQueue::ByDate(heap, seq) => {
+                heap.push((time, Reverse(*seq), sha));
+                *seq += 1;
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<String> {
+        match self {
+            ReadyQueue::Lifo(stack) => stack.pop(),
+            ReadyQueue::ByDate(heap, _) => heap.pop().map(|(_, _, sha)| sha),
+        }
+    }
+}
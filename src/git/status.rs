@@ -0,0 +1,332 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::git::{
+    ignore::{GitIgnore, check_ignore, gitignore_read},
+    index::{GitIndex, GitIndexEntry},
+    objects::{GitBlob, GitObjectType, object_resolve, object_peel, object_write},
+    repo::{GitRepository, repo_config_get},
+    tree::{GitTreeLeaf, tree_flatten},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl ChangeKind {
+    /// Single-letter code used by the porcelain formats.
+    pub fn code(&self) -> char {
+        match self {
+            ChangeKind::Added => 'A',
+            ChangeKind::Modified => 'M',
+            ChangeKind::Deleted => 'D',
+        }
+    }
+
+    /// Label used by the long format, e.g. "new file:".
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "new file:",
+            ChangeKind::Modified => "modified:",
+            ChangeKind::Deleted => "deleted:",
+        }
+    }
+}
+
+/// A tracked path that differs between HEAD, the index or the worktree.
+#[derive(Debug, Clone)]
+pub struct StatusEntry {
+    pub path: String,
+    /// HEAD -> index
+    pub staged: Option<ChangeKind>,
+    /// index -> worktree
+    pub unstaged: Option<ChangeKind>,
+    pub head: Option<(u32, String)>,
+    pub index: Option<(u32, String)>,
+    pub worktree_mode: Option<u32>,
+}
+
+/// A path with base/ours/theirs (stage 1-3) entries in the index.
+#[derive(Debug, Clone)]
+pub struct UnmergedEntry {
+    pub path: String,
+    pub stages: [Option<(u32, String)>; 3],
+    pub worktree_mode: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct RepoStatus {
+    pub entries: Vec<StatusEntry>,
+    /// Paths with conflict stages left by a merge.
+    pub unmerged: Vec<UnmergedEntry>,
+    /// Untracked paths; directories without tracked content end with '/'.
+    pub untracked: Vec<String>,
+    pub ignored: Vec<String>,
+}
+
+/// The flattened tree of the commit HEAD points to (empty on an unborn branch).
+pub fn head_tree(repo: &GitRepository) -> Result<BTreeMap<String, GitTreeLeaf>> {
+    let Ok(head) = object_resolve(repo, "HEAD") else {
+        return Ok(BTreeMap::new());
+    };
+    let tree = object_peel(repo, &head, Some(GitObjectType::tree))?;
+    tree_flatten(repo, &tree)
+}
+
+/// Git mode for a file on disk: symlink, executable or regular file.
+pub fn worktree_mode(meta: &fs::Metadata) -> u32 {
+    if meta.file_type().is_symlink() {
+        0o120000
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// Whether the executable bit of files on disk is meaningful
+/// (`core.filemode`, on unless set to false).
+pub fn filemode_trusted(repo: &GitRepository) -> bool {
+    !matches!(
+        repo_config_get(repo, "core", "filemode").as_deref(),
+        Some("false" | "no" | "off" | "0")
+    )
+}
+
+/// The mode to record in the index for a file on disk. Unless `filemode` is
+/// trusted, regular files keep the mode of their `current` index entry, or
+/// are taken as non-executable.
+pub fn index_mode(meta: &fs::Metadata, current: Option<u32>, filemode: bool) -> u32 {
+    match worktree_mode(meta) {
+        0o120000 => 0o120000,
+        mode if filemode => mode,
+        _ => current
+            .filter(|m| m & 0o170000 == 0o100000)
+            .unwrap_or(0o100644),
+    }
+}
+
+/// What a worktree file stores as a blob: its content, or for a symlink the
+/// path it points to.
+pub fn worktree_content(path: &Path) -> Result<Vec<u8>> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() {
+        Ok(fs::read_link(path)?
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes())
+    } else {
+        Ok(fs::read(path)?)
+    }
+}
+
+/// Blob SHA of a worktree file as it would be stored, without writing it.
+pub fn worktree_hash(repo: &GitRepository, path: &Path) -> Result<String> {
+    let data = worktree_content(path)?;
+    object_write(repo, &GitBlob { data }, &GitObjectType::blob, false)
+}
+
+/// Modification time of the index file as (seconds, nanoseconds), used to
+/// detect racily clean entries.
+pub fn index_mtime(repo: &GitRepository) -> Option<(u32, u32)> {
+    fs::metadata(repo.gitdir.join("index"))
+        .ok()
+        .map(|m| (m.mtime() as u32, m.mtime_nsec() as u32))
+}
+
+/// Whether the worktree file at `path` still matches its index entry.
+/// Stat data is trusted when it matches and the entry isn't racily clean;
+/// otherwise the file is re-hashed.
+pub fn worktree_matches(
+    repo: &GitRepository,
+    entry: &GitIndexEntry,
+    meta: &fs::Metadata,
+    index_mtime: Option<(u32, u32)>,
+) -> Result<bool> {
+    let stat_clean = meta.mtime() as u32 == entry.mtime
+        && meta.mtime_nsec() as u32 == entry.mtime_nsec
+        && meta.ctime() as u32 == entry.ctime
+        && meta.ctime_nsec() as u32 == entry.ctime_nsec
+        && meta.ino() as u32 == entry.ino
+        && meta.uid() == entry.uid
+        && meta.gid() == entry.gid
+        && meta.len() as u32 == entry.size;
+    // A file modified no earlier than the index was written may have
+    // changed without its stat data changing
+    let racy = index_mtime.is_none_or(|t| (entry.mtime, entry.mtime_nsec) >= t);
+
+    if stat_clean && !racy {
+        return Ok(true);
+    }
+    if meta.len() as u32 != entry.size && !meta.file_type().is_symlink() {
+        return Ok(false);
+    }
+
+    Ok(worktree_hash(repo, &repo.worktree.join(&entry.path))? == entry.sha)
+}
+
+pub fn status_compute(repo: &GitRepository, index: &GitIndex) -> Result<RepoStatus> {
+    let head = head_tree(repo)?;
+    let rules = gitignore_read(repo)?;
+
+    let index_mtime = index_mtime(repo);
+    let filemode = filemode_trusted(repo);
+
+    let mut entries: BTreeMap<String, StatusEntry> = BTreeMap::new();
+    let mut unmerged: BTreeMap<String, UnmergedEntry> = BTreeMap::new();
+    let mut tracked = BTreeSet::new();
+
+    for e in &index.entries {
+        tracked.insert(e.path.clone());
+
+        if e.stage() != 0 {
+            let entry = unmerged
+                .entry(e.path.clone())
+                .or_insert_with(|| UnmergedEntry {
+                    path: e.path.clone(),
+                    stages: Default::default(),
+                    worktree_mode: fs::symlink_metadata(repo.worktree.join(&e.path))
+                        .ok()
+                        .map(|m| index_mode(&m, None, filemode)),
+                });
+            entry.stages[e.stage() as usize - 1] = Some((e.mode, e.sha.clone()));
+            continue;
+        }
+
+        let head_leaf = head.get(&e.path);
+        let staged = match head_leaf {
+            // Recorded with `add -N`: nothing is staged yet
+            None if e.intent_to_add() => None,
+            None => Some(ChangeKind::Added),
+            Some(leaf) if hex::encode(leaf.sha) != e.sha || leaf.mode_bits() != e.mode => {
+                Some(ChangeKind::Modified)
+            }
+            Some(_) => None,
+        };
+
+        let abs = repo.worktree.join(&e.path);
+        let (unstaged, wt_mode) = match fs::symlink_metadata(&abs) {
+            // The worktree copy is not to be looked at
+            _ if e.assume_valid() || e.skip_worktree() => (None, Some(e.mode)),
+            Ok(meta) if meta.is_dir() => (Some(ChangeKind::Deleted), None),
+            Ok(meta) if e.intent_to_add() => (
+                Some(ChangeKind::Added),
+                Some(index_mode(&meta, Some(e.mode), filemode)),
+            ),
+            Ok(meta) => {
+                let mode = index_mode(&meta, Some(e.mode), filemode);
+                if mode == e.mode && worktree_matches(repo, e, &meta, index_mtime)? {
+                    (None, Some(mode))
+                } else {
+                    (Some(ChangeKind::Modified), Some(mode))
+                }
+            }
+            Err(_) => (Some(ChangeKind::Deleted), None),
+        };
+
+        if staged.is_some() || unstaged.is_some() {
+            entries.insert(
+                e.path.clone(),
+                StatusEntry {
+                    path: e.path.clone(),
+                    staged,
+                    unstaged,
+                    head: head_leaf.map(|l| (l.mode_bits(), hex::encode(l.sha))),
+                    index: Some((e.mode, e.sha.clone())),
+                    worktree_mode: wt_mode,
+                },
+            );
+        }
+    }
+
+    for (path, leaf) in &head {
+        if !tracked.contains(path) {
+            entries.insert(
+                path.clone(),
+                StatusEntry {
+                    path: path.clone(),
+                    staged: Some(ChangeKind::Deleted),
+                    unstaged: None,
+                    head: Some((leaf.mode_bits(), hex::encode(leaf.sha))),
+                    index: None,
+                    worktree_mode: None,
+                },
+            );
+        }
+    }
+
+    let mut status = RepoStatus {
+        entries: entries.into_values().collect(),
+        unmerged: unmerged.into_values().collect(),
+        ..Default::default()
+    };
+    collect_untracked(&rules, &tracked, &repo.worktree, "", &mut status)?;
+
+    Ok(status)
+}
+
+/// Walk `dir`, recording untracked and ignored paths. Directories holding
+/// no tracked files are reported once, as "dir/".
+fn collect_untracked(
+    rules: &GitIgnore,
+    tracked: &BTreeSet<String>,
+    dir: &Path,
+    prefix: &str,
+    status: &mut RepoStatus,
+) -> Result<()> {
+    let mut names: Vec<_> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+
+    for name in names {
+        let name = name.to_string_lossy();
+        if prefix.is_empty() && name == ".git" {
+            continue;
+        }
+
+        let rel = format!("{prefix}{name}");
+        let abs = dir.join(&*name);
+        let is_dir = fs::symlink_metadata(&abs)?.is_dir();
+
+        if check_ignore(rules, &rel)? {
+            status.ignored.push(if is_dir { format!("{rel}/") } else { rel });
+            continue;
+        }
+
+        if !is_dir {
+            if !tracked.contains(&rel) {
+                status.untracked.push(rel);
+            }
+            continue;
+        }
+
+        let dir_prefix = format!("{rel}/");
+        let has_tracked = tracked
+            .range(dir_prefix.clone()..)
+            .next()
+            .is_some_and(|p| p.starts_with(&dir_prefix));
+
+        if has_tracked {
+            collect_untracked(rules, tracked, &abs, &dir_prefix, status)?;
+        } else {
+            let mut inner = RepoStatus::default();
+            collect_untracked(rules, tracked, &abs, &dir_prefix, &mut inner)?;
+            if !inner.untracked.is_empty() {
+                status.untracked.push(dir_prefix);
+            }
+            status.ignored.extend(inner.ignored);
+        }
+    }
+
+    Ok(())
+}
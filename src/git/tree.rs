@@ -1,11 +1,12 @@
-use std::{
-    collections::BTreeMap,
-    path::{Path, PathBuf},
-};
+use std::collections::BTreeMap;
 
 use anyhow::{Context, Result};
 
-use crate::git::{index::GitIndex, objects::{object_write, GitObject, GitObjectType}, repo::GitRepository};
+use crate::git::{
+    index::{CacheTree, GitIndex, GitIndexEntry},
+    objects::{GitObject, GitObjectType, object_read, object_write},
+    repo::GitRepository,
+};
 
 #[derive(Debug, Clone)]
 pub struct GitTreeLeaf {
@@ -14,6 +15,18 @@ pub struct GitTreeLeaf {
     pub sha: [u8; 20],
 }
 
+impl GitTreeLeaf {
+    /// Whether this entry points at a subtree ("40000").
+    pub fn is_tree(&self) -> bool {
+        self.mode.trim_start_matches('0').starts_with('4')
+    }
+
+    /// The entry's mode as a number, e.g. 0o100644.
+    pub fn mode_bits(&self) -> u32 {
+        u32::from_str_radix(&self.mode, 8).unwrap_or(0)
+    }
+}
+
 /// A tree object (list of entries)
 #[derive(Debug, Clone)]
 pub struct GitTree {
@@ -82,66 +95,154 @@ impl GitObject for GitTree {
     }
 }
 
-pub fn tree_from_index(repo: &GitRepository, index: &GitIndex) -> Result<String> {
-    build_tree(repo, Path::new(""), index)
+/// Write the stage-0 entries of `index` as a hierarchy of tree objects and
+/// return the SHA of the root tree. Directories the index's cache tree still
+/// holds are not rebuilt, and the cache tree is filled in with what was written.
+pub fn tree_from_index(repo: &GitRepository, index: &mut GitIndex) -> Result<String> {
+    let mut cache = index
+        .cache_tree
+        .take()
+        .unwrap_or_else(|| CacheTree::new(""));
+    let entries: Vec<(&str, &GitIndexEntry)> = index
+        .entries
+        .iter()
+        .filter(|e| e.stage() == 0 && !e.intent_to_add())
+        .map(|e| (e.path.as_str(), e))
+        .collect();
+
+    let sha = build_tree(repo, &entries, &mut cache)?;
+
+    // Entry counts only describe the index if every entry went into a tree
+    if entries.len() == index.entries.len() {
+        index.cache_tree_set(cache);
+    }
+    Ok(sha)
 }
 
 /// Recursively descend directories and construct GitTree objects.
-fn build_tree(repo: &GitRepository, prefix: &Path, index: &GitIndex) -> Result<String> {
+/// `entries` holds paths relative to the tree being built; `cache` is its
+/// cache-tree node, reused if still valid and updated otherwise.
+fn build_tree(
+    repo: &GitRepository,
+    entries: &[(&str, &GitIndexEntry)],
+    cache: &mut CacheTree,
+) -> Result<String> {
+    if let Some(sha) = cache.valid_sha()
+        && cache.entry_count == entries.len() as i64
+    {
+        return Ok(sha.to_string());
+    }
+
     // Group entries by directory name
-    let mut files: Vec<GitTreeLeaf> = Vec::new();
-    let mut dirs: BTreeMap<String, Vec<String>> = BTreeMap::new();
-
-    for e in &index.entries {
-        let path = PathBuf::from(&e.path);
-        if let Ok(rel) = path.strip_prefix(prefix) {
-            let comps: Vec<_> = rel.components().collect();
-            if comps.len() == 1 {
-                // Direct child file
-                let sha_bytes = hex::decode(&e.sha)
+    let mut leaves: Vec<GitTreeLeaf> = Vec::new();
+    let mut dirs: BTreeMap<&str, Vec<(&str, &GitIndexEntry)>> = BTreeMap::new();
+
+    for &(rel, e) in entries {
+        match rel.split_once('/') {
+            Some((dirname, rest)) => dirs.entry(dirname).or_default().push((rest, e)),
+            None => {
+                let mut sha = [0u8; 20];
+                hex::decode_to_slice(&e.sha, &mut sha)
                     .with_context(|| format!("Invalid SHA in index for {}", e.path))?;
-                let mut sha_arr = [0u8; 20];
-                sha_arr.copy_from_slice(&sha_bytes);
 
-                files.push(GitTreeLeaf {
-                    mode: "100644".to_string(),
-                    path: rel.to_string_lossy().to_string(),
-                    sha: sha_arr,
+                leaves.push(GitTreeLeaf {
+                    mode: format!("{:o}", canonical_mode(e.mode)),
+                    path: rel.to_string(),
+                    sha,
                 });
-            } else {
-                // Goes into subdir
-                let dirname = comps[0].as_os_str().to_string_lossy().to_string();
-                dirs.entry(dirname).or_default().push(e.path.clone());
             }
         }
     }
 
-    let mut entries: Vec<GitTreeLeaf> = Vec::new();
-
-    // Add files
-    entries.extend(files);
-
     // Recurse into dirs
-    for (dirname, _) in dirs {
-        let subprefix = prefix.join(&dirname);
-        let sub_sha = build_tree(repo, &subprefix, index)?;
-
-        let sha_bytes = hex::decode(&sub_sha)?;
-        let mut sha_arr = [0u8; 20];
-        sha_arr.copy_from_slice(&sha_bytes);
-
-        entries.push(GitTreeLeaf {
+    cache
+        .subtrees
+        .retain(|t| dirs.contains_key(t.name.as_str()));
+    for (dirname, children) in dirs {
+        let sub_sha = build_tree(repo, &children, cache.subtree_mut(dirname))?;
+        let mut sha = [0u8; 20];
+        hex::decode_to_slice(&sub_sha, &mut sha)?;
+
+        leaves.push(GitTreeLeaf {
             mode: "40000".to_string(),
-            path: dirname,
-            sha: sha_arr,
+            path: dirname.to_string(),
+            sha,
         });
     }
 
-    // Sort entries by path, just like Git
-    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    tree_sort(&mut leaves);
 
     // Write this tree object
-    let tree = GitTree { entries };
+    let tree = GitTree { entries: leaves };
     let sha = object_write(repo, &tree, &GitObjectType::tree, true)?;
+
+    cache.entry_count = entries.len() as i64;
+    cache.sha = Some(sha.clone());
     Ok(sha)
 }
+
+/// The mode git records in a tree for an index mode: regular files are
+/// normalized to 100644/100755, symlinks and gitlinks are kept.
+fn canonical_mode(mode: u32) -> u32 {
+    match mode & 0o170000 {
+        0o120000 | 0o160000 => mode & 0o170000,
+        _ if mode & 0o111 != 0 => 0o100755,
+        _ => 0o100644,
+    }
+}
+
+/// Sort entries in git's canonical order: byte order of the names, with
+/// subtrees compared as if their name ended in '/'.
+pub fn tree_sort(entries: &mut [GitTreeLeaf]) {
+    entries.sort_by_cached_key(|e| {
+        let mut key = e.path.as_bytes().to_vec();
+        if e.is_tree() {
+            key.push(b'/');
+        }
+        key
+    });
+}
+
+/// Recursively list every non-tree entry below `sha`, keyed by full path.
+/// The returned leaves carry the full path in `path`.
+pub fn tree_flatten(repo: &GitRepository, sha: &str) -> Result<BTreeMap<String, GitTreeLeaf>> {
+    let mut out = BTreeMap::new();
+    flatten_into(repo, sha, "", &mut out)?;
+    Ok(out)
+}
+
+fn flatten_into(
+    repo: &GitRepository,
+    sha: &str,
+    prefix: &str,
+    out: &mut BTreeMap<String, GitTreeLeaf>,
+) -> Result<()> {
+    let (_, obj) = object_read(repo, sha)?;
+    let tree = obj
+        .as_any()
+        .downcast_ref::<GitTree>()
+        .with_context(|| format!("Object {sha} is not a tree"))?;
+
+    for entry in &tree.entries {
+        let path = if prefix.is_empty() {
+            entry.path.clone()
+        } else {
+            format!("{prefix}/{}", entry.path)
+        };
+
+        if entry.is_tree() {
+            flatten_into(repo, &hex::encode(entry.sha), &path, out)?;
+        } else {
+            out.insert(
+                path.clone(),
+                GitTreeLeaf {
+                    mode: entry.mode.clone(),
+                    path,
+                    sha: entry.sha,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
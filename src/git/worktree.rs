@@ -0,0 +1,328 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt, symlink},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::git::{
+    index::{GitIndexEntry, read_index, write_index},
+    objects::object_read_raw,
+    repo::GitRepository,
+    status::{RepoStatus, status_compute},
+    tree::GitTreeLeaf,
+};
+
+/// Replace whatever is at `abs` (file, symlink or empty directory) with
+/// nothing. A directory still holding files is left alone and reported, as
+/// those files are untracked by the time a path is cleared.
+fn worktree_clear(abs: &Path) -> Result<()> {
+    if let Ok(meta) = fs::symlink_metadata(abs) {
+        if meta.is_dir() {
+            fs::remove_dir(abs).with_context(|| format!("Failed to remove directory {:?}", abs))?;
+        } else {
+            fs::remove_file(abs).with_context(|| format!("Failed to remove {:?}", abs))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write `data` to the worktree path `path` with the given git mode.
+pub fn worktree_write(repo: &GitRepository, path: &str, mode: u32, data: &[u8]) -> Result<()> {
+    let abs = repo.worktree.join(path);
+    if let Some(parent) = abs.parent() {
+        // Tracked files in the way are removed before anything is written, so
+        // a file still blocking the directory is untracked and must stay
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {:?}", parent))?;
+    }
+    worktree_clear(&abs)?;
+
+    match mode {
+        0o120000 => symlink(String::from_utf8_lossy(data).as_ref(), &abs)?,
+        _ => {
+            fs::write(&abs, data).with_context(|| format!("Failed to write {:?}", abs))?;
+            let perm = if mode == 0o100755 { 0o755 } else { 0o644 };
+            fs::set_permissions(&abs, fs::Permissions::from_mode(perm))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check out the blob `sha` at `path`. Submodules become empty directories.
+pub fn worktree_write_blob(repo: &GitRepository, path: &str, mode: u32, sha: &str) -> Result<()> {
+    if mode == 0o160000 {
+        let abs = repo.worktree.join(path);
+        if !abs.is_dir() {
+            worktree_clear(&abs)?;
+            fs::create_dir_all(&abs)?;
+        }
+        return Ok(());
+    }
+
+    let (_, data) = object_read_raw(repo, sha)?;
+    worktree_write(repo, path, mode, &data)
+}
+
+/// Delete `path` from the worktree, pruning directories left empty.
+pub fn worktree_remove(repo: &GitRepository, path: &str) -> Result<()> {
+    let abs = repo.worktree.join(path);
+    worktree_clear(&abs)?;
+
+    let mut dir = abs.parent();
+    while let Some(d) = dir {
+        if d == repo.worktree || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+
+    Ok(())
+}
+
+/// Index entry for `path` with `mode`/`sha`, carrying the file's current stat data.
+pub fn index_entry_for(
+    repo: &GitRepository,
+    path: &str,
+    mode: u32,
+    sha: &str,
+    stage: u16,
+) -> Result<GitIndexEntry> {
+    let meta = fs::symlink_metadata(repo.worktree.join(path)).ok();
+
+    let stat = |f: fn(&fs::Metadata) -> u32| meta.as_ref().map(f).unwrap_or(0);
+
+    Ok(GitIndexEntry {
+        ctime: stat(|m| m.ctime() as u32),
+        ctime_nsec: stat(|m| m.ctime_nsec() as u32),
+        mtime: stat(|m| m.mtime() as u32),
+        mtime_nsec: stat(|m| m.mtime_nsec() as u32),
+        dev: stat(|m| m.dev() as u32),
+        ino: stat(|m| m.ino() as u32),
+        mode,
+        uid: stat(|m| m.uid()),
+        gid: stat(|m| m.gid()),
+        size: stat(|m| m.len() as u32),
+        sha: sha.to_string(),
+        flags: (stage << 12) | path.len().min(0xfff) as u16,
+        flags_extended: 0,
+        path: path.to_string(),
+    })
+}
+
+/// Index entry for `path` without stat data, so the next status re-hashes the
+/// file instead of trusting it to match.
+pub fn index_entry_unstatted(path: &str, mode: u32, sha: &str) -> GitIndexEntry {
+    GitIndexEntry {
+        ctime: 0,
+        ctime_nsec: 0,
+        mtime: 0,
+        mtime_nsec: 0,
+        dev: 0,
+        ino: 0,
+        mode,
+        uid: 0,
+        gid: 0,
+        size: 0,
+        sha: sha.to_string(),
+        flags: path.len().min(0xfff) as u16,
+        flags_extended: 0,
+        path: path.to_string(),
+    }
+}
+
+/// `path` (relative to the current directory, possibly not existing) as a
+/// path relative to the worktree root; the root itself is "".
+pub fn worktree_relpath(repo: &GitRepository, path: &Path) -> Result<String> {
+    let worktree = repo.worktree.canonicalize()?;
+    let abs = std::env::current_dir()?.canonicalize()?.join(path);
+
+    let mut normal = PathBuf::new();
+    for component in abs.components() {
+        match component {
+            Component::ParentDir => {
+                normal.pop();
+            }
+            Component::CurDir => {}
+            c => normal.push(c),
+        }
+    }
+
+    let rel = normal
+        .strip_prefix(&worktree)
+        .map_err(|_| anyhow::anyhow!("{:?} is outside repository", path))?;
+    Ok(rel.to_string_lossy().into_owned())
+}
+
+/// Whether the repo-relative `path` is `spec` itself or lies below it.
+pub fn pathspec_matches(spec: &str, path: &str) -> bool {
+    spec.is_empty()
+        || path == spec
+        || path
+            .strip_prefix(spec)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Move the worktree from tree `from` to tree `to` (both flattened), touching
+/// only the paths that differ between them.
+pub fn worktree_update(
+    repo: &GitRepository,
+    from: &BTreeMap<String, GitTreeLeaf>,
+    to: &BTreeMap<String, GitTreeLeaf>,
+) -> Result<()> {
+    for path in from.keys() {
+        if !to.contains_key(path) {
+            worktree_remove(repo, path)?;
+        }
+    }
+
+    for (path, leaf) in to {
+        let unchanged = from
+            .get(path)
+            .is_some_and(|old| old.sha == leaf.sha && old.mode_bits() == leaf.mode_bits());
+        if !unchanged {
+            worktree_write_blob(repo, path, leaf.mode_bits(), &hex::encode(leaf.sha))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Stage-0 index entries matching the flattened tree `tree`.
+pub fn index_from_tree(
+    repo: &GitRepository,
+    tree: &BTreeMap<String, GitTreeLeaf>,
+) -> Result<Vec<GitIndexEntry>> {
+    tree.iter()
+        .map(|(path, leaf)| index_entry_for(repo, path, leaf.mode_bits(), &hex::encode(leaf.sha), 0))
+        .collect()
+}
+
+/// Whether writing `path` would clobber the untracked `untracked` (a file, or
+/// a directory ending in '/'), including file/directory clashes. Inside an
+/// untracked directory only what actually exists at `path` is in the way.
+fn paths_overlap(repo: &GitRepository, untracked: &str, path: &str) -> bool {
+    let is_under = |inner: &str, outer: &str| {
+        inner.strip_prefix(outer).is_some_and(|rest| rest.starts_with('/'))
+    };
+
+    if let Some(dir) = untracked.strip_suffix('/')
+        && is_under(path, dir)
+    {
+        let abs = repo.worktree.join(path);
+        let top = repo.worktree.join(dir);
+        return fs::symlink_metadata(&abs).is_ok()
+            || abs
+                .ancestors()
+                .skip(1)
+                .take_while(|p| *p != top)
+                .any(|p| fs::symlink_metadata(p).is_ok_and(|m| !m.is_dir()));
+    }
+
+    let untracked = untracked.trim_end_matches('/');
+    untracked == path || is_under(path, untracked) || is_under(untracked, path)
+}
+
+/// Refuse to write `paths` over untracked files: paths tracked in `from` are
+/// safe, any other whose place is taken by an untracked file or directory
+/// makes `operation` abort before the worktree is touched.
+pub fn worktree_untracked_check<'a>(
+    repo: &GitRepository,
+    status: &RepoStatus,
+    from: &BTreeMap<String, GitTreeLeaf>,
+    paths: impl IntoIterator<Item = &'a str>,
+    operation: &str,
+) -> Result<()> {
+    let untracked: Vec<&str> = paths
+        .into_iter()
+        .filter(|path| !from.contains_key(*path))
+        .filter(|path| status.untracked.iter().any(|u| paths_overlap(repo, u, path)))
+        .collect();
+    if untracked.is_empty() {
+        return Ok(());
+    }
+
+    let action = if operation == "merge" {
+        "merge"
+    } else {
+        "switch branches"
+    };
+    bail!(
+        "The following untracked working tree files would be overwritten by {operation}:\n\t{}\nPlease move or remove them before you {action}.\nAborting",
+        untracked.join("\n\t")
+    )
+}
+
+/// Move the worktree and index from tree `from` to tree `to` like `git checkout`:
+/// only paths that differ between the trees are touched, local changes to
+/// other paths are carried over, and nothing is written if a local change or
+/// an untracked file would be lost. `operation` names the command in errors.
+pub fn worktree_switch(
+    repo: &GitRepository,
+    from: &BTreeMap<String, GitTreeLeaf>,
+    to: &BTreeMap<String, GitTreeLeaf>,
+    operation: &str,
+) -> Result<()> {
+    let mut index = read_index(repo)?;
+    let status = status_compute(repo, &index)?;
+    if !status.unmerged.is_empty() {
+        bail!("you need to resolve your current index first");
+    }
+
+    let changes: HashMap<&str, _> = status.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+    let leaf = |tree: &BTreeMap<String, GitTreeLeaf>, path: &str| {
+        tree.get(path).map(|l| (l.mode_bits(), hex::encode(l.sha)))
+    };
+
+    let paths: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+    let mut differing = Vec::new();
+    let mut overwritten = Vec::new();
+    let mut written = Vec::new();
+
+    for path in paths {
+        let target = leaf(to, path);
+        if leaf(from, path) == target {
+            continue;
+        }
+        differing.push(path.as_str());
+
+        if let Some(change) = changes.get(path.as_str()) {
+            // A change already staged to exactly the target version is harmless
+            if change.unstaged.is_some() || change.index != target {
+                overwritten.push(path.as_str());
+            }
+        } else if target.is_some() {
+            written.push(path.as_str());
+        }
+    }
+
+    if !overwritten.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by {operation}:\n\t{}\nPlease commit your changes or stash them before you switch branches.\nAborting",
+            overwritten.join("\n\t")
+        );
+    }
+    worktree_untracked_check(repo, &status, from, written, operation)?;
+
+    worktree_update(repo, from, to)?;
+
+    // Entries for untouched paths keep their stat data and staged changes
+    let mut entries: BTreeMap<String, GitIndexEntry> = std::mem::take(&mut index.entries)
+        .into_iter()
+        .map(|e| (e.path.clone(), e))
+        .collect();
+    for path in differing {
+        entries.remove(path);
+        if let Some(l) = to.get(path) {
+            let entry = index_entry_for(repo, path, l.mode_bits(), &hex::encode(l.sha), 0)?;
+            entries.insert(path.to_string(), entry);
+        }
+    }
+
+    index.entries = entries.into_values().collect();
+    index.resolve_undo.clear();
+    write_index(repo, &index)
+}
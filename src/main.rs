@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 
 use crate::git::objects::GitObjectType;
 
@@ -42,11 +42,65 @@ enum Commands {
         /// The object to display
         object: String,
     },
-    /// Display history of a given commit.
+    /// Display history of the given commits.
     Log {
-        /// Commit to start at.
-        #[arg(default_value = "HEAD")]
-        commit: String,
+        /// Shorthand for `--pretty=oneline` with abbreviated hashes
+        #[arg(long)]
+        oneline: bool,
+
+        /// Output format: medium, oneline, dot, format:<template> or tformat:<template>
+        #[arg(long, alias = "pretty", conflicts_with = "oneline")]
+        format: Option<String>,
+
+        /// Draw the history as ASCII lanes next to the log
+        #[arg(long)]
+        graph: bool,
+
+        #[command(flatten)]
+        walk: WalkArgs,
+
+        #[command(flatten)]
+        filter: CommitFilterArgs,
+
+        #[command(flatten)]
+        refs: RevArgs,
+
+        /// Commits to start at (default HEAD), ranges (A..B, A...B) or ^<commit>
+        commits: Vec<String>,
+
+        /// Only show commits that change these paths
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// List commits reachable from the given revisions, newest first.
+    RevList {
+        /// Print the number of commits instead of listing them
+        #[arg(long)]
+        count: bool,
+
+        /// Also list the trees and blobs the commits reference
+        #[arg(long)]
+        objects: bool,
+
+        /// Mark commits with '<' or '>' by the side of a symmetric range they are on
+        #[arg(long)]
+        left_right: bool,
+
+        #[command(flatten)]
+        walk: WalkArgs,
+
+        #[command(flatten)]
+        filter: CommitFilterArgs,
+
+        #[command(flatten)]
+        refs: RevArgs,
+
+        /// Commits to start at, ranges (A..B, A...B) or ^<commit>
+        revs: Vec<String>,
+
+        /// Only list commits that change these paths
+        #[arg(last = true)]
+        paths: Vec<String>,
     },
     /// Pretty-print a tree object.
     LsTree {
@@ -57,13 +111,27 @@ enum Commands {
         /// A tree-ish object.
         tree: String,
     },
-    /// Checkout a commit inside of a directory.
+    /// Switch branches, or detach HEAD at a commit.
     Checkout {
-        /// The commit or tree to checkout.
-        commit: String,
-        // TODO
-        // The EMPTY directory to checkout on.
-        // path: PathBuf,
+        /// Create a new branch at the target and switch to it
+        #[arg(short = 'b')]
+        new_branch: Option<String>,
+
+        /// The branch or commit to checkout (defaults to HEAD with -b).
+        commit: Option<String>,
+    },
+    /// Switch branches.
+    Switch {
+        /// Create a new branch and switch to it
+        #[arg(short = 'c', long = "create")]
+        create: Option<String>,
+
+        /// Detach HEAD at the given commit
+        #[arg(long)]
+        detach: bool,
+
+        /// The branch to switch to (the start point with -c)
+        target: Option<String>,
     },
     /// Parse revision (or other objects) identifiers
     RevParse {
@@ -74,8 +142,63 @@ enum Commands {
         /// The name to parse
         name: String,
     },
+    /// Find the best common ancestors of commits.
+    MergeBase {
+        /// Print all best common ancestors, not just one
+        #[arg(short, long)]
+        all: bool,
+
+        /// Find the common ancestors of all commits, as for an octopus merge
+        #[arg(long)]
+        octopus: bool,
+
+        /// Exit with 0 if the first commit is an ancestor of the second, 1 otherwise
+        #[arg(long, conflicts_with_all = ["all", "octopus"])]
+        is_ancestor: bool,
+
+        /// The commits to compare
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
     /// List references.
     ShowRef {},
+    /// Manage reflog information
+    #[command(args_conflicts_with_subcommands = true)]
+    Reflog {
+        #[command(subcommand)]
+        action: Option<ReflogAction>,
+
+        /// The ref whose log to show (default HEAD)
+        refname: Option<String>,
+    },
+    /// Pack refs into .git/packed-refs
+    PackRefs {
+        /// Pack all refs, not just tags
+        #[arg(long)]
+        all: bool,
+    },
+    /// Update the object a ref points to, optionally only if it still has an expected value
+    UpdateRef {
+        /// Reason recorded in the reflog
+        #[arg(short)]
+        message: Option<String>,
+
+        /// Delete the ref
+        #[arg(short)]
+        delete: bool,
+
+        /// Read update, create, delete and verify commands from stdin and apply them together
+        #[arg(long, conflicts_with = "delete")]
+        stdin: bool,
+
+        /// The ref to update
+        #[arg(required_unless_present = "stdin", conflicts_with = "stdin")]
+        refname: Option<String>,
+
+        /// <new> [<old>], or [<old>] with -d
+        #[arg(num_args = 0..=2)]
+        values: Vec<String>,
+    },
     /// List and create tags
     Tag {
         /// Whether to create a tag object
@@ -97,7 +220,15 @@ enum Commands {
         paths: Vec<PathBuf>,
     },
     /// Show the working tree status.
-    Status {},
+    Status {
+        /// Machine-readable output (v1 or v2)
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "v1")]
+        porcelain: Option<commands::status::PorcelainVersion>,
+
+        /// Also show ignored files
+        #[arg(long)]
+        ignored: bool,
+    },
     /// Remove files from the working tree and the index.
     Rm {
         /// Files to remove
@@ -110,9 +241,306 @@ enum Commands {
     },
     /// Record changes to the repository.
     Commit {
-        /// Message to associate with this commit.
+        /// Message to associate with this commit; several become paragraphs.
+        #[arg(short, long, conflicts_with = "file")]
+        message: Vec<String>,
+
+        /// Take the message from a file ("-" for standard input)
+        #[arg(short = 'F', long)]
+        file: Option<PathBuf>,
+
+        /// Replace the tip of the current branch with a new commit
+        #[arg(long)]
+        amend: bool,
+
+        /// Reuse the amended commit's or merge's message without launching an editor
+        #[arg(long)]
+        no_edit: bool,
+
+        /// Make the amended commit's author the current user
+        #[arg(long, requires = "amend")]
+        reset_author: bool,
+
+        /// Override the author, as "Name <email>"
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Override the author date (RFC 2822, ISO 8601 or "@<epoch> <tz>")
+        #[arg(long)]
+        date: Option<String>,
+
+        /// Allow a commit that records the same tree as its parent
+        #[arg(long)]
+        allow_empty: bool,
+
+        /// Stage all modified and deleted tracked files first
+        #[arg(short, long)]
+        all: bool,
+
+        /// Bypass the pre-commit and commit-msg hooks
+        #[arg(short = 'n', long)]
+        no_verify: bool,
+    },
+    /// Reset the current branch to a commit, or unstage paths.
+    Reset {
+        /// Only move the branch
+        #[arg(long, conflicts_with_all = ["mixed", "hard"])]
+        soft: bool,
+
+        /// Move the branch and reset the index (the default)
+        #[arg(long, conflicts_with = "hard")]
+        mixed: bool,
+
+        /// Move the branch and reset the index and working tree
+        #[arg(long)]
+        hard: bool,
+
+        /// The commit to reset to (default HEAD)
+        commit: Option<String>,
+
+        /// Only reset the index entries of these paths
+        #[arg(last = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Restore working tree files or index entries.
+    Restore {
+        /// Restore the index (from HEAD by default)
+        #[arg(short = 'S', long)]
+        staged: bool,
+
+        /// Restore the working tree (the default without --staged)
+        #[arg(short = 'W', long)]
+        worktree: bool,
+
+        /// The commit to take the files from
+        #[arg(short, long)]
+        source: Option<String>,
+
+        /// Paths to restore
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Show changes between commits, commit and working tree, etc.
+    Diff {
+        /// Compare the index against HEAD (or the given commit)
+        #[arg(long, alias = "staged")]
+        cached: bool,
+
+        /// Number of context lines around each change
+        #[arg(short = 'U', long = "unified", default_value_t = 3)]
+        unified: usize,
+
+        /// Commits to compare
+        #[arg(num_args = 0..=2)]
+        commits: Vec<String>,
+    },
+    /// List, create, or delete branches
+    Branch {
+        /// Delete a branch that is fully merged into HEAD
+        #[arg(short = 'd', long = "delete", conflicts_with_all = ["force_delete", "rename"])]
+        delete: bool,
+
+        /// Delete a branch irrespective of its merged status
+        #[arg(short = 'D', conflicts_with = "rename")]
+        force_delete: bool,
+
+        /// Rename a branch (and HEAD if it points to it)
+        #[arg(short = 'm', long = "move")]
+        rename: bool,
+
+        /// The branch name (the old name when renaming)
+        name: Option<String>,
+
+        /// The commit the new branch will point to (the new name when renaming)
+        start: Option<String>,
+    },
+    /// Join two development histories together.
+    Merge {
+        /// Bypass the pre-merge-commit and commit-msg hooks
+        #[arg(long)]
+        no_verify: bool,
+
+        /// Branch or commit to merge into the current branch
+        branch: String,
+    },
+    /// Pack reachable objects into a single delta-compressed packfile.
+    Repack {
+        /// Remove loose objects and packs made redundant by the new pack
         #[arg(short)]
-        message: String,
+        d: bool,
+
+        /// Number of preceding objects considered as delta bases
+        #[arg(long, default_value_t = commands::repack::DEFAULT_WINDOW)]
+        window: usize,
+
+        /// Maximum length of a delta chain
+        #[arg(long, default_value_t = commands::repack::DEFAULT_DEPTH)]
+        depth: usize,
+    },
+    /// Cleanup unnecessary files and optimize the local repository.
+    Gc {
+        /// Spend more time searching for delta bases
+        #[arg(long)]
+        aggressive: bool,
+    },
+}
+
+/// Ref selections adding to the revisions a history walk starts from.
+#[derive(Debug, Args)]
+struct RevArgs {
+    /// Walk from HEAD and every ref
+    #[arg(long)]
+    all: bool,
+
+    /// Walk from every branch
+    #[arg(long)]
+    branches: bool,
+
+    /// Walk from every tag
+    #[arg(long)]
+    tags: bool,
+
+    /// Exclude the history of these revisions
+    #[arg(long = "not", value_name = "REV", num_args = 1..)]
+    not: Vec<String>,
+}
+
+impl RevArgs {
+    /// The revision arguments as `rev_specs_parse` takes them. With
+    /// `default_head`, nothing to start from means HEAD.
+    fn revisions(&self, mut revs: Vec<String>, default_head: bool) -> Vec<String> {
+        let flags = [
+            (self.all, "--all"),
+            (self.branches, "--branches"),
+            (self.tags, "--tags"),
+        ];
+        for (set, flag) in flags {
+            if set {
+                revs.push(flag.to_string());
+            }
+        }
+        if default_head && revs.is_empty() {
+            revs.push("HEAD".to_string());
+        }
+        if !self.not.is_empty() {
+            revs.push("--not".to_string());
+            revs.extend(self.not.iter().cloned());
+        }
+        revs
+    }
+}
+
+/// Options controlling the order and length of a history walk.
+#[derive(Debug, Args)]
+struct WalkArgs {
+    /// Limit the number of commits to output
+    #[arg(short = 'n', long = "max-count")]
+    max_count: Option<usize>,
+
+    /// Output commits in reverse order
+    #[arg(long)]
+    reverse: bool,
+
+    /// Show no parents before all of their children, keeping lines of history together
+    #[arg(long, conflicts_with = "date_order")]
+    topo_order: bool,
+
+    /// Show no parents before all of their children, otherwise by commit date
+    #[arg(long)]
+    date_order: bool,
+}
+
+impl WalkArgs {
+    fn order(&self) -> git::revwalk::WalkOrder {
+        if self.topo_order {
+            git::revwalk::WalkOrder::Topo
+        } else if self.date_order {
+            git::revwalk::WalkOrder::DateOrder
+        } else {
+            git::revwalk::WalkOrder::Date
+        }
+    }
+}
+
+/// Options limiting which commits a history walk shows.
+#[derive(Debug, Args)]
+struct CommitFilterArgs {
+    /// Show commits whose author matches the pattern
+    #[arg(long)]
+    author: Vec<String>,
+
+    /// Show commits whose committer matches the pattern
+    #[arg(long)]
+    committer: Vec<String>,
+
+    /// Show commits whose message matches the pattern
+    #[arg(long)]
+    grep: Vec<String>,
+
+    /// Match the patterns case-insensitively
+    #[arg(short = 'i', long = "regexp-ignore-case")]
+    ignore_case: bool,
+
+    /// Show commits more recent than a date
+    #[arg(long, alias = "after")]
+    since: Option<String>,
+
+    /// Show commits older than a date
+    #[arg(long, alias = "before")]
+    until: Option<String>,
+}
+
+impl CommitFilterArgs {
+    fn build(&self) -> Result<git::revwalk::CommitFilter> {
+        let patterns = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| {
+                    regex::RegexBuilder::new(p)
+                        .case_insensitive(self.ignore_case)
+                        .build()
+                        .map_err(|e| anyhow::anyhow!("invalid pattern '{p}': {e}"))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let date = |date: &Option<String>| {
+            date.as_deref().map(git::ident::approxidate).transpose()
+        };
+
+        Ok(git::revwalk::CommitFilter {
+            author: patterns(&self.author)?,
+            committer: patterns(&self.committer)?,
+            grep: patterns(&self.grep)?,
+            since: date(&self.since)?,
+            until: date(&self.until)?,
+        })
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ReflogAction {
+    /// Show the log of a reference
+    Show {
+        /// The ref whose log to show (default HEAD)
+        refname: Option<String>,
+    },
+    /// Prune old reflog entries
+    Expire {
+        /// Prune entries older than this date ("all" prunes everything)
+        #[arg(long)]
+        expire: Option<String>,
+
+        /// Process the reflogs of all references
+        #[arg(long)]
+        all: bool,
+
+        /// The refs whose logs to prune
+        refs: Vec<String>,
+    },
+    /// Delete single entries, given as <ref>@{<n>}
+    Delete {
+        #[arg(required = true)]
+        entries: Vec<String>,
     },
 }
 
@@ -136,21 +564,125 @@ fn main() -> Result<()> {
         } => {
             commands::cat_file::run(&object_type, &object)?;
         }
-        Commands::Log { commit } => {
-            commands::log::run(&commit)?;
+        Commands::Log {
+            oneline,
+            format,
+            graph,
+            walk,
+            filter,
+            refs,
+            commits,
+            paths,
+        } => {
+            let format = match format {
+                Some(format) => commands::log::LogFormat::parse(&format)?,
+                None if oneline => commands::log::LogFormat::Oneline { abbrev: true },
+                None => commands::log::LogFormat::Medium,
+            };
+
+            commands::log::run(
+                &refs.revisions(commits, true),
+                commands::log::LogOptions {
+                    format,
+                    graph,
+                    order: walk.order(),
+                    max_count: walk.max_count,
+                    reverse: walk.reverse,
+                    paths,
+                    filter: filter.build()?,
+                },
+            )?;
+        }
+        Commands::RevList {
+            count,
+            objects,
+            left_right,
+            walk,
+            filter,
+            refs,
+            revs,
+            paths,
+        } => {
+            let revs = refs.revisions(revs, false);
+            if revs.is_empty() {
+                anyhow::bail!("rev-list needs at least one revision");
+            }
+            commands::rev_list::run(
+                &revs,
+                commands::rev_list::RevListOptions {
+                    order: walk.order(),
+                    max_count: walk.max_count,
+                    reverse: walk.reverse,
+                    count,
+                    objects,
+                    left_right,
+                    paths,
+                    filter: filter.build()?,
+                },
+            )?;
         }
         Commands::LsTree { recursive, tree } => {
             commands::ls_tree::run(&tree, recursive)?;
         }
-        Commands::Checkout { commit } => {
-            commands::checkout::run(&commit)?;
+        Commands::Checkout { new_branch, commit } => {
+            commands::checkout::run(commit.as_deref(), new_branch.as_deref())?;
+        }
+        Commands::Switch {
+            create,
+            detach,
+            target,
+        } => {
+            commands::switch::run(target.as_deref(), create.as_deref(), detach)?;
         }
         Commands::RevParse { name, git_type } => {
             commands::rev_parse::run(&name, git_type)?;
         }
+        Commands::MergeBase {
+            all,
+            octopus,
+            is_ancestor,
+            commits,
+        } => {
+            if is_ancestor {
+                let [ancestor, descendant] = commits.as_slice() else {
+                    anyhow::bail!("--is-ancestor takes exactly two commits");
+                };
+                commands::merge_base::is_ancestor_check(ancestor, descendant)?;
+            } else {
+                commands::merge_base::run(&commits, all, octopus)?;
+            }
+        }
         Commands::ShowRef {} => {
             commands::show_ref::run()?;
         }
+        Commands::Reflog { action, refname } => match action {
+            None => commands::reflog::show(refname.as_deref().unwrap_or("HEAD"))?,
+            Some(ReflogAction::Show { refname }) => {
+                commands::reflog::show(refname.as_deref().unwrap_or("HEAD"))?
+            }
+            Some(ReflogAction::Expire { expire, all, refs }) => {
+                commands::reflog::expire(expire.as_deref(), all, &refs)?
+            }
+            Some(ReflogAction::Delete { entries }) => commands::reflog::delete(&entries)?,
+        },
+        Commands::PackRefs { all } => {
+            commands::pack_refs::run(all)?;
+        }
+        Commands::UpdateRef {
+            message,
+            delete,
+            stdin,
+            refname,
+            values,
+        } => {
+            let message = message.unwrap_or_default();
+            match refname {
+                Some(refname) if !stdin => {
+                    commands::update_ref::run(&refname, &values, delete, &message)?
+                }
+                _ => commands::update_ref::run_stdin(&message)?,
+            }
+        }
         Commands::Tag {
             annotate,
             name,
@@ -169,8 +701,8 @@ fn main() -> Result<()> {
         Commands::CheckIgnore { paths } => {
             commands::check_ignore::run(&paths)?;
         }
-        Commands::Status {} => {
-            commands::status::run()?;
+        Commands::Status { porcelain, ignored } => {
+            commands::status::run(porcelain, ignored)?;
         }
         Commands::Rm { paths } => {
             commands::rm::run(&paths)?;
@@ -178,8 +710,95 @@ fn main() -> Result<()> {
         Commands::Add { paths } => {
             commands::add::run(&paths)?;
         }
-        Commands::Commit { message } => {
-            commands::commit::run(&message)?;
+        Commands::Commit {
+            message,
+            file,
+            amend,
+            no_edit,
+            reset_author,
+            author,
+            date,
+            allow_empty,
+            all,
+            no_verify,
+        } => {
+            commands::commit::run(commands::commit::CommitOptions {
+                message,
+                file,
+                amend,
+                no_edit,
+                reset_author,
+                author,
+                date,
+                allow_empty,
+                all,
+                no_verify,
+            })?;
+        }
+        Commands::Reset {
+            soft,
+            mixed,
+            hard,
+            commit,
+            paths,
+        } => {
+            use commands::reset::ResetMode;
+            let mode = match (soft, mixed, hard) {
+                (true, _, _) => Some(ResetMode::Soft),
+                (_, true, _) => Some(ResetMode::Mixed),
+                (_, _, true) => Some(ResetMode::Hard),
+                _ => None,
+            };
+            commands::reset::run(mode, commit.as_deref(), &paths)?;
+        }
+        Commands::Restore {
+            staged,
+            worktree,
+            source,
+            paths,
+        } => {
+            commands::restore::run(&paths, staged, worktree, source.as_deref())?;
+        }
+        Commands::Diff {
+            cached,
+            unified,
+            commits,
+        } => {
+            commands::diff::run(cached, unified, &commits)?;
+        }
+        Commands::Branch {
+            delete,
+            force_delete,
+            rename,
+            name,
+            start,
+        } => match (name, start) {
+            (Some(name), None) if delete || force_delete => {
+                commands::branch::delete_branch(&name, force_delete)?;
+            }
+            (Some(old), Some(new)) if rename => {
+                commands::branch::rename_branch(Some(&old), &new)?;
+            }
+            (Some(new), None) if rename => {
+                commands::branch::rename_branch(None, &new)?;
+            }
+            (Some(name), start) if !(delete || force_delete || rename) => {
+                let start = start.unwrap_or_else(|| "HEAD".to_string());
+                commands::branch::create_branch(&name, &start)?;
+            }
+            (None, None) if !(delete || force_delete || rename) => {
+                commands::branch::list_branches()?;
+            }
+            _ => anyhow::bail!("Invalid combination of branch arguments"),
+        },
+        Commands::Merge { no_verify, branch } => {
+            commands::merge::run(&branch, no_verify)?;
+        }
+        Commands::Repack { d, window, depth } => {
+            commands::repack::run(d, window, depth)?;
+        }
+        Commands::Gc { aggressive } => {
+            commands::gc::run(aggressive)?;
         }
     }
 